@@ -0,0 +1,97 @@
+public class Copies {
+    // Digits of a, first to last
+    static int digits(int[] a) {
+        int n = 0;
+        for (int i = 0; i < a.length; i++) {
+            n = n * 10 + a[i];
+        }
+        return n;
+    }
+
+    // Overlapping copies within an array go as if through a temporary one
+    public static int overlap() {
+        int[] a = {1, 2, 3, 4, 5};
+        System.arraycopy(a, 0, a, 1, 4);
+        int up = digits(a);
+        int[] b = {1, 2, 3, 4, 5};
+        System.arraycopy(b, 1, b, 0, 4);
+        return up - digits(b);
+    }
+
+    public static int chars() {
+        char[] from = {'a', 'b', 'c'};
+        char[] to = new char[4];
+        System.arraycopy(from, 1, to, 2, 2);
+        System.arraycopy(from, 0, to, 0, 0);
+        return to[0] + to[1] + to[2] * 1000 + to[3];
+    }
+
+    // Elements before one that can't be stored are still copied
+    public static int objects() {
+        Object[] from = new Object[3];
+        for (int i = 0; i < from.length; i++) {
+            from[i] = new Copies();
+        }
+        Copies[] to = new Copies[3];
+        System.arraycopy(from, 0, to, 0, 3);
+        if (to[2] != from[2]) {
+            return -1;
+        }
+        Copies kept = to[1];
+        from[0] = null;
+        from[1] = new Object();
+        try {
+            System.arraycopy(from, 0, to, 0, 3);
+            return -2;
+        } catch (ArrayStoreException e) {
+        }
+        if (to[0] != null || to[1] != kept) {
+            return -3;
+        }
+        Object[] objects = new Object[1];
+        System.arraycopy(new Object[] {new int[2]}, 0, objects, 0, 1);
+        return objects[0] == null ? -4 : to.length;
+    }
+
+    // Which of the ways of getting it wrong throw what they should
+    public static int errors() {
+        int[] ints = new int[4];
+        int thrown = 0;
+        try {
+            System.arraycopy(null, 0, ints, 0, 1);
+        } catch (NullPointerException e) {
+            thrown += 1;
+        }
+        try {
+            System.arraycopy(ints, 0, null, 0, 1);
+        } catch (NullPointerException e) {
+            thrown += 2;
+        }
+        try {
+            System.arraycopy(ints, 3, ints, 0, 2);
+        } catch (ArrayIndexOutOfBoundsException e) {
+            thrown += 4;
+        }
+        try {
+            System.arraycopy(ints, 0, ints, 0, -1);
+        } catch (ArrayIndexOutOfBoundsException e) {
+            thrown += 8;
+        }
+        try {
+            System.arraycopy(ints, 0, new char[4], 0, 1);
+        } catch (ArrayStoreException e) {
+            thrown += 16;
+        }
+        try {
+            System.arraycopy(new Copies(), 0, ints, 0, 1);
+        } catch (ArrayStoreException e) {
+            thrown += 32;
+        }
+        try {
+            System.arraycopy(ints, 1, ints, 2, Integer.MAX_VALUE);
+        } catch (ArrayIndexOutOfBoundsException e) {
+            thrown += 64;
+        }
+        return thrown;
+    }
+}
@@ -0,0 +1,13 @@
+public class Counter {
+    public static int count;
+
+    public static int bump() {
+        count = count + 1;
+        return count;
+    }
+
+    public static int bumpTwice() {
+        bump();
+        return bump();
+    }
+}
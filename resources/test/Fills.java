@@ -0,0 +1,34 @@
+public class Fills {
+    private int total;
+
+    // Each fills what it is given, so the caller can check it was done in
+    // place rather than on a copy
+    public static int ints(int[] a) {
+        for (int i = 0; i < a.length; i++) {
+            a[i] = i;
+        }
+        return a[a.length - 1];
+    }
+
+    public static int chars(char[] a) {
+        for (int i = 0; i < a.length; i++) {
+            a[i] = (char) i;
+        }
+        return a[a.length - 1];
+    }
+
+    public static int objects(Object[] a) {
+        Object o = new Object();
+        for (int i = 0; i < a.length; i++) {
+            a[i] = o;
+        }
+        return a[a.length - 1] == o ? a.length : -1;
+    }
+
+    public static int fields(Fills f, int n) {
+        for (int i = 0; i < n; i++) {
+            f.total = f.total + i;
+        }
+        return f.total;
+    }
+}
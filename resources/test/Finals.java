@@ -0,0 +1,75 @@
+public class Finals {
+    private static int finalized;
+    private static Finals saved;
+    private int value;
+
+    protected void finalize() {
+        finalized += value;
+        if (value < 0) {
+            saved = this;
+        }
+        // Exceptions thrown by finalizers go nowhere
+        if (value == 3) {
+            Finals none = null;
+            none.touch();
+        }
+    }
+
+    private void touch() {
+    }
+
+    // Drops objects valued 1 to n, then collects once, after which all of
+    // them have been finalized
+    public static int drop(int n) {
+        finalized = 0;
+        for (int i = 1; i <= n; i++) {
+            make(i);
+        }
+        System.gc();
+        System.runFinalization();
+        return finalized;
+    }
+
+    // The finalizer of the object valued -5 brings it back to life. Once
+    // dropped again it is freed without being finalized a second time, so
+    // this gives 95 rather than 90.
+    public static int resurrect() {
+        finalized = 0;
+        saved = null;
+        make(-5);
+        while (saved == null) {
+            System.gc();
+        }
+        int value = saved.value;
+        saved = null;
+        make(100);
+        while (finalized == value) {
+            System.gc();
+        }
+        return finalized;
+    }
+
+    // Objects valued 1 to n are left queued for finalization by a collection
+    // the interpreter doesn't know about, so only runFinalization() runs
+    // their finalizers, all before it returns
+    public static int runOnce(int n) {
+        // Called on directly, as any call into Java code would run them too
+        Runtime rt = Runtime.getRuntime();
+        finalized = 0;
+        for (int i = 1; i <= n; i++) {
+            make(i);
+        }
+        collectQuietly();
+        int before = finalized;
+        rt.runFinalization();
+        return before * 1000 + finalized;
+    }
+
+    // Registered by the test
+    private static native void collectQuietly();
+
+    private static void make(int value) {
+        Finals f = new Finals();
+        f.value = value;
+    }
+}
@@ -0,0 +1,66 @@
+public class Garbage {
+    private static Garbage kept;
+    private Garbage next;
+    private int value;
+
+    // Keeps one chain in a static and one in a local while dropping lots
+    // of others around a collection, so freed slots get handed out again
+    public static int churn(int rounds) {
+        kept = chain(3);
+        Garbage local = chain(4);
+        for (int i = 0; i < rounds; i++) {
+            chain(10);
+        }
+        Runtime.getRuntime().gc();
+        for (int i = 0; i < rounds; i++) {
+            chain(10);
+        }
+        return sum(kept) * 100 + sum(local);
+    }
+
+    // Asks for an array of ints, giving -1 if there isn't room for it
+    public static int hog(int length) {
+        try {
+            int[] ints = new int[length];
+            ints[length - 1] = length;
+            return ints[length - 1];
+        } catch (OutOfMemoryError e) {
+            return -1;
+        }
+    }
+
+    // The heap's usage goes up by at least the size of an array of ints
+    public static int memory(int length) {
+        Runtime rt = Runtime.getRuntime();
+        long used = rt.totalMemory() - rt.freeMemory();
+        if (rt.freeMemory() <= 0) {
+            return -1;
+        }
+        int[] ints = new int[length];
+        ints[length - 1] = length;
+        if (rt.totalMemory() - rt.freeMemory() - used < 4L * length) {
+            return -2;
+        }
+        return ints[length - 1];
+    }
+
+    private static Garbage chain(int length) {
+        Garbage head = null;
+        for (int i = 1; i <= length; i++) {
+            Garbage g = new Garbage();
+            g.value = i;
+            g.next = head;
+            head = g;
+        }
+        return head;
+    }
+
+    private static int sum(Garbage g) {
+        int total = 0;
+        while (g != null) {
+            total += g.value;
+            g = g.next;
+        }
+        return total;
+    }
+}
@@ -0,0 +1,36 @@
+public class Hashes {
+    // An object's hash stays the same through locking, and is the one
+    // System.identityHashCode() gives
+    public static int stable() {
+        Object o = new Object();
+        int hash = o.hashCode();
+        synchronized (o) {
+            if (o.hashCode() != hash) {
+                return -1;
+            }
+        }
+        if (System.identityHashCode(o) != hash) {
+            return -2;
+        }
+        if (System.identityHashCode(null) != 0) {
+            return -3;
+        }
+        return hash == 0 ? -4 : 0;
+    }
+
+    // How many of 16 buckets the hashes of n objects land in
+    public static int buckets(int n) {
+        int[] counts = new int[16];
+        for (int i = 0; i < n; i++) {
+            int bucket = new Object().hashCode() % 16;
+            counts[bucket] = counts[bucket] + 1;
+        }
+        int used = 0;
+        for (int i = 0; i < 16; i++) {
+            if (counts[i] > 0) {
+                used++;
+            }
+        }
+        return used;
+    }
+}
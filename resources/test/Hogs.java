@@ -0,0 +1,48 @@
+public class Hogs extends Thread {
+    private int length;
+    private int rounds;
+    private int done;
+
+    public Hogs(int length, int rounds) {
+        this.length = length;
+        this.rounds = rounds;
+    }
+
+    public void run() {
+        done = fill(length, rounds);
+    }
+
+    // Asks for an array of ints over and over, each one garbage once the
+    // next is made, giving -1 if one of them doesn't fit
+    private static int fill(int length, int rounds) {
+        int total = 0;
+        try {
+            for (int i = 0; i < rounds; i++) {
+                int[] ints = new int[length];
+                ints[length - 1] = 1;
+                total += ints[length - 1];
+            }
+        } catch (OutOfMemoryError e) {
+            return -1;
+        }
+        return total;
+    }
+
+    // Two threads fill a heap with room for only a few of the arrays, so
+    // each needs the other's garbage collected to keep going
+    public static int race(int length, int rounds) throws InterruptedException {
+        Hogs other = new Hogs(length, rounds);
+        other.start();
+        int mine = fill(length, rounds);
+        other.join();
+        return mine * 1000 + other.done;
+    }
+
+    // The other thread's collections go ahead while this one is blocked
+    public static int joined(int length, int rounds) throws InterruptedException {
+        Hogs other = new Hogs(length, rounds);
+        other.start();
+        other.join();
+        return other.done;
+    }
+}
@@ -0,0 +1,27 @@
+public class Isolated {
+    static int count;
+    // Keeps the last string handed out from being collected
+    static Object last;
+
+    public static int bump() {
+        return ++count;
+    }
+
+    public static Object hello() {
+        last = "hello";
+        return last;
+    }
+
+    static Object greeting() {
+        return "hello";
+    }
+
+    // String constants are interned, so each is the one object
+    public static int interned() {
+        Object first = "hello";
+        if (first != greeting()) {
+            return -1;
+        }
+        return first == "goodbye" ? -2 : 1;
+    }
+}
@@ -0,0 +1,31 @@
+public class Looper {
+    public static int sum(int n) {
+        int s = 0;
+        for (int i = 0; i < n; i++) {
+            s += i;
+        }
+        return s;
+    }
+
+    public static int table(int i) {
+        switch (i) {
+            case 1: return 10;
+            case 2: return 20;
+            case 3: return 30;
+            default: return -1;
+        }
+    }
+
+    public static int lookup(int i) {
+        switch (i) {
+            case -100: return 1;
+            case 1000: return 2;
+            default: return 3;
+        }
+    }
+
+    public static void spin() {
+        while (true) {
+        }
+    }
+}
@@ -0,0 +1,80 @@
+public class Mirrors implements Runnable, Cloneable {
+    // Keeps the last name asked for from being collected
+    static Object last;
+
+    public void run() {
+    }
+
+    static Object of(int which) {
+        switch (which) {
+            case 0:
+                return new Mirrors();
+            case 1:
+                return new int[1];
+            case 2:
+                return new Mirrors[1];
+            default:
+                return new Object();
+        }
+    }
+
+    // Each class and array type has the one Class object
+    public static int same() {
+        if (new Mirrors().getClass() != Mirrors.class) {
+            return -1;
+        }
+        if (new int[1].getClass() != int[].class || new int[2].getClass() != new int[0].getClass()) {
+            return -2;
+        }
+        if (new Mirrors[0].getClass() != Mirrors[].class || (Object) int[].class == char[].class) {
+            return -3;
+        }
+        return (Object) Mirrors.class == Object.class ? -4 : 0;
+    }
+
+    public static Object name(int which) {
+        last = of(which).getClass().getName();
+        return last;
+    }
+
+    public static Object superName(int which) {
+        Class sup = of(which).getClass().getSuperclass();
+        last = sup == null ? null : sup.getName();
+        return last;
+    }
+
+    public static int interfaces() {
+        Class[] declared = Mirrors.class.getInterfaces();
+        int found = declared.length * 100;
+        if (declared[0] == Runnable.class) {
+            found += 10;
+        }
+        if (declared[1] == Cloneable.class) {
+            found += 1;
+        }
+        return found + int[].class.getInterfaces().length;
+    }
+
+    public static int kinds() {
+        int kinds = 0;
+        if (Runnable.class.isInterface()) {
+            kinds += 1;
+        }
+        if (Mirrors.class.isInterface()) {
+            kinds += 2;
+        }
+        if (int[].class.isInterface()) {
+            kinds += 4;
+        }
+        if (Runnable.class.getSuperclass() == null) {
+            kinds += 8;
+        }
+        return kinds;
+    }
+
+    // Static synchronized methods hold the Class object's monitor
+    public static synchronized int locked() {
+        Mirrors.class.notify();
+        return 1;
+    }
+}
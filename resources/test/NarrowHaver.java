@@ -0,0 +1,30 @@
+public class NarrowHaver {
+    public byte b;
+    public short s;
+    public char c;
+    public boolean z;
+
+    public static int fields() {
+        NarrowHaver nh = new NarrowHaver();
+        nh.b = (byte) 200;
+        nh.s = (short) 70000;
+        nh.c = (char) 0xd800;
+        return nh.b + nh.s + nh.c;
+    }
+
+    public static boolean flag() {
+        NarrowHaver nh = new NarrowHaver();
+        nh.z = true;
+        return nh.z;
+    }
+
+    public static int arrays() {
+        byte[] bs = new byte[2];
+        short[] ss = new short[2];
+        char[] cs = new char[2];
+        bs[1] = (byte) 200;
+        ss[1] = (short) 70000;
+        cs[1] = (char) 0xd800;
+        return bs[1] + ss[1] + cs[1];
+    }
+}
@@ -0,0 +1,37 @@
+public class Natives {
+    private int base;
+
+    public Natives(int base) {
+        this.base = base;
+    }
+
+    static native int add(int a, int b);
+
+    native int scaled(int by);
+
+    static native int[] squares(int n);
+
+    static native int missing();
+
+    public static int sum() {
+        return add(2, 3) + new Natives(10).scaled(4);
+    }
+
+    public static int squaresTotal(int n) {
+        int[] s = squares(n);
+        int total = 0;
+        for (int i = 0; i < s.length; i++) {
+            total += s[i];
+        }
+        return total;
+    }
+
+    public static int unlinked() {
+        try {
+            missing();
+            return 0;
+        } catch (UnsatisfiedLinkError e) {
+            return 1;
+        }
+    }
+}
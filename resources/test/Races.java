@@ -0,0 +1,37 @@
+public class Races extends Thread {
+    private static int total;
+    private int rounds;
+
+    public Races(int rounds) {
+        this.rounds = rounds;
+    }
+
+    public void run() {
+        for (int i = 0; i < rounds; i++) {
+            int seen = total;
+            total = seen + 1;
+        }
+    }
+
+    // Two threads bump a shared counter without a lock, so updates can
+    // get lost
+    public static int unlocked(int rounds) throws InterruptedException {
+        total = 0;
+        Races a = new Races(rounds);
+        Races b = new Races(rounds);
+        a.start();
+        b.start();
+        a.join();
+        b.join();
+        return total;
+    }
+
+    // Nothing ever notifies the lock
+    public static int stuck() throws InterruptedException {
+        Object lock = new Object();
+        synchronized (lock) {
+            lock.wait();
+        }
+        return 1;
+    }
+}
@@ -0,0 +1,16 @@
+public class Recurser {
+    public static int depth(int n) {
+        if (n == 0) {
+            return 0;
+        }
+        return depth(n - 1) + 1;
+    }
+
+    public static int caught(int n) {
+        try {
+            return depth(n);
+        } catch (StackOverflowError e) {
+            return -1;
+        }
+    }
+}
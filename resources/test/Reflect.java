@@ -0,0 +1,118 @@
+public class Reflect {
+    static int inits;
+    static int made;
+
+    static {
+        inits++;
+    }
+
+    public Reflect() {
+        made++;
+    }
+
+    static abstract class Shape {
+    }
+
+    interface Named {
+    }
+
+    static class Hidden {
+        private Hidden() {
+        }
+    }
+
+    static class Sized {
+        Sized(int size) {
+        }
+    }
+
+    // Only ever loaded by forName()
+    static class Base {
+        static int order;
+
+        static {
+            Base.order = Base.order * 10 + 1;
+        }
+    }
+
+    static class Lazy extends Base {
+        static {
+            Base.order = Base.order * 10 + 2;
+        }
+    }
+
+    // Initialisation happens on the first forName() only
+    public static int initialise() throws Exception {
+        int before = inits;
+        Class found = Class.forName(Reflect.class.getName());
+        if (found != Reflect.class) {
+            return -1;
+        }
+        Class.forName(Reflect.class.getName());
+        return before * 10 + inits;
+    }
+
+    // Loads a class nothing has needed yet, its superclass set up first
+    public static int loads() throws Exception {
+        Class.forName("Reflect$Lazy");
+        return Base.order;
+    }
+
+    public static int arrays() throws Exception {
+        int found = 0;
+        if (Class.forName(int[].class.getName()) == int[].class) {
+            found += 1;
+        }
+        if (Class.forName(Reflect[].class.getName()) == Reflect[].class) {
+            found += 10;
+        }
+        if (Class.forName(Hidden.class.getName()) == Hidden.class) {
+            found += 100;
+        }
+        return found;
+    }
+
+    static int notFound(String name) {
+        try {
+            Class.forName(name);
+            return 0;
+        } catch (ClassNotFoundException e) {
+            return 1;
+        }
+    }
+
+    public static int missing() {
+        return notFound(new String(new char[] {'N', 'o', 'p', 'e'}))
+                + notFound(Object.class.getName().replace('.', '/')) * 10
+                + notFound(new String(new char[] {'[', 'Q'})) * 100;
+    }
+
+    public static int instances() throws Exception {
+        int before = made;
+        Object first = Reflect.class.newInstance();
+        Object second = Class.forName(Reflect.class.getName()).newInstance();
+        if (first == second || first.getClass() != Reflect.class) {
+            return -1;
+        }
+        return made - before;
+    }
+
+    static int cannotMake(Class klass) {
+        try {
+            klass.newInstance();
+            return 0;
+        } catch (InstantiationException e) {
+            return 1;
+        } catch (IllegalAccessException e) {
+            return 2;
+        }
+    }
+
+    public static int errors() {
+        return cannotMake(Shape.class)
+                + cannotMake(Named.class) * 10
+                + cannotMake(Sized.class) * 100
+                + cannotMake(Hidden.class) * 1000
+                + cannotMake(int[].class) * 10000;
+    }
+}
@@ -0,0 +1,53 @@
+public class Sheep implements Cloneable {
+    static Sheep original;
+
+    int legs;
+    Sheep mother;
+
+    Sheep(int legs, Sheep mother) {
+        this.legs = legs;
+        this.mother = mother;
+    }
+
+    Object twin() throws CloneNotSupportedException {
+        return clone();
+    }
+
+    public static Object dolly() throws CloneNotSupportedException {
+        original = new Sheep(4, new Sheep(4, null));
+        return original.twin();
+    }
+
+    // A copy is a different object with the same field values
+    public static int compare(Sheep s) {
+        if (s == original) {
+            return -1;
+        }
+        if (s.legs != original.legs) {
+            return -2;
+        }
+        if (s.mother != original.mother) {
+            return -3;
+        }
+        return 0;
+    }
+
+    public static Object copy(int[] a) {
+        return a.clone();
+    }
+
+    public static int goat() {
+        try {
+            new Goat().twin();
+            return 0;
+        } catch (CloneNotSupportedException e) {
+            return -1;
+        }
+    }
+
+    static class Goat {
+        Object twin() throws CloneNotSupportedException {
+            return clone();
+        }
+    }
+}
@@ -0,0 +1,40 @@
+public class Syncer {
+    private int count;
+
+    public synchronized int inc() {
+        count = count + 1;
+        return count;
+    }
+
+    public static synchronized int twice(int n) {
+        return n + n;
+    }
+
+    // Returns the object so the caller can check its monitor was released
+    public static Object nested() {
+        Object o = new Object();
+        synchronized (o) {
+            synchronized (o) {
+            }
+        }
+        return o;
+    }
+
+    public static Syncer counted() {
+        Syncer s = new Syncer();
+        s.inc();
+        s.inc();
+        return s;
+    }
+
+    public static int nullLock() {
+        Object o = null;
+        try {
+            synchronized (o) {
+                return 1;
+            }
+        } catch (NullPointerException e) {
+            return -1;
+        }
+    }
+}
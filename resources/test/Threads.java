@@ -0,0 +1,61 @@
+public class Threads extends Thread {
+    private static int total;
+    private int rounds;
+
+    public Threads(int rounds) {
+        this.rounds = rounds;
+    }
+
+    public void run() {
+        for (int i = 0; i < rounds; i++) {
+            add(1);
+            if (i % 100 == 0) {
+                Thread.yield();
+            }
+        }
+    }
+
+    private static synchronized void add(int n) {
+        total = total + n;
+    }
+
+    // Two subclassed threads bump a shared counter under a lock
+    public static int race(int rounds) throws InterruptedException {
+        total = 0;
+        Threads a = new Threads(rounds);
+        Threads b = new Threads(rounds);
+        a.start();
+        b.start();
+        a.join();
+        b.join();
+        return total;
+    }
+
+    // A Thread given a Runnable runs it, and is only alive while it does
+    public static int runnable(int rounds) throws InterruptedException {
+        total = 0;
+        Thread t = new Thread(new Threads(rounds));
+        if (t.isAlive()) {
+            return -1;
+        }
+        t.start();
+        t.join();
+        if (t.isAlive()) {
+            return -2;
+        }
+        return total;
+    }
+
+    public static int current() throws InterruptedException {
+        Thread me = Thread.currentThread();
+        if (me != Thread.currentThread() || !me.isAlive()) {
+            return -1;
+        }
+        long start = System.currentTimeMillis();
+        Thread.sleep(20);
+        if (System.currentTimeMillis() - start < 20) {
+            return -2;
+        }
+        return me.getPriority();
+    }
+}
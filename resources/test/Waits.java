@@ -0,0 +1,112 @@
+public class Waits extends Thread {
+    private static final int SIGNAL = 0;
+    private static final int WAIT = 1;
+    private static final int SLEEP = 2;
+
+    private Object lock;
+    private int mode;
+    private boolean waiting;
+    private int result;
+
+    public Waits(Object lock, int mode) {
+        this.lock = lock;
+        this.mode = mode;
+    }
+
+    public void run() {
+        if (mode == SLEEP) {
+            try {
+                Thread.sleep(60000);
+                result = 1;
+            } catch (InterruptedException e) {
+                result = 2;
+            }
+            return;
+        }
+        synchronized (lock) {
+            if (mode == SIGNAL) {
+                result = 1;
+                lock.notifyAll();
+            } else {
+                waiting = true;
+                lock.notifyAll();
+                try {
+                    lock.wait();
+                    result = 1;
+                } catch (InterruptedException e) {
+                    result = 2;
+                }
+            }
+        }
+    }
+
+    // Main waits for a worker to hand over a result
+    public static int handoff() throws InterruptedException {
+        Object lock = new Object();
+        Waits w = new Waits(lock, SIGNAL);
+        synchronized (lock) {
+            w.start();
+            while (w.result == 0) {
+                lock.wait();
+            }
+        }
+        w.join();
+        return w.result;
+    }
+
+    // A waiting worker is interrupted out of wait(), and the
+    // InterruptedException clears its interrupted flag
+    public static int interruptWait() throws InterruptedException {
+        Object lock = new Object();
+        Waits w = new Waits(lock, WAIT);
+        synchronized (lock) {
+            w.start();
+            while (!w.waiting) {
+                lock.wait();
+            }
+            w.interrupt();
+        }
+        w.join();
+        return w.isInterrupted() ? -1 : w.result;
+    }
+
+    public static int interruptSleep() throws InterruptedException {
+        Waits w = new Waits(null, SLEEP);
+        w.start();
+        w.interrupt();
+        w.join();
+        return w.result;
+    }
+
+    public static int timedWait() {
+        Object lock = new Object();
+        long start = System.currentTimeMillis();
+        synchronized (lock) {
+            try {
+                lock.wait(30);
+            } catch (InterruptedException e) {
+                return -1;
+            }
+        }
+        return System.currentTimeMillis() - start >= 30 ? 1 : 0;
+    }
+
+    // Both need the caller to hold the monitor
+    public static int notOwner() {
+        Object o = new Object();
+        int thrown = 0;
+        try {
+            o.notify();
+        } catch (IllegalMonitorStateException e) {
+            thrown = thrown + 1;
+        }
+        try {
+            o.wait();
+        } catch (IllegalMonitorStateException e) {
+            thrown = thrown + 1;
+        } catch (InterruptedException e) {
+            return -1;
+        }
+        return thrown;
+    }
+}
@@ -0,0 +1,151 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::interp_stack::InterpEvalStack;
+use crate::otklass::OtKlass;
+use crate::otmethod::OtMethod;
+use crate::InterpLocalVars;
+use crate::JvmValue;
+
+// Same as HotSpot's default -Xss on 64-bit Linux
+pub const DEFAULT_STACK_SIZE: usize = 1024 * 1024;
+
+// An activation record for a bytecode method. Frames live on the heap in a
+// FrameStack rather than on the Rust stack, so Java call depth does not
+// depend on the size of the native thread stack.
+pub struct Frame {
+    klass: Arc<OtKlass>,
+    method_idx: usize,
+    pub pc: usize,
+    pub lvt: InterpLocalVars,
+    pub eval: InterpEvalStack,
+    // Object whose monitor was taken on entry to a synchronized method
+    pub monitor: Option<usize>,
+    // Running finalize() for the collector, so exceptions stop here
+    pub finalizer: bool,
+}
+
+impl Frame {
+    pub fn of(klass: Arc<OtKlass>, method_idx: usize, lvt: InterpLocalVars) -> Frame {
+        Frame {
+            klass,
+            method_idx,
+            pc: 0,
+            lvt,
+            eval: InterpEvalStack::of(),
+            monitor: None,
+            finalizer: false,
+        }
+    }
+
+    pub fn get_klass(&self) -> &Arc<OtKlass> {
+        &self.klass
+    }
+
+    pub fn get_method_index(&self) -> usize {
+        self.method_idx
+    }
+
+    pub fn get_method(&self) -> &OtMethod {
+        self.klass.get_method(self.method_idx)
+    }
+
+    // Objects the frame's locals and operand stack refer to, which are
+    // roots for the collector
+    pub fn refs(&self) -> impl Iterator<Item = usize> + '_ {
+        self.lvt
+            .values()
+            .iter()
+            .chain(self.eval.values())
+            .filter_map(|v| match v {
+                JvmValue::ObjRef(id) if *id != 0 => Some(*id),
+                _ => None,
+            })
+            .chain(self.monitor)
+    }
+
+    // Bytes charged against the thread's stack size for this frame
+    pub fn size(&self) -> usize {
+        let meth = self.get_method();
+        let slots = meth.get_local_var_size() as usize + meth.get_max_stack() as usize;
+        std::mem::size_of::<Frame>() + slots * std::mem::size_of::<JvmValue>()
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} @ {}", self.get_method(), self.pc)
+    }
+}
+
+pub struct FrameStack {
+    frames: Vec<Frame>,
+    used: usize,
+    max_size: usize,
+}
+
+impl FrameStack {
+    pub fn of() -> FrameStack {
+        FrameStack::with_max_size(DEFAULT_STACK_SIZE)
+    }
+
+    pub fn with_max_size(max_size: usize) -> FrameStack {
+        FrameStack {
+            frames: Vec::new(),
+            used: 0,
+            max_size,
+        }
+    }
+
+    // Returns false, leaving the stack as it was, if the frame would take
+    // it over its maximum size
+    #[must_use]
+    pub fn push(&mut self, frame: Frame) -> bool {
+        let size = frame.size();
+        if self.used + size > self.max_size {
+            return false;
+        }
+        self.used += size;
+        self.frames.push(frame);
+        true
+    }
+
+    pub fn pop(&mut self) -> Option<Frame> {
+        let frame = self.frames.pop();
+        if let Some(f) = &frame {
+            self.used -= f.size();
+        }
+        frame
+    }
+
+    // Whether a finalizer is running, maybe further down the stack
+    pub fn finalizing(&self) -> bool {
+        self.frames.iter().any(|f| f.finalizer)
+    }
+
+    pub fn top(&mut self) -> Option<&mut Frame> {
+        self.frames.last_mut()
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn refs(&self) -> impl Iterator<Item = usize> + '_ {
+        self.frames.iter().flat_map(Frame::refs)
+    }
+
+    // Innermost frame first, as in a Java stack trace
+    pub fn iter(&self) -> impl Iterator<Item = &Frame> {
+        self.frames.iter().rev()
+    }
+}
+
+impl fmt::Display for FrameStack {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for frame in self.iter() {
+            writeln!(f, "\tat {}", frame)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::simple_heap::Finalizer;
+use crate::vm::Vm;
+
+// Tracing collection of the heap, which compacts it as well if the
+// mark-compact collector was chosen. The roots are
+//  - references in interpreter frames, which the interpreter passes in,
+//    as it only collects in between instructions
+//  - static fields and mirrors of the VM's klasses, and its interned strings
+//  - native handles, i.e. objects pinned by runtime code
+//
+// Allocation pressure and Runtime.gc() only ask for a collection, which
+// happens at the interpreter's next chance. It only goes ahead once every
+// other OS thread running Java code, green threads counting as one, has
+// stopped and handed over its frame references. Threads blocked on a
+// monitor, in wait() or in sleep() count as stopped, see blocking(). The
+// others only stop at a safe point once a thread is out of memory, and
+// give up on a collection after SAFEPOINT_TIMEOUT.
+//
+// Objects whose klass overrides finalize() are queued once found
+// unreachable. The interpreter runs their finalizers at its safe points,
+// on whichever of the VM's threads gets there first.
+
+// Each VM's collector state
+#[derive(Debug, Default)]
+pub struct GcState {
+    requested: AtomicBool,
+    // Objects are waiting in the finalization queue
+    finalizing: AtomicBool,
+    // OS threads currently running Java code
+    running: AtomicUsize,
+    safepoint: Mutex<Safepoint>,
+    // Signalled as threads stop and as collections finish
+    changed: Condvar,
+}
+
+// How long threads stopped at a safe point wait for the others
+pub const SAFEPOINT_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Default)]
+struct Safepoint {
+    // Frame references of the threads stopped, by ticket
+    stopped: HashMap<u64, Vec<usize>>,
+    next_ticket: u64,
+    // Threads stopped until there's a collection
+    waiting: usize,
+    collections: u64,
+    // Objects the last collection freed
+    freed: usize,
+}
+
+impl Safepoint {
+    fn stop(&mut self, refs: Vec<usize>) -> u64 {
+        let ticket = self.next_ticket;
+        self.next_ticket += 1;
+        self.stopped.insert(ticket, refs);
+        ticket
+    }
+}
+
+impl GcState {
+    pub fn request(&self) -> () {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+}
+
+pub fn request(vm: &Vm) -> () {
+    vm.gc.request();
+}
+
+pub fn requested(vm: &Vm) -> bool {
+    vm.gc.requested.load(Ordering::Relaxed)
+}
+
+// Held by an OS thread while it runs Java code
+pub struct Running(Arc<GcState>);
+
+pub fn running(vm: &Vm) -> Running {
+    vm.gc.running.fetch_add(1, Ordering::SeqCst);
+    Running(vm.gc.clone())
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.running.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Collects now, with the frame references of the calling thread's Java
+// threads, unless other OS threads are running Java code. If wait, or when
+// others already are, it then stops at this safe point until one of them
+// collects. Returns how many objects were freed, or None if the collection
+// has to wait.
+pub fn collect<I: IntoIterator<Item = usize>>(vm: &Vm, frame_refs: I, wait: bool) -> Option<usize> {
+    let mut roots: Vec<usize> = frame_refs.into_iter().collect();
+    let mut safepoint = vm.gc.safepoint.lock().unwrap();
+    // Those not stopped, counting the calling thread until it does
+    let unstopped = |sp: &Safepoint| vm.gc.running.load(Ordering::SeqCst).saturating_sub(sp.stopped.len());
+    if unstopped(&safepoint) > 1 {
+        if !wait && safepoint.waiting == 0 {
+            return None;
+        }
+        let ticket = safepoint.stop(roots);
+        safepoint.waiting += 1;
+        vm.gc.request();
+        vm.gc.changed.notify_all();
+
+        let seen = safepoint.collections;
+        let deadline = Instant::now() + SAFEPOINT_TIMEOUT;
+        // What to return, or None if this is the last thread to stop, so
+        // collects itself
+        let outcome = loop {
+            if safepoint.collections != seen {
+                break Some(Some(safepoint.freed));
+            }
+            if unstopped(&safepoint) == 0 {
+                break None;
+            }
+            match deadline.checked_duration_since(Instant::now()) {
+                Some(left) if !left.is_zero() => {
+                    safepoint = vm.gc.changed.wait_timeout(safepoint, left).unwrap().0;
+                }
+                _ => break Some(None),
+            }
+        };
+        roots = safepoint.stopped.remove(&ticket).unwrap();
+        safepoint.waiting -= 1;
+        if let Some(freed) = outcome {
+            return freed;
+        }
+    }
+    roots.extend(safepoint.stopped.values().flatten());
+    roots.extend(vm.roots());
+
+    let mut heap = vm.heap();
+    vm.gc.requested.store(false, Ordering::SeqCst);
+    let freed = heap.collect(roots);
+    if heap.queued_finalizers() > 0 {
+        vm.gc.finalizing.store(true, Ordering::SeqCst);
+    }
+    safepoint.collections += 1;
+    safepoint.freed = freed;
+    vm.gc.changed.notify_all();
+    Some(freed)
+}
+
+// Runs f, which blocks the calling thread, with the thread counted as
+// stopped and refs as its roots, so others can collect meanwhile
+pub fn blocking<T, F: FnOnce() -> T>(vm: &Vm, refs: Vec<usize>, f: F) -> T {
+    let ticket = {
+        let mut safepoint = vm.gc.safepoint.lock().unwrap();
+        let ticket = safepoint.stop(refs);
+        vm.gc.changed.notify_all();
+        ticket
+    };
+    let ret = f();
+    // Not while a collection is under way
+    vm.gc.safepoint.lock().unwrap().stopped.remove(&ticket);
+    ret
+}
+
+pub fn finalization_pending(vm: &Vm) -> bool {
+    vm.gc.finalizing.load(Ordering::Relaxed)
+}
+
+// Takes the next object queued for finalization
+pub fn next_finalizer(vm: &Vm) -> Option<Finalizer> {
+    let mut heap = vm.heap();
+    let next = heap.next_finalizer();
+    if heap.queued_finalizers() == 0 {
+        vm.gc.finalizing.store(false, Ordering::SeqCst);
+    }
+    next
+}
@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::klass_repo::SharedKlassRepo;
+use crate::object::OtObj;
+use crate::simple_heap::SharedSimpleHeap;
+
+// Instance counts and shallow sizes by class, like jmap -histo
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClassStats {
+    // Internal name of the klass, or the descriptor of an array type
+    pub name: String,
+    pub instances: usize,
+    pub bytes: usize,
+}
+
+// Biggest classes first
+#[derive(Debug)]
+pub struct Histogram {
+    entries: Vec<ClassStats>,
+}
+
+impl Histogram {
+    // Groups heap's objects of the klasses in repo
+    pub fn of(heap: &SharedSimpleHeap, repo: &SharedKlassRepo) -> Histogram {
+        let klasses = repo.klasses();
+        let mut stats: HashMap<String, ClassStats> = HashMap::new();
+        for obj in heap.objects() {
+            let name = match obj {
+                OtObj::VmObj {
+                    klassid, fields, ..
+                } => {
+                    match klassid.checked_sub(1).and_then(|i| klasses.get(i)) {
+                        // The heap is shared, so may hold objects of other
+                        // repos' klasses, which are left out
+                        Some(k) if repo.instance_fields(k).len() == fields.len() => k.get_name(),
+                        _ => continue,
+                    }
+                }
+                OtObj::VmArrInt { .. } => "[I".to_string(),
+                OtObj::VmArrLong { .. } => "[J".to_string(),
+                OtObj::VmArrBoolean { .. } => "[Z".to_string(),
+                OtObj::VmArrByte { .. } => "[B".to_string(),
+                OtObj::VmArrChar { .. } => "[C".to_string(),
+                OtObj::VmArrShort { .. } => "[S".to_string(),
+                OtObj::VmArrObj { klassid, .. } => {
+                    match klassid.checked_sub(1).and_then(|i| klasses.get(i)) {
+                        Some(k) => format!("[L{};", k.get_name()),
+                        None => continue,
+                    }
+                }
+            };
+            let entry = stats.entry(name.clone()).or_insert(ClassStats {
+                name,
+                instances: 0,
+                bytes: 0,
+            });
+            entry.instances += 1;
+            entry.bytes += obj.size();
+        }
+
+        let mut entries: Vec<ClassStats> = stats.into_values().collect();
+        entries.sort_by(|a, b| {
+            b.bytes
+                .cmp(&a.bytes)
+                .then(b.instances.cmp(&a.instances))
+                .then(a.name.cmp(&b.name))
+        });
+        Histogram { entries }
+    }
+
+    pub fn entries(&self) -> &[ClassStats] {
+        &self.entries
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ClassStats> {
+        self.entries.iter().find(|s| s.name == name)
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, " num     #instances         #bytes  class name")?;
+        writeln!(f, "----------------------------------------------")?;
+        for (i, s) in self.entries.iter().enumerate() {
+            writeln!(
+                f,
+                "{:>4}: {:>14} {:>14}  {}",
+                i + 1,
+                s.instances,
+                s.bytes,
+                s.name
+            )?;
+        }
+        let instances: usize = self.entries.iter().map(|s| s.instances).sum();
+        let bytes: usize = self.entries.iter().map(|s| s.bytes).sum();
+        write!(f, "Total {:>14} {:>14}", instances, bytes)
+    }
+}
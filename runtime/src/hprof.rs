@@ -0,0 +1,404 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use byteorder::{BigEndian, WriteBytesExt};
+
+use crate::klass_repo::SharedKlassRepo;
+use crate::object::OtObj;
+use crate::otfield::OtField;
+use crate::otklass::OtKlass;
+use crate::simple_heap::SharedSimpleHeap;
+use crate::JvmValue;
+
+// Heap dumps in the HPROF binary format that jmap -dump writes, so the usual
+// tools can read them. Identifiers are 8 bytes: objects go by their id,
+// while klasses and the strings naming things get ids above any object's.
+// String contents show up through the char[] each String refers to.
+// Object arrays get a class of their own for each element klass.
+
+const HEADER: &[u8] = b"JAVA PROFILE 1.0.2\0";
+const ID_SIZE: u32 = 8;
+
+// Top level records
+const UTF8: u8 = 0x01;
+const LOAD_CLASS: u8 = 0x02;
+const STACK_TRACE: u8 = 0x05;
+const HEAP_DUMP_SEGMENT: u8 = 0x1C;
+const HEAP_DUMP_END: u8 = 0x2C;
+
+// Heap dump sub-records
+const ROOT_UNKNOWN: u8 = 0xFF;
+const ROOT_JNI_GLOBAL: u8 = 0x01;
+const ROOT_STICKY_CLASS: u8 = 0x05;
+const CLASS_DUMP: u8 = 0x20;
+const INSTANCE_DUMP: u8 = 0x21;
+const OBJECT_ARRAY_DUMP: u8 = 0x22;
+const PRIMITIVE_ARRAY_DUMP: u8 = 0x23;
+
+const KLASS_IDS: u64 = 1 << 48;
+const ARRAY_KLASS_IDS: u64 = 1 << 52;
+const NAME_IDS: u64 = 1 << 56;
+
+// Stack trace serial of the single, empty, trace everything refers to
+const NO_TRACE: u32 = 1;
+
+// Heap dump segments are written out once they get this big
+const SEGMENT_SIZE: usize = 1 << 20;
+
+// Writes heap's objects of the klasses in repo, with roots and the pinned
+// objects as GC roots. The statics of each klass are reachable from its
+// class dump.
+pub fn write<W: Write>(
+    heap: &SharedSimpleHeap,
+    repo: &SharedKlassRepo,
+    roots: &[usize],
+    out: W,
+) -> io::Result<()> {
+    let klasses = repo.klasses();
+    let by_name: HashMap<String, &Arc<OtKlass>> =
+        klasses.iter().map(|k| (k.get_name(), k)).collect();
+    let element_klasses: BTreeSet<usize> = heap
+        .objects()
+        .filter_map(|obj| match obj {
+            OtObj::VmArrObj { klassid, .. } => Some(*klassid),
+            _ => None,
+        })
+        .collect();
+    let array_klasses: Vec<&Arc<OtKlass>> = element_klasses
+        .iter()
+        .filter_map(|kid| kid.checked_sub(1).and_then(|i| klasses.get(i)))
+        .collect();
+    let mut dump = Dump::of(out)?;
+
+    let mut stack = vec![];
+    stack.write_u32::<BigEndian>(NO_TRACE)?;
+    stack.write_u32::<BigEndian>(0)?;
+    stack.write_u32::<BigEndian>(0)?;
+    dump.record(STACK_TRACE, &stack)?;
+
+    for (serial, k) in klasses.iter().enumerate() {
+        let name = dump.name(&k.get_name())?;
+        let mut load = vec![];
+        load.write_u32::<BigEndian>(serial as u32 + 1)?;
+        load.write_u64::<BigEndian>(klass_id(k))?;
+        load.write_u32::<BigEndian>(NO_TRACE)?;
+        load.write_u64::<BigEndian>(name)?;
+        dump.record(LOAD_CLASS, &load)?;
+    }
+    for (serial, k) in array_klasses.iter().enumerate() {
+        let name = dump.name(&format!("[L{};", k.get_name()))?;
+        let mut load = vec![];
+        load.write_u32::<BigEndian>((klasses.len() + serial) as u32 + 1)?;
+        load.write_u64::<BigEndian>(array_klass_id(k))?;
+        load.write_u32::<BigEndian>(NO_TRACE)?;
+        load.write_u64::<BigEndian>(name)?;
+        dump.record(LOAD_CLASS, &load)?;
+    }
+
+    for k in klasses.iter() {
+        let mut instance_size = 0;
+        let mut c = Some(k);
+        while let Some(sk) = c {
+            instance_size += sk.own_instance_fields().iter().map(value_size).sum::<u32>();
+            c = superklass(&by_name, sk);
+        }
+        dump.class_dump(k, superklass(&by_name, k), instance_size)?;
+    }
+    let object = by_name.get("java/lang/Object").copied();
+    for k in array_klasses.iter() {
+        dump.array_class_dump(k, object)?;
+    }
+    for &id in roots.iter().filter(|&&id| id != 0) {
+        dump.segment.write_u8(ROOT_UNKNOWN)?;
+        dump.segment.write_u64::<BigEndian>(id as u64)?;
+    }
+    for id in heap.pinned() {
+        dump.segment.write_u8(ROOT_JNI_GLOBAL)?;
+        dump.segment.write_u64::<BigEndian>(id as u64)?;
+        dump.segment.write_u64::<BigEndian>(id as u64)?;
+    }
+    for k in klasses.iter() {
+        dump.segment.write_u8(ROOT_STICKY_CLASS)?;
+        dump.segment.write_u64::<BigEndian>(klass_id(k))?;
+    }
+
+    for obj in heap.objects() {
+        match obj {
+            OtObj::VmObj {
+                id,
+                klassid,
+                fields,
+                ..
+            } => {
+                let klass = match klassid.checked_sub(1).and_then(|i| klasses.get(i)) {
+                    Some(k) => k,
+                    None => continue,
+                };
+                let mut chain = vec![];
+                let mut k = Some(klass);
+                while let Some(c) = k {
+                    chain.extend(c.own_instance_fields());
+                    k = superklass(&by_name, c);
+                }
+                // The heap is shared, so may hold objects of other repos'
+                // klasses, which are left out
+                if chain.len() != fields.len() {
+                    continue;
+                }
+                let mut values = vec![];
+                for f in chain {
+                    write_value(&mut values, f.narrow(fields[f.get_offset() as usize].get()))?;
+                }
+                dump.segment.write_u8(INSTANCE_DUMP)?;
+                dump.segment.write_u64::<BigEndian>(*id as u64)?;
+                dump.segment.write_u32::<BigEndian>(NO_TRACE)?;
+                dump.segment.write_u64::<BigEndian>(klass_id(klass))?;
+                dump.segment.write_u32::<BigEndian>(values.len() as u32)?;
+                dump.segment.extend(values);
+            }
+            OtObj::VmArrInt { id, elements, .. } => {
+                dump.array_header(*id, elements.len(), 'I')?;
+                for &e in elements {
+                    dump.segment.write_i32::<BigEndian>(e)?;
+                }
+            }
+            OtObj::VmArrLong { id, elements, .. } => {
+                dump.array_header(*id, elements.len(), 'J')?;
+                for &e in elements {
+                    dump.segment.write_i64::<BigEndian>(e)?;
+                }
+            }
+            OtObj::VmArrBoolean { id, elements, .. } => {
+                dump.array_header(*id, elements.len(), 'Z')?;
+                for &e in elements {
+                    dump.segment.write_u8(e as u8)?;
+                }
+            }
+            OtObj::VmArrByte { id, elements, .. } => {
+                dump.array_header(*id, elements.len(), 'B')?;
+                for &e in elements {
+                    dump.segment.write_i8(e)?;
+                }
+            }
+            OtObj::VmArrChar { id, elements, .. } => {
+                dump.array_header(*id, elements.len(), 'C')?;
+                for &e in elements {
+                    dump.segment.write_u16::<BigEndian>(e)?;
+                }
+            }
+            OtObj::VmArrShort { id, elements, .. } => {
+                dump.array_header(*id, elements.len(), 'S')?;
+                for &e in elements {
+                    dump.segment.write_i16::<BigEndian>(e)?;
+                }
+            }
+            OtObj::VmArrObj {
+                id,
+                klassid,
+                elements,
+                ..
+            } => {
+                let klass = match klassid.checked_sub(1).and_then(|i| klasses.get(i)) {
+                    Some(k) => k,
+                    None => continue,
+                };
+                dump.segment.write_u8(OBJECT_ARRAY_DUMP)?;
+                dump.segment.write_u64::<BigEndian>(*id as u64)?;
+                dump.segment.write_u32::<BigEndian>(NO_TRACE)?;
+                dump.segment.write_u32::<BigEndian>(elements.len() as u32)?;
+                dump.segment.write_u64::<BigEndian>(array_klass_id(klass))?;
+                for &e in elements {
+                    dump.segment.write_u64::<BigEndian>(e as u64)?;
+                }
+            }
+        }
+        dump.flush_segment(SEGMENT_SIZE)?;
+    }
+    dump.flush_segment(0)?;
+    dump.record(HEAP_DUMP_END, &[])?;
+    dump.out.flush()
+}
+
+struct Dump<W: Write> {
+    out: W,
+    names: HashMap<String, u64>,
+    // Sub-records of the heap dump segment being built
+    segment: Vec<u8>,
+}
+
+impl<W: Write> Dump<W> {
+    fn of(mut out: W) -> io::Result<Dump<W>> {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        out.write_all(HEADER)?;
+        out.write_u32::<BigEndian>(ID_SIZE)?;
+        out.write_u64::<BigEndian>(millis)?;
+        Ok(Dump {
+            out,
+            names: HashMap::new(),
+            segment: vec![],
+        })
+    }
+
+    fn record(&mut self, tag: u8, body: &[u8]) -> io::Result<()> {
+        self.out.write_u8(tag)?;
+        // Microseconds since the header's timestamp
+        self.out.write_u32::<BigEndian>(0)?;
+        self.out.write_u32::<BigEndian>(body.len() as u32)?;
+        self.out.write_all(body)
+    }
+
+    // The id of a UTF8 record holding s, writing it the first time
+    fn name(&mut self, s: &str) -> io::Result<u64> {
+        if let Some(&id) = self.names.get(s) {
+            return Ok(id);
+        }
+        let id = NAME_IDS + self.names.len() as u64;
+        let mut body = vec![];
+        body.write_u64::<BigEndian>(id)?;
+        body.extend(s.as_bytes());
+        self.record(UTF8, &body)?;
+        self.names.insert(s.to_string(), id);
+        Ok(id)
+    }
+
+    fn flush_segment(&mut self, at_least: usize) -> io::Result<()> {
+        if self.segment.is_empty() || self.segment.len() < at_least {
+            return Ok(());
+        }
+        let segment = std::mem::take(&mut self.segment);
+        self.record(HEAP_DUMP_SEGMENT, &segment)
+    }
+
+    fn class_dump(
+        &mut self,
+        k: &OtKlass,
+        superklass: Option<&Arc<OtKlass>>,
+        instance_size: u32,
+    ) -> io::Result<()> {
+        let mut statics = vec![];
+        let s_fields = k.get_static_fields();
+        statics.write_u16::<BigEndian>(s_fields.len() as u16)?;
+        for (slot, f) in s_fields.iter().enumerate() {
+            statics.write_u64::<BigEndian>(self.name(&f.get_name())?)?;
+            statics.write_u8(basic_type(f))?;
+            write_value(&mut statics, f.narrow(k.get_static_at(slot)))?;
+        }
+        let mut fields = vec![];
+        let i_fields = k.own_instance_fields();
+        fields.write_u16::<BigEndian>(i_fields.len() as u16)?;
+        for f in i_fields {
+            fields.write_u64::<BigEndian>(self.name(&f.get_name())?)?;
+            fields.write_u8(basic_type(f))?;
+        }
+
+        let s = &mut self.segment;
+        s.write_u8(CLASS_DUMP)?;
+        s.write_u64::<BigEndian>(klass_id(k))?;
+        s.write_u32::<BigEndian>(NO_TRACE)?;
+        s.write_u64::<BigEndian>(superklass.map_or(0, |sk| klass_id(sk)))?;
+        // Class loader, signers, protection domain and two reserved ids
+        for _ in 0..5 {
+            s.write_u64::<BigEndian>(0)?;
+        }
+        s.write_u32::<BigEndian>(instance_size)?;
+        // Constant pool
+        s.write_u16::<BigEndian>(0)?;
+        s.extend(statics);
+        s.extend(fields);
+        Ok(())
+    }
+
+    // The class of arrays of k's instances, which only has Object's fields
+    fn array_class_dump(&mut self, k: &OtKlass, object: Option<&Arc<OtKlass>>) -> io::Result<()> {
+        let s = &mut self.segment;
+        s.write_u8(CLASS_DUMP)?;
+        s.write_u64::<BigEndian>(array_klass_id(k))?;
+        s.write_u32::<BigEndian>(NO_TRACE)?;
+        s.write_u64::<BigEndian>(object.map_or(0, |o| klass_id(o)))?;
+        for _ in 0..5 {
+            s.write_u64::<BigEndian>(0)?;
+        }
+        s.write_u32::<BigEndian>(0)?;
+        // No constants, statics or instance fields
+        for _ in 0..3 {
+            s.write_u16::<BigEndian>(0)?;
+        }
+        Ok(())
+    }
+
+    fn array_header(&mut self, id: usize, length: usize, letter: char) -> io::Result<()> {
+        self.segment.write_u8(PRIMITIVE_ARRAY_DUMP)?;
+        self.segment.write_u64::<BigEndian>(id as u64)?;
+        self.segment.write_u32::<BigEndian>(NO_TRACE)?;
+        self.segment.write_u32::<BigEndian>(length as u32)?;
+        self.segment.write_u8(letter_type(letter))
+    }
+}
+
+fn klass_id(k: &OtKlass) -> u64 {
+    KLASS_IDS + k.get_id() as u64
+}
+
+fn array_klass_id(element: &OtKlass) -> u64 {
+    ARRAY_KLASS_IDS + element.get_id() as u64
+}
+
+// j.l.Object is its own superclass, and has none here
+fn superklass<'a>(
+    by_name: &HashMap<String, &'a Arc<OtKlass>>,
+    k: &OtKlass,
+) -> Option<&'a Arc<OtKlass>> {
+    let super_name = k.get_super_name();
+    if super_name == k.get_name() {
+        None
+    } else {
+        by_name.get(&super_name).copied()
+    }
+}
+
+// HPROF's basic type codes, by descriptor letter
+fn letter_type(letter: char) -> u8 {
+    match letter {
+        'Z' => 4,
+        'C' => 5,
+        'F' => 6,
+        'D' => 7,
+        'B' => 8,
+        'S' => 9,
+        'I' => 10,
+        'J' => 11,
+        // Object
+        _ => 2,
+    }
+}
+
+fn basic_type(f: &OtField) -> u8 {
+    letter_type(f.get_desc().chars().next().unwrap_or('L'))
+}
+
+fn value_size(f: &OtField) -> u32 {
+    match basic_type(f) {
+        4 | 8 => 1,
+        5 | 9 => 2,
+        6 | 10 => 4,
+        _ => 8,
+    }
+}
+
+fn write_value(out: &mut Vec<u8>, v: JvmValue) -> io::Result<()> {
+    match v {
+        JvmValue::Boolean(b) => out.write_u8(b as u8),
+        JvmValue::Byte(b) => out.write_i8(b),
+        JvmValue::Short(s) => out.write_i16::<BigEndian>(s),
+        JvmValue::Char(c) => out.write_u16::<BigEndian>(c),
+        JvmValue::Int(i) => out.write_i32::<BigEndian>(i),
+        JvmValue::Long(l) => out.write_i64::<BigEndian>(l),
+        JvmValue::Float(f) => out.write_f32::<BigEndian>(f),
+        JvmValue::Double(d) => out.write_f64::<BigEndian>(d),
+        JvmValue::ObjRef(id) => out.write_u64::<BigEndian>(id as u64),
+    }
+}
@@ -0,0 +1,482 @@
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::constant_pool::CpEntry;
+use crate::opcode;
+
+//////////// PRE-DECODED INSTRUCTIONS
+
+// Bytecode is decoded once, when the method's klass is built. Operands are
+// already read out of the instruction stream, constants are taken from the
+// constant pool, and branch targets are indices into the decoded method
+// rather than byte offsets. Loads, stores and returns are untyped here, as
+// the interpreter treats all of them the same way.
+#[derive(Clone, Debug)]
+pub enum Instruction {
+    Nop,
+    AconstNull,
+    // Numeric LDC, LDC_W and LDC2_W also decode to these
+    Iconst(i32), // ICONST_*, BIPUSH, SIPUSH
+    Lconst(i64),
+    Fconst(f32),
+    Dconst(f64),
+    LdcString(u16), // CP index of the String entry
+    LdcClass(u16),  // CP index of the Class entry
+    Load(u8),
+    Store(u8),
+    Iinc(u8, i32),
+
+    Iaload,
+    Iastore,
+    Baload, // Also used for boolean[]
+    Bastore,
+    Caload,
+    Castore,
+    Saload,
+    Sastore,
+    Aaload,
+    Aastore,
+    Arraylength,
+
+    Pop,
+    Pop2,
+    Dup,
+    DupX1,
+    Swap,
+
+    Iadd,
+    Ladd,
+    Fadd,
+    Dadd,
+    Isub,
+    Lsub,
+    Fsub,
+    Dsub,
+    Imul,
+    Lmul,
+    Fmul,
+    Dmul,
+    Idiv,
+    Ldiv,
+    Fdiv,
+    Ddiv,
+    Irem,
+    Lrem,
+    Frem,
+    Drem,
+    Ineg,
+    Lneg,
+    Fneg,
+    Dneg,
+    Ishl,
+    Lshl,
+    Ishr,
+    Lshr,
+    Iushr,
+    Lushr,
+    Iand,
+    Land,
+    Ior,
+    Lor,
+    Ixor,
+    Lxor,
+
+    I2l,
+    I2f,
+    I2d,
+    L2i,
+    L2f,
+    L2d,
+    F2i,
+    F2l,
+    F2d,
+    D2i,
+    D2l,
+    D2f,
+    I2b,
+    I2c,
+    I2s,
+
+    Lcmp,
+    Fcmpl,
+    Fcmpg,
+    Dcmpl,
+    Dcmpg,
+
+    // Branch targets are instruction indices
+    IfEq(usize),
+    IfNe(usize),
+    IfLt(usize),
+    IfGe(usize),
+    IfGt(usize),
+    IfLe(usize),
+    IfIcmpEq(usize),
+    IfIcmpNe(usize),
+    IfIcmpLt(usize),
+    IfIcmpGe(usize),
+    IfIcmpGt(usize),
+    IfIcmpLe(usize),
+    IfAcmpEq(usize),
+    IfAcmpNe(usize),
+    IfNull(usize),
+    IfNonNull(usize),
+    Goto(usize),
+    TableSwitch(Box<TableSwitch>),
+    LookupSwitch(Box<LookupSwitch>),
+
+    ReturnValue, // IRETURN, LRETURN, FRETURN, DRETURN, ARETURN
+    Return,
+
+    // Constant pool indices, resolved when executed
+    GetStatic(u16),
+    PutStatic(u16),
+    GetField(u16),
+    PutField(u16),
+    InvokeVirtual(u16),
+    InvokeSpecial(u16),
+    InvokeStatic(u16),
+    InvokeInterface(u16),
+    New(u16),
+    NewArray(char), // Descriptor letter of the element type
+    ANewArray(u16), // CP index of the element klass
+
+    MonitorEnter,
+    MonitorExit,
+
+    // BREAKPOINT, IMPDEP1/2 and the JSR / RET family
+    Disallowed(u8),
+    // Well-formed, but not handled by the interpreter yet
+    Unsupported(u8),
+}
+
+#[derive(Clone, Debug)]
+pub struct TableSwitch {
+    pub default: usize,
+    pub low: i32,
+    pub targets: Vec<usize>,
+}
+
+#[derive(Clone, Debug)]
+pub struct LookupSwitch {
+    pub default: usize,
+    pub pairs: Vec<(i32, usize)>,
+}
+
+// Branch targets are first recorded as byte offsets and fixed up to
+// instruction indices once the whole method has been walked
+pub fn fix_target(offsets: &[Option<usize>], target: usize) -> usize {
+    offsets
+        .get(target)
+        .cloned()
+        .flatten()
+        .unwrap_or_else(|| panic!("Branch to {} is not on an instruction boundary", target))
+}
+
+fn branch(code: &[u8], at: usize, operand: usize) -> usize {
+    (at as isize + BigEndian::read_i16(&code[operand..]) as isize) as usize
+}
+
+fn branch_w(code: &[u8], at: usize, operand: usize) -> usize {
+    (at as isize + BigEndian::read_i32(&code[operand..]) as isize) as usize
+}
+
+fn ldc(cp: &[CpEntry], cp_idx: u16) -> Instruction {
+    match cp.get(cp_idx as usize) {
+        Some(CpEntry::Integer(i)) => Instruction::Iconst(*i),
+        Some(CpEntry::Float(f)) => Instruction::Fconst(*f),
+        Some(CpEntry::Long(l)) => Instruction::Lconst(*l),
+        Some(CpEntry::Double(d)) => Instruction::Dconst(*d),
+        Some(CpEntry::String(_)) => Instruction::LdcString(cp_idx),
+        Some(CpEntry::Class(_)) => Instruction::LdcClass(cp_idx),
+        other => panic!("Non-handled entry {:?} found in LDC at CP index {}", other, cp_idx),
+    }
+}
+
+pub fn decode(code: &[u8], cp: &[CpEntry]) -> Vec<Instruction> {
+    decode_mapped(code, cp).0
+}
+
+// As decode, but also hands back the instruction index of each byte offset
+// (and of the end of the code) for fixing up exception table pcs
+pub fn decode_mapped(code: &[u8], cp: &[CpEntry]) -> (Vec<Instruction>, Vec<Option<usize>>) {
+    let mut out = Vec::new();
+    let mut offsets = vec![None; code.len() + 1];
+    let mut current = 0;
+
+    while current < code.len() {
+        let at = current;
+        let op = code[current];
+        offsets[at] = Some(out.len());
+        current += 1;
+
+        let u1 = |i: usize| code[current + i];
+        let u2 = |i: usize| BigEndian::read_u16(&code[current + i..]);
+
+        let ins = match op {
+            opcode::NOP => Instruction::Nop,
+            opcode::ACONST_NULL => Instruction::AconstNull,
+            opcode::ICONST_M1 => Instruction::Iconst(-1),
+            opcode::ICONST_0 => Instruction::Iconst(0),
+            opcode::ICONST_1 => Instruction::Iconst(1),
+            opcode::ICONST_2 => Instruction::Iconst(2),
+            opcode::ICONST_3 => Instruction::Iconst(3),
+            opcode::ICONST_4 => Instruction::Iconst(4),
+            opcode::ICONST_5 => Instruction::Iconst(5),
+            opcode::LCONST_0 => Instruction::Lconst(0),
+            opcode::LCONST_1 => Instruction::Lconst(1),
+            opcode::FCONST_0 => Instruction::Fconst(0.0),
+            opcode::FCONST_1 => Instruction::Fconst(1.0),
+            opcode::FCONST_2 => Instruction::Fconst(2.0),
+            opcode::DCONST_0 => Instruction::Dconst(0.0),
+            opcode::DCONST_1 => Instruction::Dconst(1.0),
+            // Operand byte is signed
+            opcode::BIPUSH => Instruction::Iconst(u1(0) as i8 as i32),
+            // Operand is a signed 16-bit value
+            opcode::SIPUSH => Instruction::Iconst(u2(0) as i16 as i32),
+            opcode::LDC => ldc(cp, u1(0) as u16),
+            opcode::LDC_W | opcode::LDC2_W => ldc(cp, u2(0)),
+
+            opcode::ILOAD | opcode::LLOAD | opcode::FLOAD | opcode::DLOAD | opcode::ALOAD => {
+                Instruction::Load(u1(0))
+            }
+            opcode::ILOAD_0 | opcode::LLOAD_0 | opcode::FLOAD_0 | opcode::DLOAD_0 | opcode::ALOAD_0 => {
+                Instruction::Load(0)
+            }
+            opcode::ILOAD_1 | opcode::LLOAD_1 | opcode::FLOAD_1 | opcode::DLOAD_1 | opcode::ALOAD_1 => {
+                Instruction::Load(1)
+            }
+            opcode::ILOAD_2 | opcode::LLOAD_2 | opcode::FLOAD_2 | opcode::DLOAD_2 | opcode::ALOAD_2 => {
+                Instruction::Load(2)
+            }
+            opcode::ILOAD_3 | opcode::LLOAD_3 | opcode::FLOAD_3 | opcode::DLOAD_3 | opcode::ALOAD_3 => {
+                Instruction::Load(3)
+            }
+            opcode::ISTORE | opcode::LSTORE | opcode::FSTORE | opcode::DSTORE | opcode::ASTORE => {
+                Instruction::Store(u1(0))
+            }
+            opcode::ISTORE_0 | opcode::LSTORE_0 | opcode::FSTORE_0 | opcode::DSTORE_0 | opcode::ASTORE_0 => {
+                Instruction::Store(0)
+            }
+            opcode::ISTORE_1 | opcode::LSTORE_1 | opcode::FSTORE_1 | opcode::DSTORE_1 | opcode::ASTORE_1 => {
+                Instruction::Store(1)
+            }
+            opcode::ISTORE_2 | opcode::LSTORE_2 | opcode::FSTORE_2 | opcode::DSTORE_2 | opcode::ASTORE_2 => {
+                Instruction::Store(2)
+            }
+            opcode::ISTORE_3 | opcode::LSTORE_3 | opcode::FSTORE_3 | opcode::DSTORE_3 | opcode::ASTORE_3 => {
+                Instruction::Store(3)
+            }
+            // Increment is a signed byte
+            opcode::IINC => Instruction::Iinc(u1(0), u1(1) as i8 as i32),
+
+            opcode::AALOAD => Instruction::Aaload,
+            opcode::AASTORE => Instruction::Aastore,
+            opcode::ARRAYLENGTH => Instruction::Arraylength,
+            opcode::IALOAD => Instruction::Iaload,
+            opcode::IASTORE => Instruction::Iastore,
+            opcode::BALOAD => Instruction::Baload,
+            opcode::BASTORE => Instruction::Bastore,
+            opcode::CALOAD => Instruction::Caload,
+            opcode::CASTORE => Instruction::Castore,
+            opcode::SALOAD => Instruction::Saload,
+            opcode::SASTORE => Instruction::Sastore,
+
+            opcode::POP => Instruction::Pop,
+            opcode::POP2 => Instruction::Pop2,
+            opcode::DUP => Instruction::Dup,
+            opcode::DUP_X1 => Instruction::DupX1,
+            opcode::SWAP => Instruction::Swap,
+
+            opcode::IADD => Instruction::Iadd,
+            opcode::LADD => Instruction::Ladd,
+            opcode::FADD => Instruction::Fadd,
+            opcode::DADD => Instruction::Dadd,
+            opcode::ISUB => Instruction::Isub,
+            opcode::LSUB => Instruction::Lsub,
+            opcode::FSUB => Instruction::Fsub,
+            opcode::DSUB => Instruction::Dsub,
+            opcode::IMUL => Instruction::Imul,
+            opcode::LMUL => Instruction::Lmul,
+            opcode::FMUL => Instruction::Fmul,
+            opcode::DMUL => Instruction::Dmul,
+            opcode::IDIV => Instruction::Idiv,
+            opcode::LDIV => Instruction::Ldiv,
+            opcode::FDIV => Instruction::Fdiv,
+            opcode::DDIV => Instruction::Ddiv,
+            opcode::IREM => Instruction::Irem,
+            opcode::LREM => Instruction::Lrem,
+            opcode::FREM => Instruction::Frem,
+            opcode::DREM => Instruction::Drem,
+            opcode::INEG => Instruction::Ineg,
+            opcode::LNEG => Instruction::Lneg,
+            opcode::FNEG => Instruction::Fneg,
+            opcode::DNEG => Instruction::Dneg,
+            opcode::ISHL => Instruction::Ishl,
+            opcode::LSHL => Instruction::Lshl,
+            opcode::ISHR => Instruction::Ishr,
+            opcode::LSHR => Instruction::Lshr,
+            opcode::IUSHR => Instruction::Iushr,
+            opcode::LUSHR => Instruction::Lushr,
+            opcode::IAND => Instruction::Iand,
+            opcode::LAND => Instruction::Land,
+            opcode::IOR => Instruction::Ior,
+            opcode::LOR => Instruction::Lor,
+            opcode::IXOR => Instruction::Ixor,
+            opcode::LXOR => Instruction::Lxor,
+
+            opcode::I2L => Instruction::I2l,
+            opcode::I2F => Instruction::I2f,
+            opcode::I2D => Instruction::I2d,
+            opcode::L2I => Instruction::L2i,
+            opcode::L2F => Instruction::L2f,
+            opcode::L2D => Instruction::L2d,
+            opcode::F2I => Instruction::F2i,
+            opcode::F2L => Instruction::F2l,
+            opcode::F2D => Instruction::F2d,
+            opcode::D2I => Instruction::D2i,
+            opcode::D2L => Instruction::D2l,
+            opcode::D2F => Instruction::D2f,
+            opcode::I2B => Instruction::I2b,
+            opcode::I2C => Instruction::I2c,
+            opcode::I2S => Instruction::I2s,
+
+            opcode::LCMP => Instruction::Lcmp,
+            opcode::FCMPL => Instruction::Fcmpl,
+            opcode::FCMPG => Instruction::Fcmpg,
+            opcode::DCMPL => Instruction::Dcmpl,
+            opcode::DCMPG => Instruction::Dcmpg,
+
+            opcode::IFEQ => Instruction::IfEq(branch(code, at, current)),
+            opcode::IFNE => Instruction::IfNe(branch(code, at, current)),
+            opcode::IFLT => Instruction::IfLt(branch(code, at, current)),
+            opcode::IFGE => Instruction::IfGe(branch(code, at, current)),
+            opcode::IFGT => Instruction::IfGt(branch(code, at, current)),
+            opcode::IFLE => Instruction::IfLe(branch(code, at, current)),
+            opcode::IF_ICMPEQ => Instruction::IfIcmpEq(branch(code, at, current)),
+            opcode::IF_ICMPNE => Instruction::IfIcmpNe(branch(code, at, current)),
+            opcode::IF_ICMPLT => Instruction::IfIcmpLt(branch(code, at, current)),
+            opcode::IF_ICMPGE => Instruction::IfIcmpGe(branch(code, at, current)),
+            opcode::IF_ICMPGT => Instruction::IfIcmpGt(branch(code, at, current)),
+            opcode::IF_ICMPLE => Instruction::IfIcmpLe(branch(code, at, current)),
+            opcode::IF_ACMPEQ => Instruction::IfAcmpEq(branch(code, at, current)),
+            opcode::IF_ACMPNE => Instruction::IfAcmpNe(branch(code, at, current)),
+            opcode::IFNULL => Instruction::IfNull(branch(code, at, current)),
+            opcode::IFNONNULL => Instruction::IfNonNull(branch(code, at, current)),
+            opcode::GOTO => Instruction::Goto(branch(code, at, current)),
+            opcode::GOTO_W => Instruction::Goto(branch_w(code, at, current)),
+
+            opcode::TABLESWITCH | opcode::LOOKUPSWITCH => {
+                // Operands start on the next 4-byte boundary of the method
+                current = (current + 3) & !3;
+                let s4 = |i: usize| BigEndian::read_i32(&code[current + 4 * i..]);
+                let default = (at as isize + s4(0) as isize) as usize;
+                if op == opcode::TABLESWITCH {
+                    let low = s4(1);
+                    let count = (s4(2) - low + 1) as usize;
+                    let targets = (0..count)
+                        .map(|i| (at as isize + s4(3 + i) as isize) as usize)
+                        .collect();
+                    current += 4 * (3 + count);
+                    Instruction::TableSwitch(Box::new(TableSwitch { default, low, targets }))
+                } else {
+                    let count = s4(1) as usize;
+                    let pairs = (0..count)
+                        .map(|i| (s4(2 + 2 * i), (at as isize + s4(3 + 2 * i) as isize) as usize))
+                        .collect();
+                    current += 4 * (2 + 2 * count);
+                    Instruction::LookupSwitch(Box::new(LookupSwitch { default, pairs }))
+                }
+            }
+
+            opcode::IRETURN | opcode::LRETURN | opcode::FRETURN | opcode::DRETURN | opcode::ARETURN => {
+                Instruction::ReturnValue
+            }
+            opcode::RETURN => Instruction::Return,
+
+            opcode::GETSTATIC => Instruction::GetStatic(u2(0)),
+            opcode::PUTSTATIC => Instruction::PutStatic(u2(0)),
+            opcode::GETFIELD => Instruction::GetField(u2(0)),
+            opcode::PUTFIELD => Instruction::PutField(u2(0)),
+            opcode::INVOKEVIRTUAL => Instruction::InvokeVirtual(u2(0)),
+            opcode::INVOKESPECIAL => Instruction::InvokeSpecial(u2(0)),
+            opcode::INVOKESTATIC => Instruction::InvokeStatic(u2(0)),
+            // The count and zero bytes after the index are redundant
+            opcode::INVOKEINTERFACE => Instruction::InvokeInterface(u2(0)),
+            opcode::NEW => Instruction::New(u2(0)),
+            opcode::NEWARRAY => match u1(0) {
+                4 => Instruction::NewArray('Z'),
+                5 => Instruction::NewArray('C'),
+                6 => Instruction::NewArray('F'),
+                7 => Instruction::NewArray('D'),
+                8 => Instruction::NewArray('B'),
+                9 => Instruction::NewArray('S'),
+                10 => Instruction::NewArray('I'),
+                11 => Instruction::NewArray('J'),
+                t => panic!("Illegal primitive array type {} at {}", t, at),
+            },
+            opcode::ANEWARRAY => Instruction::ANewArray(u2(0)),
+
+            opcode::MONITORENTER => Instruction::MonitorEnter,
+            opcode::MONITOREXIT => Instruction::MonitorExit,
+
+            opcode::BREAKPOINT
+            | opcode::IMPDEP1
+            | opcode::IMPDEP2
+            | opcode::JSR
+            | opcode::JSR_W
+            | opcode::RET => Instruction::Disallowed(op),
+
+            opcode::WIDE => {
+                // Widened IINC carries a 16-bit index and increment
+                let len = if u1(0) == opcode::IINC { 5 } else { 3 };
+                current += len;
+                Instruction::Unsupported(op)
+            }
+            op if op <= opcode::JSR_W => Instruction::Unsupported(op),
+            _ => panic!("Illegal opcode byte: {} encountered at position {}", op, at),
+        };
+
+        // Switches and WIDE have already moved past their operands
+        match op {
+            opcode::TABLESWITCH | opcode::LOOKUPSWITCH | opcode::WIDE => (),
+            _ => current += opcode::num_params(op),
+        }
+        out.push(ins);
+    }
+
+    for ins in out.iter_mut() {
+        match ins {
+            Instruction::IfEq(t)
+            | Instruction::IfNe(t)
+            | Instruction::IfLt(t)
+            | Instruction::IfGe(t)
+            | Instruction::IfGt(t)
+            | Instruction::IfLe(t)
+            | Instruction::IfIcmpEq(t)
+            | Instruction::IfIcmpNe(t)
+            | Instruction::IfIcmpLt(t)
+            | Instruction::IfIcmpGe(t)
+            | Instruction::IfIcmpGt(t)
+            | Instruction::IfIcmpLe(t)
+            | Instruction::IfAcmpEq(t)
+            | Instruction::IfAcmpNe(t)
+            | Instruction::IfNull(t)
+            | Instruction::IfNonNull(t)
+            | Instruction::Goto(t) => *t = fix_target(&offsets, *t),
+            Instruction::TableSwitch(ts) => {
+                ts.default = fix_target(&offsets, ts.default);
+                for t in ts.targets.iter_mut() {
+                    *t = fix_target(&offsets, *t);
+                }
+            }
+            Instruction::LookupSwitch(ls) => {
+                ls.default = fix_target(&offsets, ls.default);
+                for (_, t) in ls.pairs.iter_mut() {
+                    *t = fix_target(&offsets, *t);
+                }
+            }
+            _ => (),
+        }
+    }
+    offsets[code.len()] = Some(out.len());
+    (out, offsets)
+}
@@ -15,11 +15,47 @@ fn ordering(o: Ordering) -> JvmValue {
     })
 }
 
+// Floating-point to integer conversions (JVMS 2.8.3): NaN becomes zero and
+// out-of-range values saturate. Spelled out rather than relying on `as`.
+fn f2i(v: f64) -> i32 {
+    if v.is_nan() {
+        0
+    } else if v >= i32::MAX as f64 {
+        i32::MAX
+    } else if v <= i32::MIN as f64 {
+        i32::MIN
+    } else {
+        v.trunc() as i32
+    }
+}
+
+fn f2l(v: f64) -> i64 {
+    if v.is_nan() {
+        0
+    } else if v >= i64::MAX as f64 {
+        i64::MAX
+    } else if v <= i64::MIN as f64 {
+        i64::MIN
+    } else {
+        v.trunc() as i64
+    }
+}
+
 impl InterpEvalStack {
     pub fn of() -> InterpEvalStack {
         InterpEvalStack { stack: Vec::new() }
     }
 
+    pub fn values(&self) -> &[JvmValue] {
+        &self.stack
+    }
+
+    pub fn clear(&mut self) -> () {
+        self.stack.clear();
+    }
+
+    // Callers must already have widened boolean, byte, short and char values
+    // with to_computational(); this is on the hot path, so it doesn't check
     pub fn push(&mut self, val: JvmValue) {
         let s = &mut self.stack;
         s.push(val);
@@ -47,13 +83,13 @@ impl InterpEvalStack {
 
     pub fn i2b(&mut self) {
         let i1 = self.pop().as_int().expect("Unexpected, non-integer value encountered");
-        self.push(JvmValue::Byte(i1 as i8));
+        self.push(JvmValue::Int(i1 as i8 as i32));
     }
 
     pub fn i2c(&mut self) {
         let i1 = self.pop().as_int().expect("Unexpected, non-integer value encountered");
-        let c = std::char::from_u32(i1 as u32).unwrap();
-        self.push(JvmValue::Char(c));
+        // Zero-extends, so surrogate code units are just more ints
+        self.push(JvmValue::Int(i1 as u16 as i32));
     }
 
     pub fn i2d(&mut self) {
@@ -73,42 +109,54 @@ impl InterpEvalStack {
 
     pub fn i2s(&mut self) {
         let i1 = self.pop().as_int().expect("Unexpected, non-integer value encountered");
-        self.push(JvmValue::Short(i1 as i16));
+        self.push(JvmValue::Int(i1 as i16 as i32));
     }
 
     pub fn iadd(&mut self) {
         // For a runtime checking interpreter - type checks would go here...
         let i1 = self.pop().as_int().expect("Unexpected, non-integer value encountered");
         let i2 = self.pop().as_int().expect("Unexpected, non-integer value encountered");
-        self.push(JvmValue::Int(i1 + i2));
+        self.push(JvmValue::Int(i1.wrapping_add(i2)));
     }
 
     pub fn isub(&mut self) {
         // For a runtime checking interpreter - type checks would go here...
         let i1 = self.pop().as_int().expect("Unexpected, non-integer value encountered");
         let i2 = self.pop().as_int().expect("Unexpected, non-integer value encountered");
-        self.push(JvmValue::Int(i1 - i2));
+        self.push(JvmValue::Int(i2.wrapping_sub(i1)));
     }
 
     pub fn imul(&mut self) {
         // For a runtime checking interpreter - type checks would go here...
         let i1 = self.pop().as_int().expect("Unexpected, non-integer value encountered");
         let i2 = self.pop().as_int().expect("Unexpected, non-integer value encountered");
-        self.push(JvmValue::Int(i1 * i2));
+        self.push(JvmValue::Int(i1.wrapping_mul(i2)));
     }
 
-    pub fn irem(&mut self) {
+    // The divisions return false for a zero divisor, having popped both
+    // operands, for the interpreter to throw ArithmeticException
+    pub fn irem(&mut self) -> bool {
         // For a runtime checking interpreter - type checks would go here...
         let i1 = self.pop().as_int().expect("Unexpected, non-integer value encountered");
         let i2 = self.pop().as_int().expect("Unexpected, non-integer value encountered");
-        self.push(JvmValue::Int(i2 % i1));
+        if i1 == 0 {
+            return false;
+        }
+        // Integer.MIN_VALUE % -1 is 0 in Java, wrapping_rem gives the same
+        self.push(JvmValue::Int(i2.wrapping_rem(i1)));
+        true
     }
 
-    pub fn idiv(&mut self) {
+    pub fn idiv(&mut self) -> bool {
         // For a runtime checking interpreter - type checks would go here...
         let i1 = self.pop().as_int().expect("Unexpected, non-integer value encountered");
         let i2 = self.pop().as_int().expect("Unexpected, non-integer value encountered");
-        self.push(JvmValue::Int(i2 / i1));
+        if i1 == 0 {
+            return false;
+        }
+        // Integer.MIN_VALUE / -1 overflows back to Integer.MIN_VALUE
+        self.push(JvmValue::Int(i2.wrapping_div(i1)));
+        true
     }
 
     pub fn iand(&mut self) {
@@ -119,7 +167,7 @@ impl InterpEvalStack {
 
     pub fn ineg(&mut self) {
         let i1 = self.pop().as_int().expect("Unexpected, non-integer value encountered");
-        self.push(JvmValue::Int(-i1));
+        self.push(JvmValue::Int(i1.wrapping_neg()));
     }
 
     pub fn ior(&mut self) {
@@ -132,25 +180,25 @@ impl InterpEvalStack {
         let i1 = self.pop().as_int().expect("Unexpected, non-integer value encountered");
         let i2 = self.pop().as_int().expect("Unexpected, non-integer value encountered");
         self.push(JvmValue::Int(i1 ^ i2));
-
     }
 
+    // Shift distance is on top of the stack, and only the low 5 bits are used
     pub fn ishl(&mut self) {
+        let s = self.pop().as_int().expect("Unexpected, non-integer value encountered");
         let i1 = self.pop().as_int().expect("Unexpected, non-integer value encountered");
-        let i2 = self.pop().as_int().expect("Unexpected, non-integer value encountered");
-        self.push(JvmValue::Int(i1 << i2));
+        self.push(JvmValue::Int(i1.wrapping_shl(s as u32 & 0x1f)));
     }
 
     pub fn ishr(&mut self) {
+        let s = self.pop().as_int().expect("Unexpected, non-integer value encountered");
         let i1 = self.pop().as_int().expect("Unexpected, non-integer value encountered");
-        let i2 = self.pop().as_int().expect("Unexpected, non-integer value encountered");
-        self.push(JvmValue::Int(i1 >> i2));
+        self.push(JvmValue::Int(i1.wrapping_shr(s as u32 & 0x1f)));
     }
 
     pub fn iushr(&mut self) {
+        let s = self.pop().as_int().expect("Unexpected, non-integer value encountered");
         let i1 = self.pop().as_int().expect("Unexpected, non-integer value encountered");
-        let i2 = self.pop().as_int().expect("Unexpected, non-integer value encountered");
-        self.push(JvmValue::Int((i1 as u32 >> i2 as u32) as i32));
+        self.push(JvmValue::Int((i1 as u32).wrapping_shr(s as u32 & 0x1f) as i32));
     }
 
     //
@@ -180,40 +228,49 @@ impl InterpEvalStack {
         // For a runtime checking interpreter - type checks would go here...
         let i1 = self.pop().as_long().expect("Unexpected, non-long value encountered");
         let i2 = self.pop().as_long().expect("Unexpected, non-long value encountered");
-        self.push(JvmValue::Long(i1 + i2));
+        self.push(JvmValue::Long(i1.wrapping_add(i2)));
     }
 
     pub fn lsub(&mut self) {
         // For a runtime checking interpreter - type checks would go here...
         let i1 = self.pop().as_long().expect("Unexpected, non-long value encountered");
         let i2 = self.pop().as_long().expect("Unexpected, non-long value encountered");
-        self.push(JvmValue::Long(i1 - i2));
+        self.push(JvmValue::Long(i2.wrapping_sub(i1)));
     }
 
-    pub fn lrem(&mut self) {
+    pub fn lrem(&mut self) -> bool {
         // For a runtime checking interpreter - type checks would go here...
         let i1 = self.pop().as_long().expect("Unexpected, non-long value encountered");
         let i2 = self.pop().as_long().expect("Unexpected, non-long value encountered");
-        self.push(JvmValue::Long(i2 % i1));
+        if i1 == 0 {
+            return false;
+        }
+        self.push(JvmValue::Long(i2.wrapping_rem(i1)));
+        true
     }
 
-    pub fn ldiv(&mut self) {
+    pub fn ldiv(&mut self) -> bool {
         // For a runtime checking interpreter - type checks would go here...
         let i1 = self.pop().as_long().expect("Unexpected, non-long value encountered");
         let i2 = self.pop().as_long().expect("Unexpected, non-long value encountered");
-        self.push(JvmValue::Long(i2 / i1));
+        if i1 == 0 {
+            return false;
+        }
+        // Long.MIN_VALUE / -1 overflows back to Long.MIN_VALUE
+        self.push(JvmValue::Long(i2.wrapping_div(i1)));
+        true
     }
 
     pub fn lmul(&mut self) {
         // For a runtime checking interpreter - type checks would go here...
         let i1 = self.pop().as_long().expect("Unexpected, non-long value encountered");
         let i2 = self.pop().as_long().expect("Unexpected, non-long value encountered");
-        self.push(JvmValue::Long(i2 * i1));
+        self.push(JvmValue::Long(i2.wrapping_mul(i1)));
     }
 
     pub fn lneg(&mut self) {
         let i1 = self.pop().as_long().expect("Unexpected, non-long value encountered");
-        self.push(JvmValue::Long(-i1));
+        self.push(JvmValue::Long(i1.wrapping_neg()));
     }
 
     pub fn land(&mut self) {
@@ -234,22 +291,23 @@ impl InterpEvalStack {
         self.push(JvmValue::Long(i1 ^ i2));
     }
 
+    // Shift distance is an int on top of the stack, and only the low 6 bits are used
     pub fn lshl(&mut self) {
+        let s = self.pop().as_int().expect("Unexpected, non-integer value encountered");
         let i1 = self.pop().as_long().expect("Unexpected, non-long value encountered");
-        let i2 = self.pop().as_long().expect("Unexpected, non-long value encountered");
-        self.push(JvmValue::Long(i1 << i2));
+        self.push(JvmValue::Long(i1.wrapping_shl(s as u32 & 0x3f)));
     }
 
     pub fn lshr(&mut self) {
+        let s = self.pop().as_int().expect("Unexpected, non-integer value encountered");
         let i1 = self.pop().as_long().expect("Unexpected, non-long value encountered");
-        let i2 = self.pop().as_long().expect("Unexpected, non-long value encountered");
-        self.push(JvmValue::Long(i1 >> i2));
+        self.push(JvmValue::Long(i1.wrapping_shr(s as u32 & 0x3f)));
     }
 
     pub fn lushr(&mut self) {
+        let s = self.pop().as_int().expect("Unexpected, non-integer value encountered");
         let i1 = self.pop().as_long().expect("Unexpected, non-long value encountered");
-        let i2 = self.pop().as_long().expect("Unexpected, non-long value encountered");
-        self.push(JvmValue::Long((i1 as u64 >> i2 as u64) as i64));
+        self.push(JvmValue::Long((i1 as u64).wrapping_shr(s as u32 & 0x3f) as i64));
     }
 
     pub fn lcmp(&mut self) {
@@ -270,12 +328,12 @@ impl InterpEvalStack {
 
     pub fn f2i(&mut self) {
         let i1 = self.pop().as_float().expect("Unexpected, non-float value encountered");
-        self.push(JvmValue::Int(i1 as i32));
+        self.push(JvmValue::Int(f2i(i1 as f64)));
     }
 
     pub fn f2l(&mut self) {
         let i1 = self.pop().as_float().expect("Unexpected, non-float value encountered");
-        self.push(JvmValue::Long(i1 as i64));
+        self.push(JvmValue::Long(f2l(i1 as f64)));
     }
 
     pub fn fadd(&mut self) {
@@ -289,7 +347,7 @@ impl InterpEvalStack {
         // For a runtime checking interpreter - type checks would go here...
         let i1 = self.pop().as_float().expect("Unexpected, non-float value encountered");
         let i2 = self.pop().as_float().expect("Unexpected, non-float value encountered");
-        self.push(JvmValue::Float(i1 - i2));
+        self.push(JvmValue::Float(i2 - i1));
     }
 
     pub fn fmul(&mut self) {
@@ -303,7 +361,8 @@ impl InterpEvalStack {
         // For a runtime checking interpreter - type checks would go here...
         let i1 = self.pop().as_float().expect("Unexpected, non-float value encountered");
         let i2 = self.pop().as_float().expect("Unexpected, non-float value encountered");
-        self.push(JvmValue::Float(i2.rem_euclid(i1)));
+        // Java's % on floats truncates (fmod), it is not IEEE 754 remainder
+        self.push(JvmValue::Float(i2 % i1));
     }
 
     pub fn fdiv(&mut self) {
@@ -326,7 +385,7 @@ impl InterpEvalStack {
         let v2 = self.pop().as_float().expect("Non-float seen on stack during FCMPG");
         let v1 = self.pop().as_float().expect("Non-float seen on stack during FCMPG");
         self.push(f32::partial_cmp(&v1, &v2).map(ordering).unwrap_or(JvmValue::Int(1)));
-    } 
+    }
 
     pub fn fcmpl(&mut self) {
         let v2 = self.pop().as_float().expect("Non-float seen on stack during FCMPL");
@@ -338,6 +397,21 @@ impl InterpEvalStack {
     // D opcodes - double
     //
 
+    pub fn d2f(&mut self) {
+        let d = self.pop().as_double().expect("Unexpected, non-double value encountered");
+        self.push(JvmValue::Float(d as f32));
+    }
+
+    pub fn d2i(&mut self) {
+        let d = self.pop().as_double().expect("Unexpected, non-double value encountered");
+        self.push(JvmValue::Int(f2i(d)));
+    }
+
+    pub fn d2l(&mut self) {
+        let d = self.pop().as_double().expect("Unexpected, non-double value encountered");
+        self.push(JvmValue::Long(f2l(d)));
+    }
+
     pub fn dadd(&mut self) {
         // For a runtime checking interpreter - type checks would go here...
         let i1 = self.pop().as_double().expect("Unexpected, non-double value encountered");
@@ -349,7 +423,7 @@ impl InterpEvalStack {
         // For a runtime checking interpreter - type checks would go here...
         let i1 = self.pop().as_double().expect("Unexpected, non-double value encountered");
         let i2 = self.pop().as_double().expect("Unexpected, non-double value encountered");
-        self.push(JvmValue::Double(i1 - i2));
+        self.push(JvmValue::Double(i2 - i1));
     }
 
     pub fn dmul(&mut self) {
@@ -363,7 +437,8 @@ impl InterpEvalStack {
         // For a runtime checking interpreter - type checks would go here...
         let i1 = self.pop().as_double().expect("Unexpected, non-double value encountered");
         let i2 = self.pop().as_double().expect("Unexpected, non-double value encountered");
-        self.push(JvmValue::Double(i2.rem_euclid(i1)));
+        // Java's % on doubles truncates (fmod), it is not IEEE 754 remainder
+        self.push(JvmValue::Double(i2 % i1));
     }
 
     pub fn ddiv(&mut self) {
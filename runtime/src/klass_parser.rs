@@ -9,6 +9,7 @@ use crate::constant_pool::*;
 use crate::OtField;
 use crate::OtKlass;
 use crate::OtMethod;
+use crate::otmethod::ExceptionHandler;
 
 pub struct OtKlassParser {
     clz_read: Vec<u8>,
@@ -56,6 +57,20 @@ impl OtKlassParser {
             &self.methods,
             &self.fields,
         )
+        .with_interfaces(self.interface_names())
+    }
+
+    fn interface_names(&self) -> Vec<String> {
+        self.interfaces
+            .iter()
+            .map(|&idx| match &self.cp_entries[idx as usize] {
+                CpEntry::Class(ClassRef(icl)) => match &self.cp_entries[*icl as usize] {
+                    CpEntry::Utf8(s) => s.clone(),
+                    _ => panic!("Interface index {} does not point at utf8 string in constant pool", icl),
+                },
+                _ => panic!("Interface index {} does not point at class element in constant pool", idx),
+            })
+            .collect()
     }
 
     fn klass_name(&self) -> &String {
@@ -479,7 +494,12 @@ impl OtKlassParser {
             "Code" => {
                 //    u2 max_stack;
                 //    u2 max_locals;
-                //    FIXME: Currently Don't care about stack depth or locals
+                let b1 = self.clz_read[self.current];
+                let b2 = self.clz_read[self.current + 1];
+                method.set_max_stack(((b1 as u16) << 8) + b2 as u16);
+                let b1 = self.clz_read[self.current + 2];
+                let b2 = self.clz_read[self.current + 3];
+                method.set_max_locals(((b1 as u16) << 8) + b2 as u16);
                 self.current += 4;
                 // //    u4 code_length;
                 // //    u1 code[code_length];
@@ -503,6 +523,28 @@ impl OtKlassParser {
                     }
                     Err(e) => panic!("error parsing file: {:?}", e),
                 };
+
+                //    u2 exception_table_length;
+                //    {   u2 start_pc;
+                //        u2 end_pc;
+                //        u2 handler_pc;
+                //        u2 catch_type;
+                //    } exception_table[exception_table_length];
+                let table_len = BigEndian::read_u16(&self.clz_read[self.current..]);
+                self.current += 2;
+                let mut handlers = vec![];
+                for _ in 0..table_len {
+                    let entry = &self.clz_read[self.current..self.current + 8];
+                    handlers.push(ExceptionHandler {
+                        start: BigEndian::read_u16(&entry[0..]) as usize,
+                        end: BigEndian::read_u16(&entry[2..]) as usize,
+                        handler: BigEndian::read_u16(&entry[4..]) as usize,
+                        catch_type: BigEndian::read_u16(&entry[6..]),
+                    });
+                    self.current += 8;
+                }
+                method.set_exception_handlers(handlers);
+                //    FIXME: Code attributes (line numbers etc) are skipped
             }
             "Signature" => {
                 dbg!("Encountered signature in bytecode - skipping");
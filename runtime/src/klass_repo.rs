@@ -1,15 +1,18 @@
 use std::fmt;
-use std::path::Path;
-use std::cell::RefCell;
+use std::path::{Path, PathBuf};
 use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 
 use regex::Regex;
 
 use crate::JvmValue;
 use crate::InterpLocalVars;
+use crate::object::OtObj;
 use crate::otfield::OtField;
-use crate::otmethod::OtMethod;
-use crate::otklass::OtKlass;
+use crate::otmethod::{NativeMethod, OtMethod};
+use crate::otklass::{resolved_klass, CpResolved, OtKlass};
+use crate::vm::Vm;
+use crate::constant_pool::{CpEntry, ACC_NATIVE, ACC_PUBLIC, ACC_STATIC};
 
 use ocelotter_util::file_to_bytes;
 use ocelotter_util::ZipFiles;
@@ -19,13 +22,52 @@ use ocelotter_util::ZipFiles;
 #[derive(Debug, Clone)]
 pub enum KlassLoadingStatus {
     Mentioned {},
-    Loaded { klass: OtKlass },
-    Live { klass: OtKlass }
+    Loaded { klass: Arc<OtKlass> },
+    Live { klass: Arc<OtKlass> }
 }
 
-#[derive(Debug)]
+// What a java/lang/Class object stands for
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mirrored {
+    // By klass id
+    Klass(usize),
+    // By descriptor, e.g. [I or [Ljava/lang/String;
+    Array(String),
+}
+
+// Why code couldn't be bound to a native method, by its fully qualified name
+#[derive(Debug, Clone, PartialEq)]
+pub enum NativeError {
+    // Code is already registered for it
+    Duplicate(String),
+    // Its klass has no such method
+    NoSuchMethod(String),
+    // The method has a body of its own
+    NotNative(String),
+}
+
+impl fmt::Display for NativeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NativeError::Duplicate(fq_name) => write!(f, "Native method {} registered twice", fq_name),
+            NativeError::NoSuchMethod(fq_name) => write!(f, "No native method {} found", fq_name),
+            NativeError::NotNative(fq_name) => write!(f, "Method {} is not native", fq_name),
+        }
+    }
+}
+
+// Cloning a repo gives another handle on the same klasses, as each clone
+// of the VM owning it does
+#[derive(Clone)]
 pub struct SharedKlassRepo {
-    klass_lookup: HashMap<String, RefCell<KlassLoadingStatus>>,
+    klass_lookup: Arc<RwLock<HashMap<String, KlassLoadingStatus>>>,
+    // Loaded klasses in id order, starting from id 1
+    klasses: Arc<RwLock<Vec<Arc<OtKlass>>>>,
+    // Code registered for native methods, by fully qualified name and
+    // descriptor, whether or not their klass is loaded yet
+    natives: Arc<RwLock<HashMap<String, NativeMethod>>>,
+    // Directories and jars that load_klass() looks in, in order
+    classpath: Arc<RwLock<Vec<PathBuf>>>,
 }
 
 impl SharedKlassRepo {
@@ -57,16 +99,29 @@ impl SharedKlassRepo {
 
     pub fn of() -> SharedKlassRepo {
         SharedKlassRepo {
-            klass_lookup: HashMap::new(),
+            klass_lookup: Arc::new(RwLock::new(HashMap::new())),
+            klasses: Arc::new(RwLock::new(Vec::new())),
+            natives: Arc::new(RwLock::new(HashMap::new())),
+            classpath: Arc::new(RwLock::new(Vec::new())),
         }
     }
 
-    pub fn lookup_klass(&self, klass_name: &String) -> OtKlass {
+    // Adds a directory or jar for load_klass() to look in
+    pub fn add_classpath(&self, path: &Path) -> () {
+        self.classpath.write().unwrap().push(path.to_path_buf());
+    }
+
+    // Objects held by the klasses, i.e. their statics and mirrors
+    pub fn static_refs(&self) -> Vec<usize> {
+        self.klasses.read().unwrap().iter().flat_map(|k| k.refs()).collect()
+    }
+
+    pub fn lookup_klass(&self, klass_name: &String) -> Arc<OtKlass> {
         // let s = format!("{}", self);
         // dbg!(s);
 
-        match self.klass_lookup.get(klass_name) {
-            Some(cell) => match &*(cell.borrow()) {
+        match self.klass_lookup.read().unwrap().get(klass_name) {
+            Some(status) => match status {
                 KlassLoadingStatus::Mentioned {} => panic!("Klass {} is not loaded yet", klass_name),
                 KlassLoadingStatus::Loaded { klass : k } => k.clone(),
                 KlassLoadingStatus::Live { klass : k } => k.clone()
@@ -75,70 +130,171 @@ impl SharedKlassRepo {
         }
     }
 
-    pub fn add_klass(&mut self, k: &OtKlass) -> () {
-        // First check to see if we already have this class and which state it's in
-        let klass_name = k.get_name();
-        let upgrade = match self.klass_lookup.get(&klass_name) {
-            Some(value) => match &*(value.borrow()) {
-                KlassLoadingStatus::Mentioned {} => true,
-                KlassLoadingStatus::Loaded { klass : _ } => false, 
-                KlassLoadingStatus::Live { klass : _ } => false 
-            },
-            None => {
-                let k2: OtKlass = (*k).to_owned();
-                // Scan for every other class the newcomer mentions
-                let klasses_mentioned = k2.get_mentioned_klasses();
-
-                self.klass_lookup.insert(k.get_name().clone(), RefCell::new(KlassLoadingStatus::Loaded{ klass: k2 }));
-                // Mention everything this class refers to
-                self.mention(klasses_mentioned);
-                false
+    // As lookup_klass(), but None for klasses that aren't loaded
+    pub fn find_klass(&self, klass_name: &str) -> Option<Arc<OtKlass>> {
+        match self.klass_lookup.read().unwrap().get(klass_name) {
+            Some(KlassLoadingStatus::Loaded { klass: k }) | Some(KlassLoadingStatus::Live { klass: k }) => Some(k.clone()),
+            _ => None,
+        }
+    }
+
+    // As find_klass(), but reads in klasses that aren't loaded yet from the
+    // classpath, their superclasses first. None if any of them isn't there.
+    pub fn load_klass(&self, klass_name: &str) -> Option<Arc<OtKlass>> {
+        if let Some(k) = self.find_klass(klass_name) {
+            return Some(k);
+        }
+        let file_name = klass_name.to_owned() + ".class";
+        let bytes = self.classpath.read().unwrap().iter().find_map(|path| {
+            if path.is_dir() {
+                file_to_bytes(&path.join(&file_name)).ok()
+            } else {
+                ZipFiles::new(&path.to_string_lossy())
+                    .filter_map(Result::ok)
+                    .find(|(name, _)| *name == file_name)
+                    .map(|(_, bytes)| bytes)
             }
-        };
-        if upgrade {
-            let k2 = (*k).to_owned();
-            // Load k into map
-            self.klass_lookup.get(&klass_name).unwrap().replace(KlassLoadingStatus::Loaded{ klass: k2 });
+        })?;
+        let mut parser = crate::klass_parser::OtKlassParser::of(bytes, file_name);
+        parser.parse();
+        let k = parser.klass();
+        if k.get_name() != klass_name {
+            return None;
         }
+        self.load_klass(&k.get_super_name())?;
+        self.add_klass(&k);
+        self.find_klass(klass_name)
+    }
+
+    // Ids are handed out as klasses are added, 0 is kept for null
+    pub fn klass_by_id(&self, id: usize) -> Arc<OtKlass> {
+        match id.checked_sub(1).and_then(|i| self.klasses.read().unwrap().get(i).cloned()) {
+            Some(k) => k,
+            None => panic!("No klass with ID {} found in repo", id),
+        }
+    }
+
+    // Every loaded klass, in id order
+    pub fn klasses(&self) -> Vec<Arc<OtKlass>> {
+        self.klasses.read().unwrap().clone()
     }
 
-    fn mention(&mut self, mentions: Vec<String>) -> () {
-        // Loop over mentions
-        let mut i = 0;
-        while i < mentions.len() {
-            // Check to see if we have this class already
-            let klass_name = mentions.get(i).unwrap();
-            match self.klass_lookup.get(klass_name) {
-                // If not, add a mention
+    pub fn add_klass(&self, k: &OtKlass) -> () {
+        // Natives registered ahead of loading that the klass has no native
+        // method for, dropped once the locks are let go
+        let mut rejected = Vec::new();
+        {
+            // Taken before the lookup, as in register_native()
+            let natives = self.natives.read().unwrap();
+            let mut lookup = self.klass_lookup.write().unwrap();
+            // First check to see if we already have this class and which state it's in
+            let klass_name = k.get_name();
+            let is_new = match lookup.get(&klass_name) {
+                Some(KlassLoadingStatus::Mentioned {}) => true,
+                Some(_) => false,
                 None => {
-                    self.klass_lookup.insert(klass_name.clone(), RefCell::new(KlassLoadingStatus::Mentioned{ }));
-                },
-                Some(value) => (),
+                    // Mention everything this class refers to
+                    SharedKlassRepo::mention(&mut lookup, k.get_mentioned_klasses());
+                    true
+                }
+            };
+            if is_new {
+                let k2 = Arc::new((*k).to_owned());
+                let mut klasses = self.klasses.write().unwrap();
+                klasses.push(k2.clone());
+                k2.set_id(klasses.len());
+                // Bind whatever natives were registered ahead of loading, which
+                // only now can be checked against the klass
+                let mut live = false;
+                for (fq_name, n_code) in natives.iter() {
+                    if fq_name.split('.').next() == Some(klass_name.as_str()) {
+                        match SharedKlassRepo::native_method(&k2, fq_name) {
+                            Ok(m) => {
+                                m.set_native_code(n_code.clone());
+                                live = true;
+                            }
+                            Err(_) => rejected.push(fq_name.clone()),
+                        }
+                    }
+                }
+                // Load k into map
+                if live {
+                    lookup.insert(klass_name, KlassLoadingStatus::Live{ klass: k2 });
+                } else {
+                    lookup.insert(klass_name, KlassLoadingStatus::Loaded{ klass: k2 });
+                }
+            }
+        }
+        // Methods they were meant for stay unbound, so throw UnsatisfiedLinkError
+        if !rejected.is_empty() {
+            let mut natives = self.natives.write().unwrap();
+            for fq_name in rejected {
+                natives.remove(&fq_name);
             }
-            i = i + 1;
         }
     }
 
-    fn run_clinit_method(&mut self, klass_name: &String, i_callback: fn(&mut SharedKlassRepo, &OtMethod, &mut InterpLocalVars) -> Option<JvmValue>) {
-        let m_str = klass_name.to_owned() + ".<clinit>:()V";
-        let k = self.lookup_klass(klass_name);
-        let clinit = match k.get_method_by_name_and_desc(&m_str) {
-            Some(value) => value.clone(),
-            // FIXME Make this a clean exit
-            None => panic!("Error: Clinit method not found {}", klass_name),
-        };
-        // FIXME Parameter passing
-        let mut vars = InterpLocalVars::of(5);
-        i_callback(self, &clinit, &mut vars);
+    fn mention(lookup: &mut HashMap<String, KlassLoadingStatus>, mentions: Vec<String>) -> () {
+        for klass_name in mentions {
+            // If we don't have this class already, add a mention
+            lookup.entry(klass_name).or_insert(KlassLoadingStatus::Mentioned{ });
+        }
     }
 
-    fn install_native_method(&mut self, klass_name: &String, name_desc: &String,
-        n_code: fn(&InterpLocalVars) -> Option<JvmValue> ) -> () {
+    // Binds n_code to the native method name_desc, e.g. "add:(II)I", of
+    // klass_name. The klass needn't be loaded yet, its natives are bound as
+    // it's added, when the method is checked for instead and the code
+    // dropped if it's not there. Methods with nothing bound throw
+    // UnsatisfiedLinkError.
+    pub fn register_native<F>(&self, klass_name: &str, name_desc: &str, n_code: F) -> Result<(), NativeError>
+    where
+        F: Fn(&Vm, &InterpLocalVars) -> Option<JvmValue> + Send + Sync + 'static,
+    {
+        let fq_name = klass_name.to_owned() + "." + name_desc;
+        let mut natives = self.natives.write().unwrap();
+        if natives.contains_key(&fq_name) {
+            return Err(NativeError::Duplicate(fq_name));
+        }
+        let n_code: NativeMethod = Arc::new(n_code);
+        if let Some(k) = self.find_klass(klass_name) {
+            SharedKlassRepo::native_method(&k, &fq_name)?.set_native_code(n_code.clone());
+            self.klass_lookup.write().unwrap().insert(k.get_name(), KlassLoadingStatus::Live{ klass: k });
+        }
+        natives.insert(fq_name, n_code);
+        Ok(())
+    }
+
+    // The method of k that code registered as fq_name is for
+    fn native_method<'a>(k: &'a OtKlass, fq_name: &String) -> Result<&'a OtMethod, NativeError> {
+        match k.get_method_by_name_and_desc(fq_name) {
+            Some(m) if m.is_native() => Ok(m),
+            Some(_) => Err(NativeError::NotNative(fq_name.to_owned())),
+            None => Err(NativeError::NoSuchMethod(fq_name.to_owned())),
+        }
+    }
+
+    // The native methods of loaded klasses that have code bound to them
+    pub fn bound_natives(&self) -> Vec<String> {
+        let mut bound: Vec<String> = self
+            .klasses()
+            .iter()
+            .flat_map(|k| k.get_methods())
+            .filter(|m| m.is_native() && m.get_native_code().is_some())
+            .map(|m| m.get_fq_name_desc())
+            .collect();
+        bound.sort();
+        bound
+    }
+
+    fn install_intrinsic(&mut self, klass_name: &String, name_desc: &String,
+        n_code: fn(&Vm, &InterpLocalVars) -> Option<JvmValue>) -> () {
         let k = self.lookup_klass(klass_name);
         let fq_name = klass_name.to_owned() +"."+ &name_desc;
 
-        k.set_native_method(fq_name, n_code);
-        self.klass_lookup.get(klass_name).unwrap().replace(KlassLoadingStatus::Live{ klass: k });
+        match k.get_method_by_name_and_desc(&fq_name) {
+            Some(m) => m.set_intrinsic_code(Arc::new(n_code)),
+            None => panic!("No method {} found to install an intrinsic for", fq_name),
+        }
     }
 
 //    fn double_mapper_factory(tfm: fn(f64) -> f64) -> fn(&InterpLocalVars) -> Option<JvmValue> {
@@ -153,10 +309,9 @@ impl SharedKlassRepo {
 //    }
 
     // This reads in classes.jar and adds each class one by one before fixing up
-    // the bits of native code that we have working
-    //
-    // An interpreter callback, i_callback is needed to run the static initializers
-    pub fn bootstrap(&mut self, i_callback: fn(&mut SharedKlassRepo, &OtMethod, &mut InterpLocalVars) -> Option<JvmValue>) -> () {
+    // the bits of native code that we have working. Running the static
+    // initializers is left to the VM, see Vm::bootstrap().
+    pub fn bootstrap(&mut self) -> () {
         let file = "resources/lib/classes.jar";
         ZipFiles::new(file)
         .into_iter()
@@ -168,89 +323,105 @@ impl SharedKlassRepo {
             if let Ok((name, bytes)) = z {
                 let mut parser = crate::klass_parser::OtKlassParser::of(bytes, name);
                 parser.parse();
-                self.add_klass(&parser.klass());
+                let mut k = parser.klass();
+                // Natives classes.jar predates, installed below
+                if k.get_name() == "java/lang/System" {
+                    k.add_method(OtMethod::of(
+                        k.get_name(),
+                        "identityHashCode".to_string(),
+                        "(Ljava/lang/Object;)I".to_string(),
+                        ACC_PUBLIC | ACC_STATIC | ACC_NATIVE,
+                        0,
+                        0,
+                    ));
+                }
+                self.add_klass(&k);
             }
         });
 
-//        self.install_native_method(&"java/lang/Object".to_string(), &"getClass:()Ljava/lang/Class;".to_string(), crate::native_methods::java_lang_Object__getClass);
-        self.install_native_method(&"java/lang/Object".to_string(), &"hashCode:()I".to_string(), crate::native_methods::java_lang_Object__hashcode);
-//        self.install_native_method(&"java/lang/Object".to_string(), &"clone:()Ljava/lang/Object;".to_string(), crate::native_methods::java_lang_Object__clone);
-        self.install_native_method(&"java/lang/Object".to_string(), &"notify:()V".to_string(), crate::native_methods::java_lang_Object__notify);
-        self.install_native_method(&"java/lang/Object".to_string(), &"notifyAll:()V".to_string(), crate::native_methods::java_lang_Object__notifyAll);
-        self.install_native_method(&"java/lang/Object".to_string(), &"wait:(J)V".to_string(), crate::native_methods::java_lang_Object__wait);
+        self.register_native("java/lang/Object", "getClass:()Ljava/lang/Class;", crate::native_methods::java_lang_Object__getClass).unwrap();
+        self.register_native("java/lang/Object", "hashCode:()I", crate::native_methods::java_lang_Object__hashcode).unwrap();
+        self.register_native("java/lang/Object", "clone:()Ljava/lang/Object;", crate::native_methods::java_lang_Object__clone).unwrap();
+        self.register_native("java/lang/Object", "notify:()V", crate::native_methods::java_lang_Object__notify).unwrap();
+        self.register_native("java/lang/Object", "notifyAll:()V", crate::native_methods::java_lang_Object__notifyAll).unwrap();
+        self.register_native("java/lang/Object", "wait:(J)V", crate::native_methods::java_lang_Object__wait).unwrap();
 
 
-//        public static final native java.lang.Class forName(java.lang.String) throws java.lang.ClassNotFoundException;
-//        public final native java.lang.Object newInstance() throws java.lang.InstantiationException, java.lang.IllegalAccessException;
+        self.register_native("java/lang/Class", "forName:(Ljava/lang/String;)Ljava/lang/Class;", crate::native_methods::java_lang_Class__forName).unwrap();
+        self.register_native("java/lang/Class", "newInstance:()Ljava/lang/Object;", crate::native_methods::java_lang_Class__newInstance).unwrap();
 
-        self.install_native_method(&"java/lang/Class".to_string(), &"getName:()Ljava/lang/String;".to_string(), crate::native_methods::java_lang_Class__getName);
-//        public final native java.lang.String getName();
-//        public final native java.lang.Class getSuperclass();
-//        public final native java.lang.Class[] getInterfaces();
+        self.register_native("java/lang/Class", "getName:()Ljava/lang/String;", crate::native_methods::java_lang_Class__getName).unwrap();
+        self.register_native("java/lang/Class", "getSuperclass:()Ljava/lang/Class;", crate::native_methods::java_lang_Class__getSuperclass).unwrap();
+        self.register_native("java/lang/Class", "getInterfaces:()[Ljava/lang/Class;", crate::native_methods::java_lang_Class__getInterfaces).unwrap();
 //        public final native java.lang.ClassLoader getClassLoader();
-//        public final native boolean isInterface();
-
-        self.install_native_method(&"java/lang/Compiler".to_string(), &"compileClass:(Ljava/lang/Class;)Z".to_string(), crate::native_methods::java_lang_Compiler__compileClass);
-        self.install_native_method(&"java/lang/Compiler".to_string(), &"compileClasses:(Ljava/lang/String;)Z".to_string(), crate::native_methods::java_lang_Compiler__compileClasses);
+        self.register_native("java/lang/Class", "isInterface:()Z", crate::native_methods::java_lang_Class__isInterface).unwrap();
+
+        self.register_native("java/lang/Thread", "currentThread:()Ljava/lang/Thread;", crate::native_methods::java_lang_Thread__currentThread).unwrap();
+        self.register_native("java/lang/Thread", "yield:()V", crate::native_methods::java_lang_Thread__yield).unwrap();
+        self.register_native("java/lang/Thread", "sleep:(J)V", crate::native_methods::java_lang_Thread__sleep).unwrap();
+        self.register_native("java/lang/Thread", "start:()V", crate::native_methods::java_lang_Thread__start).unwrap();
+        self.register_native("java/lang/Thread", "isAlive:()Z", crate::native_methods::java_lang_Thread__isAlive).unwrap();
+        // The real constructors need String and ThreadGroup
+        self.install_intrinsic(&"java/lang/Thread".to_string(), &"<init>:()V".to_string(), crate::native_methods::java_lang_Thread__init);
+        self.install_intrinsic(&"java/lang/Thread".to_string(), &"<init>:(Ljava/lang/Runnable;)V".to_string(), crate::native_methods::java_lang_Thread__init_runnable);
+        self.install_intrinsic(&"java/lang/Thread".to_string(), &"interrupt:()V".to_string(), crate::native_methods::java_lang_Thread__interrupt);
+
+        self.register_native("java/lang/Compiler", "compileClass:(Ljava/lang/Class;)Z", crate::native_methods::java_lang_Compiler__compileClass).unwrap();
+        self.register_native("java/lang/Compiler", "compileClasses:(Ljava/lang/String;)Z", crate::native_methods::java_lang_Compiler__compileClasses).unwrap();
 //        public static final native java.lang.Object command(java.lang.Object);
-        self.install_native_method(&"java/lang/Compiler".to_string(), &"enable:()V".to_string(), crate::native_methods::java_lang_Compiler__enable);
-        self.install_native_method(&"java/lang/Compiler".to_string(), &"disable:()V".to_string(), crate::native_methods::java_lang_Compiler__disable);
+        self.register_native("java/lang/Compiler", "enable:()V", crate::native_methods::java_lang_Compiler__enable).unwrap();
+        self.register_native("java/lang/Compiler", "disable:()V", crate::native_methods::java_lang_Compiler__disable).unwrap();
         
-        self.install_native_method(&"java/lang/Runtime".to_string(), &"freeMemory:()J".to_string(), crate::native_methods::java_lang_Runtime__freeMemory);
-        self.install_native_method(&"java/lang/Runtime".to_string(), &"totalMemory:()J".to_string(), crate::native_methods::java_lang_Runtime__totalMemory);
-        self.install_native_method(&"java/lang/Runtime".to_string(), &"gc:()V".to_string(), crate::native_methods::java_lang_Runtime__gc);
-        self.install_native_method(&"java/lang/Runtime".to_string(), &"runFinalization:()V".to_string(), crate::native_methods::java_lang_Runtime__runFinalization);
-        self.install_native_method(&"java/lang/Runtime".to_string(), &"traceInstructions:(Z)V".to_string(), crate::native_methods::java_lang_Runtime__traceInstructions);
-        self.install_native_method(&"java/lang/Runtime".to_string(), &"traceMethodCalls:(Z)V".to_string(), crate::native_methods::java_lang_Runtime__traceMethodCalls);
-
-        self.install_native_method(&"java/lang/System".to_string(), &"currentTimeMillis:()J".to_string(), crate::native_methods::java_lang_System__currentTimeMillis);
-        self.install_native_method(&"java/lang/System".to_string(), &"arraycopy:(Ljava/lang/Object;ILjava/lang/Object;II)V".to_string(), crate::native_methods::java_lang_System__arraycopy);
+        self.install_intrinsic(&"java/lang/Runtime".to_string(), &"getRuntime:()Ljava/lang/Runtime;".to_string(), crate::native_methods::java_lang_Runtime__getRuntime);
+        self.register_native("java/lang/Runtime", "freeMemory:()J", crate::native_methods::java_lang_Runtime__freeMemory).unwrap();
+        self.register_native("java/lang/Runtime", "totalMemory:()J", crate::native_methods::java_lang_Runtime__totalMemory).unwrap();
+        self.register_native("java/lang/Runtime", "gc:()V", crate::native_methods::java_lang_Runtime__gc).unwrap();
+        self.register_native("java/lang/Runtime", "runFinalization:()V", crate::native_methods::java_lang_Runtime__runFinalization).unwrap();
+        self.register_native("java/lang/Runtime", "traceInstructions:(Z)V", crate::native_methods::java_lang_Runtime__traceInstructions).unwrap();
+        self.register_native("java/lang/Runtime", "traceMethodCalls:(Z)V", crate::native_methods::java_lang_Runtime__traceMethodCalls).unwrap();
+
+        self.register_native("java/lang/System", "currentTimeMillis:()J", crate::native_methods::java_lang_System__currentTimeMillis).unwrap();
+        self.register_native("java/lang/System", "identityHashCode:(Ljava/lang/Object;)I", crate::native_methods::java_lang_System__identityHashCode).unwrap();
+        self.register_native("java/lang/System", "arraycopy:(Ljava/lang/Object;ILjava/lang/Object;II)V", crate::native_methods::java_lang_System__arraycopy).unwrap();
 
         // Load j.l.Math native methods
 //        let sin_f = SharedKlassRepo::double_mapper_factory(|i: f64| -> f64 { i.sin() });
-//        self.install_native_method(&"java/lang/Math".to_string(), &"sin:(D)D".to_string(), sin_f);
-        self.install_native_method(&"java/lang/Math".to_string(), &"sin:(D)D".to_string(), crate::native_methods::java_lang_Math__sin);
-        self.install_native_method(&"java/lang/Math".to_string(), &"cos:(D)D".to_string(), crate::native_methods::java_lang_Math__cos);
-        self.install_native_method(&"java/lang/Math".to_string(), &"tan:(D)D".to_string(), crate::native_methods::java_lang_Math__tan);
-        self.install_native_method(&"java/lang/Math".to_string(), &"asin:(D)D".to_string(), crate::native_methods::java_lang_Math__asin);
-        self.install_native_method(&"java/lang/Math".to_string(), &"acos:(D)D".to_string(), crate::native_methods::java_lang_Math__acos);
-        self.install_native_method(&"java/lang/Math".to_string(), &"atan:(D)D".to_string(), crate::native_methods::java_lang_Math__atan);
-        self.install_native_method(&"java/lang/Math".to_string(), &"exp:(D)D".to_string(), crate::native_methods::java_lang_Math__exp);
-        self.install_native_method(&"java/lang/Math".to_string(), &"log:(D)D".to_string(), crate::native_methods::java_lang_Math__log);
-        self.install_native_method(&"java/lang/Math".to_string(), &"sqrt:(D)D".to_string(), crate::native_methods::java_lang_Math__sqrt);
+//        self.register_native("java/lang/Math", "sin:(D)D", sin_f).unwrap();
+        self.register_native("java/lang/Math", "sin:(D)D", crate::native_methods::java_lang_Math__sin).unwrap();
+        self.register_native("java/lang/Math", "cos:(D)D", crate::native_methods::java_lang_Math__cos).unwrap();
+        self.register_native("java/lang/Math", "tan:(D)D", crate::native_methods::java_lang_Math__tan).unwrap();
+        self.register_native("java/lang/Math", "asin:(D)D", crate::native_methods::java_lang_Math__asin).unwrap();
+        self.register_native("java/lang/Math", "acos:(D)D", crate::native_methods::java_lang_Math__acos).unwrap();
+        self.register_native("java/lang/Math", "atan:(D)D", crate::native_methods::java_lang_Math__atan).unwrap();
+        self.register_native("java/lang/Math", "exp:(D)D", crate::native_methods::java_lang_Math__exp).unwrap();
+        self.register_native("java/lang/Math", "log:(D)D", crate::native_methods::java_lang_Math__log).unwrap();
+        self.register_native("java/lang/Math", "sqrt:(D)D", crate::native_methods::java_lang_Math__sqrt).unwrap();
 //public static final native double IEEEremainder(double, double);
-        self.install_native_method(&"java/lang/Math".to_string(), &"ceil:(D)D".to_string(), crate::native_methods::java_lang_Math__ceil);
-        self.install_native_method(&"java/lang/Math".to_string(), &"floor:(D)D".to_string(), crate::native_methods::java_lang_Math__floor);
+        self.register_native("java/lang/Math", "ceil:(D)D", crate::native_methods::java_lang_Math__ceil).unwrap();
+        self.register_native("java/lang/Math", "floor:(D)D", crate::native_methods::java_lang_Math__floor).unwrap();
 //public static final native double rint(double);
-        self.install_native_method(&"java/lang/Math".to_string(), &"atan2:(DD)D".to_string(), crate::native_methods::java_lang_Math__atan2);
-        self.install_native_method(&"java/lang/Math".to_string(), &"pow:(DD)D".to_string(), crate::native_methods::java_lang_Math__pow);
+        self.register_native("java/lang/Math", "atan2:(DD)D", crate::native_methods::java_lang_Math__atan2).unwrap();
+        self.register_native("java/lang/Math", "pow:(DD)D", crate::native_methods::java_lang_Math__pow).unwrap();
 
         // TODO Get enough of java.io.PrintStream working to get System.out.println() to work
 
         // // private native void open(String name) throws IOException;
-        // self.install_native_method(&"java/io/FileOutputStream".to_string(), &"open:(Ljava/lang/String;)V".to_string(), crate::native_methods::java_io/_FileOutputStream__open);
+        // self.register_native("java/io/FileOutputStream", "open:(Ljava/lang/String;)V", crate::native_methods::java_io/_FileOutputStream__open).unwrap();
         
         // // public native void write(int b) throws IOException;
-        // self.install_native_method(&"java/io/FileOutputStream".to_string(), &"write:(I)V".to_string(), crate::native_methods::java_io/_FileOutputStream__write);
+        // self.register_native("java/io/FileOutputStream", "write:(I)V", crate::native_methods::java_io/_FileOutputStream__write).unwrap();
 
         // // private native void writeBytes(byte b[], int off, int len) throws IOException;
-        // self.install_native_method(&"java/io/FileOutputStream".to_string(), &"writeBytes:([BII])V".to_string(), crate::native_methods::java_io/_FileOutputStream__writeBytes);
+        // self.register_native("java/io/FileOutputStream", "writeBytes:([BII])V", crate::native_methods::java_io/_FileOutputStream__writeBytes).unwrap();
 
         // // public native void close() throws IOException;
-        // self.install_native_method(&"java/io/FileOutputStream".to_string(), &"close:()V".to_string(), crate::native_methods::java_io/_FileOutputStream__close);
+        // self.register_native("java/io/FileOutputStream", "close:()V", crate::native_methods::java_io/_FileOutputStream__close).unwrap();
 
         // // private static native FileDescriptor initSystemFD(FileDescriptor fdObj, int desc);
-        self.install_native_method(&"java/io/FileDescriptor".to_string(), &"initSystemFD:(Ljava/io/FileDescriptor;I)Ljava/io/FileDescriptor;".to_string(), crate::native_methods::java_io_FileDescriptor__initSystemFD);
+        self.register_native("java/io/FileDescriptor", "initSystemFD:(Ljava/io/FileDescriptor;I)Ljava/io/FileDescriptor;", crate::native_methods::java_io_FileDescriptor__initSystemFD).unwrap();
 
         // let s = format!("{:?}", self.klass_lookup);
         // dbg!(s);
-
-        // All native methods are installed for the bootstrap classes 
-        // Now, we need to run the static initializers in the right order
-        self.run_clinit_method(&"java/io/FileDescriptor".to_string(), i_callback);
-
-        // // This requires the file descriptor handling to already exist
-        // self.run_clinit_method(&"java/lang/System".to_string(), i_callback);
     }
 
     pub fn lookup_static_field(&self, klass_name: &String, idx: u16) -> OtField {
@@ -280,8 +451,14 @@ impl SharedKlassRepo {
         let fq_name_desc = current_klass.cp_as_string(idx);
         let target_klass_name = &SharedKlassRepo::klass_name_from_fq(&fq_name_desc);
         let target_klass = self.lookup_klass(&target_klass_name);
+        let name_desc = &fq_name_desc[target_klass_name.len() + 1..];
 
-        let opt_f = target_klass.get_instance_field_by_name_and_desc(&fq_name_desc);
+        // The field may be inherited, and hidden fields come earlier
+        let opt_f = self
+            .instance_fields(&target_klass)
+            .iter()
+            .rev()
+            .find(|f| f.get_name_desc() == name_desc);
 
         match opt_f {
             Some(f) => f.clone(),
@@ -293,14 +470,180 @@ impl SharedKlassRepo {
         }
     }
 
+    // Lays out the instance fields of klass and its superclasses
+    pub fn instance_fields<'a>(&self, klass: &'a OtKlass) -> &'a [OtField] {
+        klass.instance_layout(|| {
+            let super_name = klass.get_super_name();
+            // j.l.Object is its own superclass
+            if super_name == klass.get_name() {
+                Vec::new()
+            } else {
+                self.instance_fields(&self.lookup_klass(&super_name)).to_vec()
+            }
+        })
+    }
+
+    //////////////////////////////////////////////
+    // Resolution of CP entries, cached on the klass doing the referring
+
+    pub fn resolve_instance_field<'a>(&self, klass: &'a OtKlass, idx: u16) -> &'a OtField {
+        let resolved = klass.resolved(idx, || {
+            CpResolved::InstanceField(self.lookup_instance_field(&klass.get_name(), idx))
+        });
+        match resolved {
+            CpResolved::InstanceField(f) => f,
+            other => panic!("CP index {} on {} is {:?}, not an instance field", idx, klass.get_name(), other),
+        }
+    }
+
+    pub fn resolve_static_field<'a>(&self, klass: &'a OtKlass, idx: u16) -> (Arc<OtKlass>, usize, &'a OtField) {
+        let resolved = klass.resolved(idx, || {
+            let field = self.lookup_static_field(&klass.get_name(), idx);
+            let target = self.lookup_klass(&field.get_klass_name());
+            let slot = target.get_static_field_offset(&field);
+            CpResolved::StaticField { klass: Arc::downgrade(&target), slot, field }
+        });
+        match resolved {
+            CpResolved::StaticField { klass: k, slot, field } => (resolved_klass(k), *slot, field),
+            other => panic!("CP index {} on {} is {:?}, not a static field", idx, klass.get_name(), other),
+        }
+    }
+
+    pub fn resolve_method(&self, klass: &OtKlass, idx: u16) -> (Arc<OtKlass>, usize, u8) {
+        let resolved = klass.resolved(idx, || {
+            let (klz_idx, nt_idx) = match klass.lookup_cp(idx) {
+                CpEntry::MethodRef(mr) => (mr.clz_idx, mr.nt_idx),
+                CpEntry::InterfaceMethodRef(mr) => (mr.clz_idx, mr.nt_idx),
+                _ => panic!("Non-methodref found in {} at CP index {}", klass.get_name(), idx),
+            };
+            // Arrays only have the methods of j.l.Object, e.g. clone()
+            let target_name = match klass.cp_as_string(klz_idx) {
+                name if name.starts_with('[') => "java/lang/Object".to_string(),
+                name => name,
+            };
+            let target = self.lookup_klass(&target_name);
+            let name_desc = klass.cp_as_string(nt_idx);
+            let (found, m_idx) = self
+                .find_method(&target, &name_desc)
+                .unwrap_or_else(|| panic!("No method {} found on {}", name_desc, target.get_name()));
+            CpResolved::Method { klass: Arc::downgrade(&found), idx: m_idx, arg_count: klass.get_method_arg_count(idx) }
+        });
+        match resolved {
+            CpResolved::Method { klass: k, idx: m_idx, arg_count } => (resolved_klass(k), *m_idx, *arg_count),
+            other => panic!("CP index {} on {} is {:?}, not a method", idx, klass.get_name(), other),
+        }
+    }
+
+    // Looks for a method on klass and then up its superclasses, handing back
+    // the klass that declares it and the method's index there
+    pub fn find_method(&self, klass: &Arc<OtKlass>, name_desc: &str) -> Option<(Arc<OtKlass>, usize)> {
+        let mut current = klass.clone();
+        loop {
+            if let Some(m_idx) = current.get_method_index(&(current.get_name() + "." + name_desc)) {
+                return Some((current, m_idx));
+            }
+            // j.l.Object is its own superclass
+            let super_name = current.get_super_name();
+            if super_name == current.get_name() {
+                return None;
+            }
+            current = self.lookup_klass(&super_name);
+        }
+    }
+
+    // Whether klass is iface, extends it, or implements it directly or
+    // through another interface
+    pub fn implements(&self, klass: &OtKlass, iface: &str) -> bool {
+        let mut pending: Vec<String> = vec![];
+        let mut current = Some(klass.get_name());
+        while let Some(name) = current.take().or_else(|| pending.pop()) {
+            if name == iface {
+                return true;
+            }
+            // Interfaces of classes that were never loaded can't be looked at
+            let k = match self.find_klass(&name) {
+                Some(k) => k,
+                None => continue,
+            };
+            pending.extend(k.get_interfaces().iter().cloned());
+            if k.get_super_name() != name {
+                current = Some(k.get_super_name());
+            }
+        }
+        false
+    }
+
+    // Whether value can be stored in an array of element_klass's instances,
+    // as checked by AASTORE. Arrays are Objects, Cloneables and nothing else.
+    pub fn can_store(&self, element_klass: usize, value: &OtObj) -> bool {
+        let target = self.klass_by_id(element_klass).get_name();
+        match value {
+            OtObj::VmObj { klassid, .. } => self.implements(&self.klass_by_id(*klassid), &target),
+            _ => target == "java/lang/Object" || target == "java/lang/Cloneable",
+        }
+    }
+
+    //////////////////////////////////////////////
+    // Class objects
+
+    // The class of obj, for Vm::mirror_for() once the heap is let go of
+    pub fn type_of(&self, obj: &OtObj) -> Mirrored {
+        let desc = match obj {
+            OtObj::VmObj { klassid, .. } => return Mirrored::Klass(*klassid),
+            OtObj::VmArrInt { .. } => "[I".to_string(),
+            OtObj::VmArrLong { .. } => "[J".to_string(),
+            OtObj::VmArrBoolean { .. } => "[Z".to_string(),
+            OtObj::VmArrByte { .. } => "[B".to_string(),
+            OtObj::VmArrChar { .. } => "[C".to_string(),
+            OtObj::VmArrShort { .. } => "[S".to_string(),
+            OtObj::VmArrObj { klassid, .. } => format!("[L{};", self.klass_by_id(*klassid).get_name()),
+        };
+        Mirrored::Array(desc)
+    }
+
+    // Where klass's finalize() is, if it overrides Object's, which does
+    // nothing
+    pub fn finalizer(&self, klass: &Arc<OtKlass>) -> Option<(Arc<OtKlass>, usize)> {
+        let find = || {
+            self.find_method(klass, "finalize:()V")
+                .filter(|(k, _)| k.get_name() != "java/lang/Object")
+        };
+        if klass.is_finalizable(|| find().is_some()) {
+            find()
+        } else {
+            None
+        }
+    }
+
+    pub fn resolve_klass(&self, klass: &OtKlass, idx: u16) -> Arc<OtKlass> {
+        let resolved = klass.resolved(idx, || match klass.lookup_cp(idx) {
+            CpEntry::Class(c) => {
+                // FIXME Arrays of arrays are made as arrays of Object for now
+                let target_name = match klass.cp_as_string(c.0) {
+                    name if name.starts_with('[') => "java/lang/Object".to_string(),
+                    name => name,
+                };
+                let target = self.lookup_klass(&target_name);
+                // Ready for NEW
+                self.instance_fields(&target);
+                CpResolved::Klass(Arc::downgrade(&target))
+            }
+            _ => panic!("Non-class found in {} at CP index {}", klass.get_name(), idx),
+        });
+        match resolved {
+            CpResolved::Klass(k) => resolved_klass(k),
+            other => panic!("CP index {} on {} is {:?}, not a class", idx, klass.get_name(), other),
+        }
+    }
+
     // FIXME Lookup offset properly
     pub fn get_field_offset(&self, kid: usize, f: OtField) -> usize {
         0
     }
 
     pub fn lookup_method_exact(&self, klass_name: &String, fq_name_desc: String) -> OtMethod {
-        match self.klass_lookup.get(klass_name) {
-            Some(cell) => match &*(cell.borrow()) {
+        match self.klass_lookup.read().unwrap().get(klass_name) {
+            Some(status) => match status {
                 KlassLoadingStatus::Mentioned {} => panic!("Klass with ID {} is not loaded yet", klass_name),
                 KlassLoadingStatus::Loaded { klass : k } => k.get_method_by_name_and_desc(&fq_name_desc).unwrap().clone(),
                 KlassLoadingStatus::Live { klass : k } => k.get_method_by_name_and_desc(&fq_name_desc).unwrap().clone(),
@@ -311,8 +654,8 @@ impl SharedKlassRepo {
 
     // m_idx is IDX in CP of current class
     pub fn lookup_method_virtual(&self, klass_name: &String, m_idx: u16) -> OtMethod {
-        match self.klass_lookup.get(klass_name) {
-            Some(cell) => match &*(cell.borrow()) {
+        match self.klass_lookup.read().unwrap().get(klass_name) {
+            Some(status) => match status {
                 KlassLoadingStatus::Mentioned {} => panic!("Klass with ID {} is not loaded yet", klass_name),
                 KlassLoadingStatus::Loaded { klass : k } => k.get_method_by_offset_virtual(m_idx),
                 KlassLoadingStatus::Live { klass : k } => k.get_method_by_offset_virtual(m_idx),
@@ -327,17 +670,10 @@ impl fmt::Display for SharedKlassRepo {
         write!(
             f,
             "{:#?}",
-            self.klass_lookup
+            self.klass_lookup.read().unwrap()
         )
     }
 }
 
-impl Clone for SharedKlassRepo {
-    fn clone(&self) -> SharedKlassRepo {
-        SharedKlassRepo {
-            klass_lookup: self.klass_lookup.clone(),
-        }
-    }
-}
 
 /////////////////////////////////////////////////////////////////
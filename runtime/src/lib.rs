@@ -11,15 +11,24 @@ use std::sync::Mutex;
 extern crate lazy_static;
 
 pub mod constant_pool;
+pub mod frame;
+pub mod gc;
+pub mod histogram;
+pub mod hprof;
+pub mod instruction;
 pub mod interp_stack;
 pub mod klass_parser;
 pub mod klass_repo;
+pub mod monitor;
 pub mod native_methods;
 pub mod object;
+pub mod opcode;
 pub mod otfield;
 pub mod otklass;
 pub mod otmethod;
 pub mod simple_heap;
+pub mod thread;
+pub mod vm;
 
 use crate::simple_heap::SharedSimpleHeap;
 use object::OtObj;
@@ -27,10 +36,7 @@ use otfield::OtField;
 use otklass::OtKlass;
 use otmethod::OtMethod;
 use klass_repo::SharedKlassRepo;
-
-lazy_static! {
-    pub static ref HEAP: Mutex<SharedSimpleHeap> = Mutex::new(SharedSimpleHeap::of());
-}
+use vm::Vm;
 
 //////////// RUNTIME JVM VALUES
 
@@ -43,7 +49,7 @@ pub enum JvmValue {
     Long(i64),
     Float(f32),
     Double(f64),
-    Char(char),
+    Char(u16), // A UTF-16 code unit, not a Unicode scalar value
     ObjRef(usize), // Access objects by id
 }
 
@@ -83,12 +89,36 @@ impl JvmValue {
             'J' => JvmValue::Long(0),
             'F' => JvmValue::Float(0.0),
             'D' => JvmValue::Double(0.0),
-            'C' => JvmValue::Char('\0'),
+            'C' => JvmValue::Char(0),
             'A' => JvmValue::ObjRef(0),
             _   => panic!("Illegal type {} seen when trying to parse", letter)
         }
     }
 
+    // boolean, byte, short and char all have int as their computational
+    // type (JVMS 2.11.1), so this is what goes on the stack and in locals
+    pub fn to_computational(self) -> JvmValue {
+        match self {
+            JvmValue::Boolean(v) => JvmValue::Int(v as i32),
+            JvmValue::Byte(v) => JvmValue::Int(v as i32),
+            JvmValue::Short(v) => JvmValue::Int(v as i32),
+            JvmValue::Char(v) => JvmValue::Int(v as i32),
+            other => other,
+        }
+    }
+
+    // The reverse of to_computational(), applied when storing into a field
+    // or array element of the given type. Other types pass through.
+    pub fn narrow_to(self, letter: char) -> JvmValue {
+        match (letter, self.to_computational()) {
+            ('Z', JvmValue::Int(v)) => JvmValue::Boolean(v & 1 == 1),
+            ('B', JvmValue::Int(v)) => JvmValue::Byte(v as i8),
+            ('S', JvmValue::Int(v)) => JvmValue::Short(v as i16),
+            ('C', JvmValue::Int(v)) => JvmValue::Char(v as u16),
+            _ => self,
+        }
+    }
+
     value_as!(as_bool:   Boolean(bool));
     value_as!(as_byte:   Byte(i8));
     value_as!(as_short:  Short(i16));
@@ -96,7 +126,7 @@ impl JvmValue {
     value_as!(as_long:   Long(i64));
     value_as!(as_float:  Float(f32));
     value_as!(as_double: Double(f64));
-    value_as!(as_char:   Char(char));
+    value_as!(as_char:   Char(u16));
     value_as!(as_objref: ObjRef(usize));
 }
 
@@ -110,7 +140,10 @@ impl fmt::Display for JvmValue {
             JvmValue::Long(v)    => write!(f, "{}", v),
             JvmValue::Float(v)   => write!(f, "{}", v),
             JvmValue::Double(v)  => write!(f, "{}", v),
-            JvmValue::Char(v)    => write!(f, "{}", v),
+            JvmValue::Char(v)    => match std::char::from_u32(*v as u32) {
+                Some(c) => write!(f, "{}", c),
+                None => write!(f, "\\u{:04x}", v),
+            },
             JvmValue::ObjRef(v)  => write!(f, "{}", v),
         }
     }
@@ -129,7 +162,7 @@ pub struct InterpLocalVars {
 }
 
 impl InterpLocalVars {
-    pub fn of(var_count: u8) -> InterpLocalVars {
+    pub fn of(var_count: u16) -> InterpLocalVars {
         let mut out = InterpLocalVars { lvt: Vec::new() };
         for i in 0..var_count {
             out.lvt.push(JvmValue::default());
@@ -137,18 +170,29 @@ impl InterpLocalVars {
         out
     }
 
+    // Makes room for at least var_count locals, keeping the current values
+    pub fn grow_to(&mut self, var_count: u16) -> () {
+        if self.lvt.len() < var_count as usize {
+            self.lvt.resize(var_count as usize, JvmValue::default());
+        }
+    }
+
     pub fn load(&self, idx: u8) -> JvmValue {
         self.lvt[idx as usize].clone()
     }
 
+    pub fn values(&self) -> &[JvmValue] {
+        &self.lvt
+    }
+
     pub fn store(&mut self, idx: u8, val: JvmValue) -> () {
-        self.lvt[idx as usize] = val
+        self.lvt[idx as usize] = val.to_computational()
     }
 
-    pub fn iinc(&mut self, idx: u8, incr: u8) -> () {
+    pub fn iinc(&mut self, idx: u8, incr: i32) -> () {
         let val = self.lvt[idx as usize].as_int()
             .unwrap_or_else(|| panic!("Non-integer value encountered in IINC of local var {}", idx));
-        self.lvt[idx as usize] = JvmValue::Int(val + 1);
+        self.lvt[idx as usize] = JvmValue::Int(val.wrapping_add(incr));
     }
 }
 
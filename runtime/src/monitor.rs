@@ -0,0 +1,257 @@
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::vm::Vm;
+
+// Monitor state lives in each object's mark word (see OtObj), guarded by
+// the heap lock. Threads that find a monitor taken wait on the VM's
+// released condvar, which shares that lock.
+//
+// Threads in Object.wait() or Thread.sleep() are kept in its wait_sets and
+// block on woken instead. Where both locks are needed, wait_sets is taken
+// first.
+
+#[derive(Default)]
+pub struct Monitors {
+    released: Condvar,
+    wait_sets: Mutex<WaitSets>,
+    woken: Condvar,
+}
+
+// Waiting threads are known by their Thread object, as that is what
+// Thread.interrupt() is called on
+struct Waiter {
+    thread: usize,
+    // Object waited on, 0 for a sleep
+    obj: usize,
+    notified: bool,
+}
+
+#[derive(Default)]
+struct WaitSets {
+    waiters: Vec<Waiter>,
+    // Threads interrupted since they last threw InterruptedException
+    interrupted: HashSet<usize>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum WaitError {
+    NotOwner,
+    Interrupted,
+}
+
+static NEXT_THREAD_ID: AtomicU32 = AtomicU32::new(1);
+// Ids given back by threads that are done, as a mark word only has room
+// for so many, see OtObj::set_monitor()
+static FREE_THREAD_IDS: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+// A thread's id, handed out again once it is dropped. Ids start from 1, so
+// a zero owner in a mark word means unowned.
+pub struct ThreadId(u32);
+
+impl ThreadId {
+    pub fn allocate() -> ThreadId {
+        match FREE_THREAD_IDS.lock().unwrap().pop() {
+            Some(id) => ThreadId(id),
+            None => ThreadId(NEXT_THREAD_ID.fetch_add(1, Ordering::SeqCst)),
+        }
+    }
+
+    pub fn get(&self) -> u32 {
+        self.0
+    }
+}
+
+impl Drop for ThreadId {
+    fn drop(&mut self) {
+        FREE_THREAD_IDS.lock().unwrap().push(self.0);
+    }
+}
+
+thread_local! {
+    // The OS thread's own id, given back as it exits
+    static OWN_THREAD_ID: ThreadId = ThreadId::allocate();
+    // The green thread running, if any
+    static THREAD_ID: Cell<u32> = const { Cell::new(0) };
+}
+
+pub fn current_thread_id() -> u32 {
+    match THREAD_ID.with(|id| id.get()) {
+        0 => OWN_THREAD_ID.with(|id| id.get()),
+        id => id,
+    }
+}
+
+// Green threads share an OS thread, so the scheduler switches the id as it
+// switches between them
+pub fn set_current_thread_id(id: u32) -> () {
+    THREAD_ID.with(|t| t.set(id));
+}
+
+// Takes the monitor of object id for the current thread, or returns false
+// if it is taken. The interpreter blocks in await_release() rather than
+// here, as a green thread mustn't block the OS thread its peers run on, and
+// an OS thread must let the collector know what it holds first.
+pub fn enter(vm: &Vm, id: usize) -> bool {
+    vm.heap().try_monitor_enter(id, current_thread_id())
+}
+
+// Blocks until the monitor of object id is free, or already the current
+// thread's, for enter() to be tried again
+pub fn await_release(vm: &Vm, id: usize) -> () {
+    let me = current_thread_id();
+    let mut heap = vm.heap();
+    while ![0, me].contains(&heap.get_obj(id).monitor_owner()) {
+        heap = vm.monitors.released.wait(heap).unwrap();
+    }
+}
+
+// Blocks until the current thread holds the monitor of object id
+pub fn monitor_enter(vm: &Vm, id: usize) -> () {
+    let me = current_thread_id();
+    let mut heap = vm.heap();
+    while !heap.try_monitor_enter(id, me) {
+        heap = vm.monitors.released.wait(heap).unwrap();
+    }
+}
+
+// Returns false if the current thread doesn't hold the monitor
+pub fn monitor_exit(vm: &Vm, id: usize) -> bool {
+    let me = current_thread_id();
+    let released = vm.heap().monitor_exit(id, me);
+    if released == Some(true) {
+        vm.monitors.released.notify_all();
+    }
+    released.is_some()
+}
+
+// Object.wait(): gives up the monitor of obj until it is notified, the
+// thread is interrupted or the timeout passes, then takes the monitor back
+// as many times as it was held
+pub fn wait(vm: &Vm, obj: usize, thread: usize, timeout: Option<Duration>) -> Result<(), WaitError> {
+    let count = begin_wait(vm, obj, thread)?;
+    end_wait(vm, obj, thread, count, timeout)
+}
+
+// The start of a wait(), which the interpreter finishes off with
+// end_wait(), or for green threads the scheduler with poll() or cancel().
+// Returns how many times the monitor was held.
+pub fn begin_wait(vm: &Vm, obj: usize, thread: usize) -> Result<u32, WaitError> {
+    join_wait_set(vm, &mut vm.monitors.wait_sets.lock().unwrap(), obj, thread)
+}
+
+fn join_wait_set(vm: &Vm, sets: &mut WaitSets, obj: usize, thread: usize) -> Result<u32, WaitError> {
+    let me = current_thread_id();
+    if vm.heap().get_obj(obj).monitor_owner() != me {
+        return Err(WaitError::NotOwner);
+    }
+    if sets.interrupted.remove(&thread) {
+        return Err(WaitError::Interrupted);
+    }
+    // Joining the wait set before letting go means a notify can't be missed
+    sets.waiters.push(Waiter { thread, obj, notified: false });
+    let count = vm.heap().monitor_release(obj, me).unwrap();
+    vm.monitors.released.notify_all();
+    Ok(count)
+}
+// Wakes one thread waiting on obj, or all of them. Returns false if the
+// current thread doesn't hold the monitor of obj.
+pub fn notify(vm: &Vm, obj: usize, all: bool) -> bool {
+    let mut sets = vm.monitors.wait_sets.lock().unwrap();
+    if vm.heap().get_obj(obj).monitor_owner() != current_thread_id() {
+        return false;
+    }
+    for waiter in sets.waiters.iter_mut().filter(|w| w.obj == obj && !w.notified) {
+        waiter.notified = true;
+        if !all {
+            break;
+        }
+    }
+    vm.monitors.woken.notify_all();
+    true
+}
+
+// Thread.sleep(), which only an interrupt cuts short
+pub fn sleep(vm: &Vm, thread: usize, time: Duration) -> Result<(), WaitError> {
+    let mut sets = vm.monitors.wait_sets.lock().unwrap();
+    sets.waiters.push(Waiter { thread, obj: 0, notified: false });
+    park(vm, sets, thread, Some(time))
+}
+
+// Blocks for the rest of a wait() or sleep(), obj being 0 for a sleep,
+// then takes the monitor back as many times as it was held
+pub fn end_wait(vm: &Vm, obj: usize, thread: usize, count: u32, timeout: Option<Duration>) -> Result<(), WaitError> {
+    let outcome = park(vm, vm.monitors.wait_sets.lock().unwrap(), thread, timeout);
+    if obj != 0 {
+        let me = current_thread_id();
+        let mut heap = vm.heap();
+        while !heap.try_monitor_restore(obj, me, count) {
+            heap = vm.monitors.released.wait(heap).unwrap();
+        }
+    }
+    outcome
+}
+
+// The start of a sleep(), as for begin_wait()
+pub fn begin_sleep(vm: &Vm, thread: usize) -> Result<(), WaitError> {
+    let mut sets = vm.monitors.wait_sets.lock().unwrap();
+    if sets.interrupted.remove(&thread) {
+        return Err(WaitError::Interrupted);
+    }
+    sets.waiters.push(Waiter { thread, obj: 0, notified: false });
+    Ok(())
+}
+
+// Whether a green thread's wait or sleep is over, leaving the wait sets
+// if so. Timeouts are left to the scheduler, which calls cancel().
+pub fn poll(vm: &Vm, thread: usize) -> Option<Result<(), WaitError>> {
+    let mut sets = vm.monitors.wait_sets.lock().unwrap();
+    let outcome = woken(&mut sets, thread)?;
+    sets.waiters.retain(|w| w.thread != thread);
+    Some(outcome)
+}
+
+// Ends a green thread's wait or sleep on its timeout
+pub fn cancel(vm: &Vm, thread: usize) -> () {
+    vm.monitors.wait_sets.lock().unwrap().waiters.retain(|w| w.thread != thread);
+}
+// Makes the thread's current or next wait or sleep throw
+pub fn interrupt(vm: &Vm, thread: usize) -> () {
+    vm.monitors.wait_sets.lock().unwrap().interrupted.insert(thread);
+    vm.monitors.woken.notify_all();
+}
+
+// Blocks until the thread's waiter is notified, the thread is interrupted
+// or the timeout passes, then takes the waiter out of the wait sets
+fn park(vm: &Vm, mut sets: MutexGuard<WaitSets>, thread: usize, timeout: Option<Duration>) -> Result<(), WaitError> {
+    let deadline = timeout.map(|t| Instant::now() + t);
+    let outcome = loop {
+        if let Some(outcome) = woken(&mut sets, thread) {
+            break outcome;
+        }
+        sets = match deadline {
+            None => vm.monitors.woken.wait(sets).unwrap(),
+            Some(d) => match d.checked_duration_since(Instant::now()) {
+                Some(left) if !left.is_zero() => vm.monitors.woken.wait_timeout(sets, left).unwrap().0,
+                _ => break Ok(()),
+            },
+        };
+    };
+    sets.waiters.retain(|w| w.thread != thread);
+    outcome
+}
+
+// A notify wins over an interrupt that comes with it, leaving the
+// interrupt pending
+fn woken(sets: &mut WaitSets, thread: usize) -> Option<Result<(), WaitError>> {
+    if sets.waiters.iter().any(|w| w.thread == thread && w.notified) {
+        return Some(Ok(()));
+    }
+    if sets.interrupted.remove(&thread) {
+        return Some(Err(WaitError::Interrupted));
+    }
+    None
+}
@@ -1,67 +1,388 @@
-use std::time::SystemTime;
-
+use std::cell::Cell;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use crate::klass_repo::Mirrored;
+use crate::monitor::{self, WaitError};
+use crate::object::OtObj;
+use crate::otklass::OtKlass;
+use crate::simple_heap::{ArrayCopyError, Finalizer};
+use crate::vm::Vm;
 use crate::InterpLocalVars;
 use crate::JvmValue;
 
+pub const ARRAY_INDEX_OUT_OF_BOUNDS_EXCEPTION: &str = "java/lang/ArrayIndexOutOfBoundsException";
+pub const ARRAY_STORE_EXCEPTION: &str = "java/lang/ArrayStoreException";
+pub const CLASS_NOT_FOUND_EXCEPTION: &str = "java/lang/ClassNotFoundException";
+pub const CLONE_NOT_SUPPORTED_EXCEPTION: &str = "java/lang/CloneNotSupportedException";
+pub const ILLEGAL_ACCESS_EXCEPTION: &str = "java/lang/IllegalAccessException";
+pub const ILLEGAL_ARGUMENT_EXCEPTION: &str = "java/lang/IllegalArgumentException";
+pub const ILLEGAL_MONITOR_STATE_EXCEPTION: &str = "java/lang/IllegalMonitorStateException";
+pub const ILLEGAL_THREAD_STATE_EXCEPTION: &str = "java/lang/IllegalThreadStateException";
+pub const INSTANTIATION_EXCEPTION: &str = "java/lang/InstantiationException";
+pub const INTERRUPTED_EXCEPTION: &str = "java/lang/InterruptedException";
+pub const NULL_POINTER_EXCEPTION: &str = "java/lang/NullPointerException";
+pub const OUT_OF_MEMORY_ERROR: &str = "java/lang/OutOfMemoryError";
+pub const UNSATISFIED_LINK_ERROR: &str = "java/lang/UnsatisfiedLinkError";
+
+thread_local! {
+    // Thrown by a native, for the interpreter to raise once it returns
+    static PENDING_EXCEPTION: Cell<Option<&'static str>> = const { Cell::new(None) };
+    // A Java method for the interpreter to call once the native returns
+    static PENDING_CALL: Cell<Option<(Arc<OtKlass>, usize, InterpLocalVars)>> = const { Cell::new(None) };
+}
+
+// Natives throw by returning this
+pub fn throw(klass_name: &'static str) -> Option<JvmValue> {
+    PENDING_EXCEPTION.with(|e| e.set(Some(klass_name)));
+    None
+}
+
+pub fn take_exception() -> Option<&'static str> {
+    PENDING_EXCEPTION.with(|e| e.take())
+}
+
+// Natives run Java code with this, e.g. a constructor. It runs as though
+// called by the native's caller, after the native's result has been pushed.
+pub fn invoke(klass: Arc<OtKlass>, m_idx: usize, args: InterpLocalVars) -> () {
+    PENDING_CALL.with(|c| c.set(Some((klass, m_idx, args))));
+}
+
+pub fn take_invoke() -> Option<(Arc<OtKlass>, usize, InterpLocalVars)> {
+    PENDING_CALL.with(|c| c.take())
+}
+
+fn receiver(args: &InterpLocalVars, meth: &str) -> usize {
+    match args.load(0) {
+        JvmValue::ObjRef(v) => v,
+        x => panic!("Non-object value {} of type {} encountered in {}()", x, x.name(), meth)
+    }
+}
+
+// The contents of a java/lang/String
+fn string_value(vm: &Vm, id: usize) -> String {
+    let repo = vm.repo();
+    let klass = repo.lookup_klass(&"java/lang/String".to_string());
+    let heap = vm.heap();
+    let field = |name: &str| match repo.instance_fields(&klass).iter().find(|f| f.get_name() == name) {
+        Some(f) => heap.get_field(id, f.get_offset()),
+        None => panic!("No field {} found on java/lang/String", name),
+    };
+    match (field("value"), field("offset"), field("count")) {
+        (JvmValue::ObjRef(value), JvmValue::Int(offset), JvmValue::Int(count)) => match heap.get_obj(value) {
+            OtObj::VmArrChar { elements, .. } => {
+                String::from_utf16_lossy(&elements[offset as usize..(offset + count) as usize])
+            }
+            obj => panic!("Non-char[] {} seen as the value of String {}", obj, id),
+        },
+        other => panic!("Object {} is not a String: {:?}", id, other),
+    }
+}
+
+// What the Class object passed as receiver stands for
+fn mirrored(vm: &Vm, args: &InterpLocalVars, meth: &str) -> Mirrored {
+    let id = receiver(args, meth);
+    vm.mirrored(id).unwrap_or_else(|| panic!("Object {} seen in {}() is not a Class", id, meth))
+}
+
 ////////////////////////////////////////////
 // java.lang.Object
 
-// getClass()
+pub fn java_lang_Object__getClass(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
+    let repo = vm.repo();
+    let id = receiver(args, "Object.getClass");
+    // Making the mirror may take the heap lock itself
+    let class = repo.type_of(vm.heap().get_obj(id));
+    Some(JvmValue::ObjRef(vm.mirror_for(&class)))
+}
 
-pub fn java_lang_Object__hashcode(args: &InterpLocalVars) -> Option<JvmValue> {
-    // FIXME Proper hashCode algorithm
-    Some(JvmValue::Int(255))
+pub fn java_lang_Object__hashcode(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
+    let id = receiver(args, "Object.hashCode");
+    Some(JvmValue::Int(vm.heap().identity_hash(id)))
 }
 
-// clone()
+// Arrays can always be cloned, other objects if their class is Cloneable
+pub fn java_lang_Object__clone(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
+    let id = receiver(args, "Object.clone");
+    let klass_id = match vm.heap().get_obj(id) {
+        OtObj::VmObj { klassid, .. } => Some(*klassid),
+        _ => None,
+    };
+    let finalizer = match klass_id {
+        Some(klass_id) => {
+            let repo = vm.repo();
+            let klass = repo.klass_by_id(klass_id);
+            if !repo.implements(&klass, "java/lang/Cloneable") {
+                return throw(CLONE_NOT_SUPPORTED_EXCEPTION);
+            }
+            repo.finalizer(&klass)
+        }
+        None => None,
+    };
 
-pub fn java_lang_Object__notify(args: &InterpLocalVars) -> Option<JvmValue> {
-    // NO-OP for now
-    None
+    let mut heap = vm.heap();
+    let copy = match heap.try_clone_obj(id) {
+        Some(copy) => copy,
+        None => return throw(OUT_OF_MEMORY_ERROR),
+    };
+    // A copy of a finalizable object gets finalized too
+    if let Some((klass, method_idx)) = finalizer {
+        heap.register_finalizer(Finalizer { obj: copy, klass, method_idx });
+    }
+    Some(JvmValue::ObjRef(copy))
 }
 
-pub fn java_lang_Object__notifyAll(args: &InterpLocalVars) -> Option<JvmValue> {
-    // NO-OP for now
+pub fn java_lang_Object__notify(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
+    if !monitor::notify(vm, receiver(args, "Object.notify"), false) {
+        return throw(ILLEGAL_MONITOR_STATE_EXCEPTION);
+    }
     None
 }
 
-pub fn java_lang_Object__wait(args: &InterpLocalVars) -> Option<JvmValue> {
-    // NO-OP for now
+pub fn java_lang_Object__notifyAll(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
+    if !monitor::notify(vm, receiver(args, "Object.notifyAll"), true) {
+        return throw(ILLEGAL_MONITOR_STATE_EXCEPTION);
+    }
     None
 }
 
+pub fn java_lang_Object__wait(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
+    let obj = receiver(args, "Object.wait");
+    let timeout = match args.load(1) {
+        JvmValue::Long(0) => None,
+        JvmValue::Long(v) if v > 0 => Some(Duration::from_millis(v as u64)),
+        JvmValue::Long(_) => return throw(ILLEGAL_ARGUMENT_EXCEPTION),
+        x => panic!("Non-long value {} of type {} encountered in Object.wait()", x, x.name())
+    };
+    match crate::thread::wait(vm, obj, timeout) {
+        Ok(()) => None,
+        Err(WaitError::NotOwner) => throw(ILLEGAL_MONITOR_STATE_EXCEPTION),
+        Err(WaitError::Interrupted) => throw(INTERRUPTED_EXCEPTION),
+    }
+}
+
 ////////////////////////////////////////////
 // java.lang.Class
 
 
-pub fn java_lang_Class__getName(args: &InterpLocalVars) -> Option<JvmValue> {
-    let obj = match args.load(0) {
+// Finds a class by dotted name, e.g. java.lang.String or [I, loading it
+// from the classpath if need be, and starts initialising it. Superclasses
+// not initialised yet are, outermost first, before its own <clinit> runs.
+pub fn java_lang_Class__forName(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
+    let repo = vm.repo();
+    let name = match args.load(0) {
+        JvmValue::ObjRef(0) => return throw(NULL_POINTER_EXCEPTION),
+        JvmValue::ObjRef(id) => string_value(vm, id),
+        x => panic!("Non-object value {} of type {} encountered in Class.forName()", x, x.name())
+    };
+    if name.contains('/') {
+        return throw(CLASS_NOT_FOUND_EXCEPTION);
+    }
+    let name = name.replace('.', "/");
+
+    if let Some(element) = name.strip_prefix('[') {
+        let element = element.trim_start_matches('[');
+        let known = match element.strip_prefix('L').and_then(|e| e.strip_suffix(';')) {
+            Some(klass_name) => repo.load_klass(klass_name).is_some(),
+            None => element.len() == 1 && "ZBCSIJFD".contains(element),
+        };
+        if !known {
+            return throw(CLASS_NOT_FOUND_EXCEPTION);
+        }
+        return Some(JvmValue::ObjRef(vm.array_mirror(&name)));
+    }
+    let klass = match repo.load_klass(&name) {
+        Some(k) => k,
+        None => return throw(CLASS_NOT_FOUND_EXCEPTION),
+    };
+    let mirror = vm.mirror(&klass);
+    let mut supers = vec![];
+    let mut current = klass.clone();
+    while current.get_super_name() != current.get_name() {
+        current = repo.lookup_klass(&current.get_super_name());
+        supers.push(current.clone());
+    }
+    // Run through here, as only the one call can be left to the interpreter
+    for k in supers.iter().rev().filter(|k| k.begin_initialization()) {
+        let m_str = k.get_name() + ".<clinit>:()V";
+        if let Some(clinit) = k.get_method_by_name_and_desc(&m_str) {
+            vm.clone().interpret(clinit, &mut InterpLocalVars::of(0));
+        }
+    }
+    if klass.begin_initialization() {
+        if let Some(clinit) = klass.get_method_index(&format!("{}.<clinit>:()V", name)) {
+            invoke(klass, clinit, InterpLocalVars::of(0));
+        }
+    }
+    Some(JvmValue::ObjRef(mirror))
+}
+
+// A new instance, made by the class's no-arg constructor, which runs once
+// newInstance() returns
+pub fn java_lang_Class__newInstance(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
+    let repo = vm.repo();
+    let klass = match mirrored(vm, args, "Class.newInstance") {
+        Mirrored::Klass(id) => repo.klass_by_id(id),
+        Mirrored::Array(_) => return throw(INSTANTIATION_EXCEPTION),
+    };
+    if klass.is_interface() || klass.is_abstract() {
+        return throw(INSTANTIATION_EXCEPTION);
+    }
+    let init = match klass.get_method_index(&format!("{}.<init>:()V", klass.get_name())) {
+        Some(init) => init,
+        None => return throw(INSTANTIATION_EXCEPTION),
+    };
+    if klass.get_method(init).is_private() {
+        return throw(ILLEGAL_ACCESS_EXCEPTION);
+    }
+    repo.instance_fields(&klass);
+    let finalizer = repo.finalizer(&klass);
+
+    let mut heap = vm.heap();
+    let obj = match heap.try_allocate_obj(&klass) {
+        Some(obj) => obj,
+        None => return throw(OUT_OF_MEMORY_ERROR),
+    };
+    if let Some((klass, method_idx)) = finalizer {
+        heap.register_finalizer(Finalizer { obj, klass, method_idx });
+    }
+    let mut vars = InterpLocalVars::of(1);
+    vars.store(0, JvmValue::ObjRef(obj));
+    invoke(klass, init, vars);
+    Some(JvmValue::ObjRef(obj))
+}
+
+// Dotted, as Java code sees it, e.g. java.lang.String or [Ljava.lang.String;
+pub fn java_lang_Class__getName(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
+    let repo = vm.repo();
+    let name = match mirrored(vm, args, "Class.getName") {
+        Mirrored::Klass(id) => repo.klass_by_id(id).get_name(),
+        Mirrored::Array(desc) => desc,
+    };
+    Some(JvmValue::ObjRef(vm.new_string(&name.replace('/', "."))))
+}
+
+// null for Object and interfaces, Object for array types
+pub fn java_lang_Class__getSuperclass(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
+    let repo = vm.repo();
+    let sup = match mirrored(vm, args, "Class.getSuperclass") {
+        Mirrored::Klass(id) => {
+            let klass = repo.klass_by_id(id);
+            if klass.is_interface() || klass.get_super_name() == klass.get_name() {
+                0
+            } else {
+                vm.mirror_by_name(&klass.get_super_name())
+            }
+        }
+        Mirrored::Array(_) => vm.mirror_by_name("java/lang/Object"),
+    };
+    Some(JvmValue::ObjRef(sup))
+}
+
+// Only those declared by the class itself, in the order it declares them
+pub fn java_lang_Class__getInterfaces(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
+    let repo = vm.repo();
+    let interfaces: Vec<usize> = match mirrored(vm, args, "Class.getInterfaces") {
+        Mirrored::Klass(id) => repo
+            .klass_by_id(id)
+            .get_interfaces()
+            .iter()
+            .map(|name| vm.mirror_by_name(name))
+            .collect(),
+        Mirrored::Array(_) => Vec::new(),
+    };
+    let class = repo.lookup_klass(&"java/lang/Class".to_string());
+
+    let mut heap = vm.heap();
+    let array = heap.allocate_obj_arr(&class, interfaces.len() as i32);
+    for (i, &iface) in interfaces.iter().enumerate() {
+        heap.obj_arr_store(array, i as i32, iface);
+    }
+    Some(JvmValue::ObjRef(array))
+}
+
+pub fn java_lang_Class__isInterface(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
+    let repo = vm.repo();
+    let interface = match mirrored(vm, args, "Class.isInterface") {
+        Mirrored::Klass(id) => repo.klass_by_id(id).is_interface(),
+        Mirrored::Array(_) => false,
+    };
+    Some(JvmValue::Boolean(interface))
+}
+
+////////////////////////////////////////////
+// java.lang.Thread
+
+pub fn java_lang_Thread__currentThread(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
+    Some(JvmValue::ObjRef(crate::thread::current_thread(vm)))
+}
+
+pub fn java_lang_Thread__yield(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
+    crate::thread::yield_now();
+    None
+}
+
+pub fn java_lang_Thread__sleep(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
+    let millis = match args.load(0) {
+        JvmValue::Long(v) if v >= 0 => v as u64,
+        JvmValue::Long(_) => return throw(ILLEGAL_ARGUMENT_EXCEPTION),
+        x => panic!("Non-long value {} of type {} encountered in Thread.sleep()", x, x.name())
+    };
+    match crate::thread::sleep(vm, millis) {
+        Ok(()) => None,
+        Err(_) => throw(INTERRUPTED_EXCEPTION),
+    }
+}
+
+pub fn java_lang_Thread__start(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
+    if !crate::thread::start(vm, receiver(args, "Thread.start")) {
+        return throw(ILLEGAL_THREAD_STATE_EXCEPTION);
+    }
+    None
+}
+
+pub fn java_lang_Thread__isAlive(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
+    Some(JvmValue::Boolean(crate::thread::is_alive(vm, receiver(args, "Thread.isAlive"))))
+}
+
+// Intrinsics for Thread() and Thread(Runnable)
+pub fn java_lang_Thread__init(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
+    crate::thread::init_thread(vm, receiver(args, "Thread.<init>"), 0);
+    None
+}
+
+pub fn java_lang_Thread__init_runnable(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
+    let target = match args.load(1) {
         JvmValue::ObjRef(v) => v,
-        x => panic!("Non-object value {} of type {} encountered in Class.getName()", x, x.name())
+        x => panic!("Non-object value {} of type {} encountered in Thread.<init>()", x, x.name())
     };
-    // Lookup object in heap...
+    crate::thread::init_thread(vm, receiver(args, "Thread.<init>"), target);
+    None
+}
 
-    // FIXME Currently returns null
-    Some(JvmValue::ObjRef(0))
+// Intrinsic, as the library's version doesn't wake a waiting thread
+pub fn java_lang_Thread__interrupt(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
+    crate::thread::interrupt(vm, receiver(args, "Thread.interrupt"));
+    None
 }
 
 ////////////////////////////////////////////
 // java.lang.Compiler
 
-pub fn java_lang_Compiler__compileClass(args: &InterpLocalVars) -> Option<JvmValue> {
+pub fn java_lang_Compiler__compileClass(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
     Some(JvmValue::Boolean(true))
 }
 
-pub fn java_lang_Compiler__compileClasses(args: &InterpLocalVars) -> Option<JvmValue> {
+pub fn java_lang_Compiler__compileClasses(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
     Some(JvmValue::Boolean(true))
 }
 
-pub fn java_lang_Compiler__enable(args: &InterpLocalVars) -> Option<JvmValue> {
+pub fn java_lang_Compiler__enable(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
     // DUMMY
     None
 }
 
-pub fn java_lang_Compiler__disable(args: &InterpLocalVars) -> Option<JvmValue> {
+pub fn java_lang_Compiler__disable(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
     // DUMMY
     None
 }
@@ -71,32 +392,53 @@ pub fn java_lang_Compiler__disable(args: &InterpLocalVars) -> Option<JvmValue> {
 // java.lang.Runtime
 
 
-pub fn java_lang_Runtime__freeMemory(args: &InterpLocalVars) -> Option<JvmValue> {
-    // DUMMY
-    Some(JvmValue::Long(64 * 1024 * 1024))
+// Intrinsic for Runtime.getRuntime(), as the static initialiser that
+// creates the Runtime isn't run
+pub fn java_lang_Runtime__getRuntime(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
+    let repo = vm.repo();
+    let klass = repo.lookup_klass(&"java/lang/Runtime".to_string());
+    let field = klass
+        .get_static_field_by_name_and_desc(&"java/lang/Runtime.currentRuntime:Ljava/lang/Runtime;".to_string())
+        .expect("No currentRuntime field on java/lang/Runtime")
+        .clone();
+    if let JvmValue::ObjRef(0) = klass.get_static(&field) {
+        repo.instance_fields(&klass);
+        let id = vm.heap().allocate_obj(&klass);
+        klass.put_static(&field, JvmValue::ObjRef(id));
+    }
+    Some(klass.get_static(&field))
 }
 
-pub fn java_lang_Runtime__totalMemory(args: &InterpLocalVars) -> Option<JvmValue> {
-    // DUMMY
-    Some(JvmValue::Long(64 * 1024 * 1024))
+pub fn java_lang_Runtime__freeMemory(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
+    let heap = vm.heap();
+    Some(JvmValue::Long(heap.total_bytes().saturating_sub(heap.used_bytes()) as i64))
 }
 
-pub fn java_lang_Runtime__gc(args: &InterpLocalVars) -> Option<JvmValue> {
-    // DUMMY
+pub fn java_lang_Runtime__totalMemory(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
+    Some(JvmValue::Long(vm.heap().total_bytes() as i64))
+}
+
+// The interpreter collects before going back to the caller
+pub fn java_lang_Runtime__gc(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
+    crate::gc::request(vm);
     None
 }
 
-pub fn java_lang_Runtime__runFinalization(args: &InterpLocalVars) -> Option<JvmValue> {
-    // DUMMY
+// The interpreter runs every queued finalizer before going back round to
+// the caller, so sending it there is enough
+pub fn java_lang_Runtime__runFinalization(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
+    if crate::gc::finalization_pending(vm) {
+        crate::thread::yield_to_interpreter();
+    }
     None
 }
 
-pub fn java_lang_Runtime__traceInstructions(args: &InterpLocalVars) -> Option<JvmValue> {
+pub fn java_lang_Runtime__traceInstructions(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
     // DUMMY
     None
 }
 
-pub fn java_lang_Runtime__traceMethodCalls(args: &InterpLocalVars) -> Option<JvmValue> {
+pub fn java_lang_Runtime__traceMethodCalls(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
     // DUMMY
     None
 }
@@ -105,23 +447,49 @@ pub fn java_lang_Runtime__traceMethodCalls(args: &InterpLocalVars) -> Option<Jvm
 // java.lang.System
 
 
-pub fn java_lang_System__currentTimeMillis(args: &InterpLocalVars) -> Option<JvmValue> {
+pub fn java_lang_System__currentTimeMillis(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
     let millis = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)
         .expect("SystemTime before UNIX EPOCH!")
         .as_millis();
     Some(JvmValue::Long(millis as i64))
 }
 
-pub fn java_lang_System__arraycopy(args: &InterpLocalVars) -> Option<JvmValue> {
-    // NO-OP for now
-    None
+// Object.hashCode() even where it is overridden, and 0 for null
+pub fn java_lang_System__identityHashCode(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
+    let hash = match receiver(args, "System.identityHashCode") {
+        0 => 0,
+        id => vm.heap().identity_hash(id),
+    };
+    Some(JvmValue::Int(hash))
+}
+
+pub fn java_lang_System__arraycopy(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
+    let array = |i: u8| match args.load(i) {
+        JvmValue::ObjRef(v) => v,
+        x => panic!("Non-object value {} of type {} encountered in System.arraycopy()", x, x.name())
+    };
+    let int = |i: u8| match args.load(i) {
+        JvmValue::Int(v) => v,
+        x => panic!("Non-int value {} of type {} encountered in System.arraycopy()", x, x.name())
+    };
+    let (src, src_pos, dest, dest_pos, length) = (array(0), int(1), array(2), int(3), int(4));
+    if src == 0 || dest == 0 {
+        return throw(NULL_POINTER_EXCEPTION);
+    }
+
+    let repo = vm.repo();
+    match vm.heap().arraycopy(repo, src, src_pos, dest, dest_pos, length) {
+        Ok(()) => None,
+        Err(ArrayCopyError::IndexOutOfBounds) => throw(ARRAY_INDEX_OUT_OF_BOUNDS_EXCEPTION),
+        Err(ArrayCopyError::ArrayStore) => throw(ARRAY_STORE_EXCEPTION),
+    }
 }
 
 ////////////////////////////////////////////
 // java.lang.Math simple maths methods
 
 
-pub fn java_lang_Math__sin(args: &InterpLocalVars) -> Option<JvmValue> {
+pub fn java_lang_Math__sin(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
     let d = match args.load(0) {
         JvmValue::Double(v) => v,
         x => panic!("Non-double value {} of type {} encountered in Math.sin", x, x.name())
@@ -129,7 +497,7 @@ pub fn java_lang_Math__sin(args: &InterpLocalVars) -> Option<JvmValue> {
     Some(JvmValue::Double(d.sin()))
 }
 
-pub fn java_lang_Math__cos(args: &InterpLocalVars) -> Option<JvmValue> {
+pub fn java_lang_Math__cos(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
     let d = match args.load(0) {
         JvmValue::Double(v) => v,
         x => panic!("Non-double value {} of type {} encountered in Math.cos", x, x.name())
@@ -137,7 +505,7 @@ pub fn java_lang_Math__cos(args: &InterpLocalVars) -> Option<JvmValue> {
     Some(JvmValue::Double(d.cos()))
 }
 
-pub fn java_lang_Math__tan(args: &InterpLocalVars) -> Option<JvmValue> {
+pub fn java_lang_Math__tan(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
     let d = match args.load(0) {
         JvmValue::Double(v) => v,
         x => panic!("Non-double value {} of type {} encountered in Math.tan", x, x.name())
@@ -145,7 +513,7 @@ pub fn java_lang_Math__tan(args: &InterpLocalVars) -> Option<JvmValue> {
     Some(JvmValue::Double(d.tan()))
 }
 
-pub fn java_lang_Math__asin(args: &InterpLocalVars) -> Option<JvmValue> {
+pub fn java_lang_Math__asin(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
     let d = match args.load(0) {
         JvmValue::Double(v) => v,
         x => panic!("Non-double value {} of type {} encountered in Math.asin", x, x.name())
@@ -153,7 +521,7 @@ pub fn java_lang_Math__asin(args: &InterpLocalVars) -> Option<JvmValue> {
     Some(JvmValue::Double(d.asin()))
 }
 
-pub fn java_lang_Math__acos(args: &InterpLocalVars) -> Option<JvmValue> {
+pub fn java_lang_Math__acos(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
     let d = match args.load(0) {
         JvmValue::Double (v) => v,
         x => panic!("Non-double value {} of type {} encountered in Math.acos", x, x.name())
@@ -162,7 +530,7 @@ pub fn java_lang_Math__acos(args: &InterpLocalVars) -> Option<JvmValue> {
     Some(JvmValue::Double(d.acos()))
 }
 
-pub fn java_lang_Math__atan(args: &InterpLocalVars) -> Option<JvmValue> {
+pub fn java_lang_Math__atan(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
     let d = match args.load(0) {
         JvmValue::Double (v) => v,
         x => panic!("Non-double value {} of type {} encountered in Math.atan", x, x.name())
@@ -171,7 +539,7 @@ pub fn java_lang_Math__atan(args: &InterpLocalVars) -> Option<JvmValue> {
     Some(JvmValue::Double(d.atan()))
 }
 
-pub fn java_lang_Math__exp(args: &InterpLocalVars) -> Option<JvmValue> {
+pub fn java_lang_Math__exp(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
     let d = match args.load(0) {
         JvmValue::Double (v) => v,
         x => panic!("Non-double value {} of type {} encountered in Math.exp", x, x.name())
@@ -180,7 +548,7 @@ pub fn java_lang_Math__exp(args: &InterpLocalVars) -> Option<JvmValue> {
     Some(JvmValue::Double(d.exp()))
 }
 
-pub fn java_lang_Math__log(args: &InterpLocalVars) -> Option<JvmValue> {
+pub fn java_lang_Math__log(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
     let d = match args.load(0) {
         JvmValue::Double (v) => v,
         x => panic!("Non-double value {} of type {} encountered in Math.log", x, x.name())
@@ -189,7 +557,7 @@ pub fn java_lang_Math__log(args: &InterpLocalVars) -> Option<JvmValue> {
     Some(JvmValue::Double(d.ln()))
 }
 
-pub fn java_lang_Math__sqrt(args: &InterpLocalVars) -> Option<JvmValue> {
+pub fn java_lang_Math__sqrt(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
     let d = match args.load(0) {
         JvmValue::Double (v) => v,
         x => panic!("Non-double value {} of type {} encountered in Math.sqrt", x, x.name())
@@ -198,7 +566,7 @@ pub fn java_lang_Math__sqrt(args: &InterpLocalVars) -> Option<JvmValue> {
     Some(JvmValue::Double(d.sqrt()))
 }
 
-pub fn java_lang_Math__ceil(args: &InterpLocalVars) -> Option<JvmValue> {
+pub fn java_lang_Math__ceil(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
     let d = match args.load(0) {
         JvmValue::Double (v) => v,
         x => panic!("Non-double value {} of type {} encountered in Math.ceil", x, x.name())
@@ -207,7 +575,7 @@ pub fn java_lang_Math__ceil(args: &InterpLocalVars) -> Option<JvmValue> {
     Some(JvmValue::Double(d.ceil()))
 }
 
-pub fn java_lang_Math__floor(args: &InterpLocalVars) -> Option<JvmValue> {
+pub fn java_lang_Math__floor(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
     let d = match args.load(0) {
         JvmValue::Double (v) => v,
         x => panic!("Non-double value {} of type {} encountered in Math.floor", x, x.name())
@@ -220,13 +588,13 @@ pub fn java_lang_Math__floor(args: &InterpLocalVars) -> Option<JvmValue> {
 
 //public static final native double rint(double);
 
-pub fn java_lang_Math__atan2(args: &InterpLocalVars) -> Option<JvmValue> {
+pub fn java_lang_Math__atan2(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
     let base = match args.load(0) {
         JvmValue::Double (v) => v,
         x => panic!("Non-double value {} of type {} encountered in Math.atan2", x, x.name())
     };
 
-    let other = match args.load(0) {
+    let other = match args.load(2) {
         JvmValue::Double (v) => v,
         x => panic!("Non-double value {} of type {} encountered in Math.atan2", x, x.name())
     };
@@ -235,13 +603,13 @@ pub fn java_lang_Math__atan2(args: &InterpLocalVars) -> Option<JvmValue> {
     Some(JvmValue::Double(base.atan2(other)))
 }
 
-pub fn java_lang_Math__pow(args: &InterpLocalVars) -> Option<JvmValue> {
+pub fn java_lang_Math__pow(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
     let base = match args.load(0) {
         JvmValue::Double (v) => v,
         x => panic!("Non-double value {} of type {} encountered in Math.pow", x, x.name())
     };
 
-    let raise = match args.load(0) {
+    let raise = match args.load(2) {
         JvmValue::Double (v) => v,
         x => panic!("Non-double value {} of type {} encountered in Math.pow", x, x.name())
     };
@@ -261,11 +629,11 @@ pub fn java_lang_Math__pow(args: &InterpLocalVars) -> Option<JvmValue> {
 ////////////////////////////////////////////
 
 // FIXME System -> Runtime -> Shutdown
-pub fn java_lang_Shutdown__exit(args: &InterpLocalVars) -> Option<JvmValue> {
+pub fn java_lang_Shutdown__exit(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
     Some(JvmValue::Int(255))
 }
 
-pub fn java_io_FileDescriptor__initSystemFD(args: &InterpLocalVars) -> Option<JvmValue> {
+pub fn java_io_FileDescriptor__initSystemFD(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
     let obj = args.load(0);
     let fd = args.load(1);
 
@@ -274,7 +642,7 @@ pub fn java_io_FileDescriptor__initSystemFD(args: &InterpLocalVars) -> Option<Jv
     Some(obj)
 }
 
-// pub fn java_lang_System__nanoTime(args: &InterpLocalVars) -> Option<JvmValue> {
+// pub fn java_lang_System__nanoTime(vm: &Vm, args: &InterpLocalVars) -> Option<JvmValue> {
 //     let millis = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
 //         Ok(n) => n.as_millis(),
 //         Err(_) => panic!("SystemTime before UNIX EPOCH!"),
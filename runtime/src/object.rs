@@ -1,9 +1,18 @@
 use std::fmt;
 use std::cell::Cell;
+use std::mem::size_of;
 
 use crate::JvmValue;
 use crate::OtField;
 
+// Layout of the mark word, see monitor_owner()
+const COUNT_BITS: u32 = 20;
+const OWNER_BITS: u32 = 20;
+const HASH_SHIFT: u32 = COUNT_BITS + OWNER_BITS;
+const COUNT_MASK: u64 = (1 << COUNT_BITS) - 1;
+const MONITOR_MASK: u64 = (1 << HASH_SHIFT) - 1;
+pub const HASH_MASK: u64 = (1 << (64 - HASH_SHIFT)) - 1;
+
 // If we need this, we'd better impl it manually
 // #[derive(Debug)]
 pub enum OtObj {
@@ -27,6 +36,42 @@ pub enum OtObj {
         length: i32,
         elements: Vec<i64>,
     },
+    VmArrBoolean {
+        id: usize,
+        mark: u64,
+        klassid: usize,
+        length: i32,
+        elements: Vec<bool>,
+    },
+    VmArrByte {
+        id: usize,
+        mark: u64,
+        klassid: usize,
+        length: i32,
+        elements: Vec<i8>,
+    },
+    VmArrChar {
+        id: usize,
+        mark: u64,
+        klassid: usize,
+        length: i32,
+        elements: Vec<u16>,
+    },
+    VmArrShort {
+        id: usize,
+        mark: u64,
+        klassid: usize,
+        length: i32,
+        elements: Vec<i16>,
+    },
+    // klassid is that of the element type, elements are object ids
+    VmArrObj {
+        id: usize,
+        mark: u64,
+        klassid: usize,
+        length: i32,
+        elements: Vec<usize>,
+    },
 }
 
 impl OtObj {
@@ -52,54 +97,180 @@ impl OtObj {
         }
     }
 
-    pub fn put_field(&self, offset : usize, val: JvmValue) -> () {
-        let (kid, fields) = match self {
-            OtObj::VmObj {
-                id: _,
-                mark: _,
-                klassid: id,
-                fields: fs,
-            } => (id, fs),
-            _ => panic!("Not an object"),
+    // Arrays of the types that share int as their computational type,
+    // keyed by descriptor letter
+    pub fn narrow_arr_of(letter: char, size: i32, obj_id: usize) -> OtObj {
+        let sz = size as usize;
+        // FIXME Need Object in the mix soon...
+        match letter {
+            'Z' => OtObj::VmArrBoolean {
+                id: obj_id,
+                mark: 0u64,
+                klassid: 2,
+                length: size,
+                elements: vec![false; sz],
+            },
+            'B' => OtObj::VmArrByte {
+                id: obj_id,
+                mark: 0u64,
+                klassid: 2,
+                length: size,
+                elements: vec![0; sz],
+            },
+            'C' => OtObj::VmArrChar {
+                id: obj_id,
+                mark: 0u64,
+                klassid: 2,
+                length: size,
+                elements: vec![0; sz],
+            },
+            'S' => OtObj::VmArrShort {
+                id: obj_id,
+                mark: 0u64,
+                klassid: 2,
+                length: size,
+                elements: vec![0; sz],
+            },
+            _ => panic!("Not a narrow primitive array type: {}", letter),
+        }
+    }
+
+    // An array of nulls, to hold instances of klass_id
+    pub fn obj_arr_of(klass_id: usize, size: i32, obj_id: usize) -> OtObj {
+        OtObj::VmArrObj {
+            id: obj_id,
+            mark: 0u64,
+            klassid: klass_id,
+            length: size,
+            elements: vec![0; size as usize],
+        }
+    }
+
+    // Bytes an object with this many fields takes up in the heap
+    pub fn obj_size(fields: usize) -> usize {
+        size_of::<OtObj>() + fields * size_of::<Cell<JvmValue>>()
+    }
+
+    // Bytes an array takes up in the heap, by element descriptor letter
+    pub fn arr_size(letter: char, length: i32) -> usize {
+        let width = match letter {
+            'Z' | 'B' => 1,
+            'C' | 'S' => 2,
+            'I' => 4,
+            'J' => 8,
+            'L' => size_of::<usize>(),
+            _ => panic!("Not an array type: {}", letter),
         };
-        // Get klass
-        dbg!("Made it to object get_field_offset");
-        // Lookup offset in klass
-        // let offset = REPO.lock().get_field_offset(*kid, f);
+        size_of::<OtObj>() + length.max(0) as usize * width
+    }
+
+    pub fn size(&self) -> usize {
         match self {
-            OtObj::VmObj {
-                id: _,
-                mark: _,
-                klassid: _,
-                fields: fs,
-            } => {
-                fs[offset].set(val);
-            }
+            OtObj::VmObj { fields, .. } => OtObj::obj_size(fields.len()),
+            OtObj::VmArrInt { length, .. } => OtObj::arr_size('I', *length),
+            OtObj::VmArrLong { length, .. } => OtObj::arr_size('J', *length),
+            OtObj::VmArrBoolean { length, .. } => OtObj::arr_size('Z', *length),
+            OtObj::VmArrByte { length, .. } => OtObj::arr_size('B', *length),
+            OtObj::VmArrChar { length, .. } => OtObj::arr_size('C', *length),
+            OtObj::VmArrShort { length, .. } => OtObj::arr_size('S', *length),
+            OtObj::VmArrObj { length, .. } => OtObj::arr_size('L', *length),
+        }
+    }
+
+    // Fields sit in cells, so are stored to in place through a shared
+    // reference
+    pub fn put_field(&self, offset: usize, val: JvmValue) -> () {
+        match self {
+            OtObj::VmObj { fields, .. } => fields[offset].set(val),
             _ => panic!("Not an object"),
-        };
+        }
     }
 
-    pub fn get_field_value(&self, offset : usize) -> JvmValue {
-        let (kid, fields) = match self {
-            OtObj::VmObj {
-                id: _,
-                mark: _,
-                klassid: id,
-                fields: fs,
-            } => (id, fs),
+    pub fn get_field_value(&self, offset: usize) -> JvmValue {
+        match self {
+            OtObj::VmObj { fields, .. } => match fields.get(offset) {
+                Some(v) => v.get(),
+                None => panic!("Fields should hold a value"),
+            },
             _ => panic!("Not an object"),
-        };
-        // Get klass
-        dbg!("Made it to object get_field_offset");
-        match fields.get(offset) {
-            Some(v) => {
-                // let v = cell.get();
-                (*v).get()
-            }
-            None => panic!("Fields should hold a value"),
         }
     }
 
+    // A shallow copy with a new id, and a mark word of its own
+    pub fn copy_as(&self, obj_id: usize) -> OtObj {
+        match self {
+            OtObj::VmObj { klassid, fields, .. } => OtObj::VmObj {
+                id: obj_id,
+                mark: 0u64,
+                klassid: *klassid,
+                fields: fields.clone(),
+            },
+            OtObj::VmArrInt { klassid, length, elements, .. } => OtObj::VmArrInt {
+                id: obj_id,
+                mark: 0u64,
+                klassid: *klassid,
+                length: *length,
+                elements: elements.clone(),
+            },
+            OtObj::VmArrLong { klassid, length, elements, .. } => OtObj::VmArrLong {
+                id: obj_id,
+                mark: 0u64,
+                klassid: *klassid,
+                length: *length,
+                elements: elements.clone(),
+            },
+            OtObj::VmArrBoolean { klassid, length, elements, .. } => OtObj::VmArrBoolean {
+                id: obj_id,
+                mark: 0u64,
+                klassid: *klassid,
+                length: *length,
+                elements: elements.clone(),
+            },
+            OtObj::VmArrByte { klassid, length, elements, .. } => OtObj::VmArrByte {
+                id: obj_id,
+                mark: 0u64,
+                klassid: *klassid,
+                length: *length,
+                elements: elements.clone(),
+            },
+            OtObj::VmArrChar { klassid, length, elements, .. } => OtObj::VmArrChar {
+                id: obj_id,
+                mark: 0u64,
+                klassid: *klassid,
+                length: *length,
+                elements: elements.clone(),
+            },
+            OtObj::VmArrShort { klassid, length, elements, .. } => OtObj::VmArrShort {
+                id: obj_id,
+                mark: 0u64,
+                klassid: *klassid,
+                length: *length,
+                elements: elements.clone(),
+            },
+            OtObj::VmArrObj { klassid, length, elements, .. } => OtObj::VmArrObj {
+                id: obj_id,
+                mark: 0u64,
+                klassid: *klassid,
+                length: *length,
+                elements: elements.clone(),
+            },
+        }
+    }
+
+    pub fn with_id(mut self, obj_id: usize) -> OtObj {
+        match &mut self {
+            OtObj::VmObj { id, .. } => *id = obj_id,
+            OtObj::VmArrInt { id, .. } => *id = obj_id,
+            OtObj::VmArrLong { id, .. } => *id = obj_id,
+            OtObj::VmArrBoolean { id, .. } => *id = obj_id,
+            OtObj::VmArrByte { id, .. } => *id = obj_id,
+            OtObj::VmArrChar { id, .. } => *id = obj_id,
+            OtObj::VmArrShort { id, .. } => *id = obj_id,
+            OtObj::VmArrObj { id, .. } => *id = obj_id,
+        }
+        self
+    }
+
     pub fn get_null() -> OtObj {
         OtObj::VmObj {
             id: 0,
@@ -119,101 +290,311 @@ impl OtObj {
 
     pub fn get_id(&self) -> usize {
         match *self {
-            OtObj::VmObj {
-                id: i,
-                mark: _,
-                klassid: _,
-                fields: _,
-            } => i,
-            OtObj::VmArrInt {
-                id: i,
-                mark: _,
-                klassid: _,
-                length: _,
-                elements: _,
-            } => i,
-            OtObj::VmArrLong {
-                id: i,
-                mark: _,
-                klassid: _,
-                length: _,
-                elements: _,
-            } => i,
+            OtObj::VmObj { id, .. } => id,
+            OtObj::VmArrInt { id, .. } => id,
+            OtObj::VmArrLong { id, .. } => id,
+            OtObj::VmArrBoolean { id, .. } => id,
+            OtObj::VmArrByte { id, .. } => id,
+            OtObj::VmArrChar { id, .. } => id,
+            OtObj::VmArrShort { id, .. } => id,
+            OtObj::VmArrObj { id, .. } => id,
         }
     }
 
     pub fn get_mark(&self) -> u64 {
         match *self {
-            OtObj::VmObj {
-                id: _,
-                mark: m,
-                klassid: _,
-                fields: _,
-            } => m,
-            OtObj::VmArrInt {
-                id: _,
-                mark: m,
-                klassid: _,
-                length: _,
-                elements: _,
-            } => m,
-            OtObj::VmArrLong {
-                id: _,
-                mark: m,
-                klassid: _,
-                length: _,
-                elements: _,
-            } => m,
+            OtObj::VmObj { mark, .. } => mark,
+            OtObj::VmArrInt { mark, .. } => mark,
+            OtObj::VmArrLong { mark, .. } => mark,
+            OtObj::VmArrBoolean { mark, .. } => mark,
+            OtObj::VmArrByte { mark, .. } => mark,
+            OtObj::VmArrChar { mark, .. } => mark,
+            OtObj::VmArrShort { mark, .. } => mark,
+            OtObj::VmArrObj { mark, .. } => mark,
+        }
+    }
+
+    fn mark_mut(&mut self) -> &mut u64 {
+        match self {
+            OtObj::VmObj { mark, .. } => mark,
+            OtObj::VmArrInt { mark, .. } => mark,
+            OtObj::VmArrLong { mark, .. } => mark,
+            OtObj::VmArrBoolean { mark, .. } => mark,
+            OtObj::VmArrByte { mark, .. } => mark,
+            OtObj::VmArrChar { mark, .. } => mark,
+            OtObj::VmArrShort { mark, .. } => mark,
+            OtObj::VmArrObj { mark, .. } => mark,
+        }
+    }
+
+    // The mark word holds the object's monitor and identity hash: the
+    // recursion count in the bottom 20 bits, the owning thread's id in the
+    // next 20 and the hash in the top 24. A monitor of 0 means nobody holds
+    // it, a hash of 0 that there isn't one yet.
+
+    fn monitor_word(&self) -> u64 {
+        self.get_mark() & MONITOR_MASK
+    }
+
+    pub fn monitor_owner(&self) -> u32 {
+        (self.monitor_word() >> COUNT_BITS) as u32
+    }
+
+    pub fn monitor_count(&self) -> u32 {
+        (self.get_mark() & COUNT_MASK) as u32
+    }
+
+    // Replaces the monitor, keeping the hash
+    fn set_monitor(&mut self, thread: u32, count: u32) -> () {
+        assert!((thread as u64) < 1 << OWNER_BITS, "Thread id {} too big for a mark word", thread);
+        assert!((count as u64) <= COUNT_MASK, "Monitor entered too many times");
+        let mark = self.mark_mut();
+        *mark = (*mark & !MONITOR_MASK) | (thread as u64) << COUNT_BITS | count as u64;
+    }
+
+    // Takes the monitor for thread, or takes it again if thread already
+    // holds it. Returns false if another thread holds it.
+    pub fn try_monitor_enter(&mut self, thread: u32) -> bool {
+        if self.monitor_word() == 0 {
+            self.set_monitor(thread, 1);
+            true
+        } else if self.monitor_owner() == thread {
+            self.set_monitor(thread, self.monitor_count() + 1);
+            true
+        } else {
+            false
+        }
+    }
+
+    // Returns None if thread doesn't hold the monitor, otherwise whether
+    // this exit released it altogether
+    pub fn monitor_exit(&mut self, thread: u32) -> Option<bool> {
+        if self.monitor_word() == 0 || self.monitor_owner() != thread {
+            return None;
+        }
+        let count = self.monitor_count() - 1;
+        if count == 0 {
+            self.set_monitor(0, 0);
+            Some(true)
+        } else {
+            self.set_monitor(thread, count);
+            Some(false)
+        }
+    }
+
+    // Lets go of the monitor however many times thread has entered it,
+    // for Object.wait(). Returns that count, or None if thread doesn't
+    // hold the monitor.
+    pub fn monitor_release(&mut self, thread: u32) -> Option<u32> {
+        if self.monitor_word() == 0 || self.monitor_owner() != thread {
+            return None;
+        }
+        let count = self.monitor_count();
+        self.set_monitor(0, 0);
+        Some(count)
+    }
+
+    // Takes the monitor back after monitor_release(), if it is free
+    pub fn try_monitor_restore(&mut self, thread: u32, count: u32) -> bool {
+        if self.monitor_word() != 0 {
+            return false;
+        }
+        self.set_monitor(thread, count);
+        true
+    }
+
+    // The identity hash, which make() comes up with the first time it is
+    // asked for. It moves with the object, so stays the same for life.
+    pub fn identity_hash<F: FnOnce() -> u32>(&mut self, make: F) -> i32 {
+        let mark = self.mark_mut();
+        if *mark >> HASH_SHIFT == 0 {
+            let hash = make() as u64 & HASH_MASK;
+            assert!(hash != 0, "Identity hash must not be 0");
+            *mark |= hash << HASH_SHIFT;
+        }
+        (*mark >> HASH_SHIFT) as i32
+    }
+
+    // Objects this one refers to, for the collector to trace through.
+    pub fn refs(&self) -> Vec<usize> {
+        match self {
+            OtObj::VmObj { fields, .. } => fields
+                .iter()
+                .filter_map(|f| match f.get() {
+                    JvmValue::ObjRef(id) if id != 0 => Some(id),
+                    _ => None,
+                })
+                .collect(),
+            OtObj::VmArrObj { elements, .. } => elements.iter().copied().filter(|&id| id != 0).collect(),
+            _ => Vec::new(),
         }
     }
 
     pub fn get_klassid(&self) -> usize {
         match *self {
-            OtObj::VmObj {
-                id: _,
-                mark: _,
-                klassid: k,
-                fields: _,
-            } => k,
-            OtObj::VmArrInt {
-                id: _,
-                mark: _,
-                klassid: k,
-                length: _,
-                elements: _,
-            } => k,
-            OtObj::VmArrLong {
-                id: _,
-                mark: _,
-                klassid: k,
-                length: _,
-                elements: _,
-            } => k,
+            OtObj::VmObj { klassid, .. } => klassid,
+            OtObj::VmArrInt { klassid, .. } => klassid,
+            OtObj::VmArrLong { klassid, .. } => klassid,
+            OtObj::VmArrBoolean { klassid, .. } => klassid,
+            OtObj::VmArrByte { klassid, .. } => klassid,
+            OtObj::VmArrChar { klassid, .. } => klassid,
+            OtObj::VmArrShort { klassid, .. } => klassid,
+            OtObj::VmArrObj { klassid, .. } => klassid,
         }
     }
 
     pub fn length(&self) -> i32 {
         match *self {
-            OtObj::VmObj {
-                id: _,
-                mark: _,
-                klassid: _,
-                fields: _,
-            } => panic!("Attempted to take the length of a normal object!"),
-            OtObj::VmArrInt {
-                id: _,
-                mark: _,
-                klassid: _,
-                length: l,
-                elements: _,
-            } => l,
-            OtObj::VmArrLong {
-                id: _,
-                mark: _,
-                klassid: _,
-                length: l,
-                elements: _,
-            } => l,
+            OtObj::VmObj { .. } => panic!("Attempted to take the length of a normal object!"),
+            OtObj::VmArrInt { length, .. } => length,
+            OtObj::VmArrLong { length, .. } => length,
+            OtObj::VmArrBoolean { length, .. } => length,
+            OtObj::VmArrByte { length, .. } => length,
+            OtObj::VmArrChar { length, .. } => length,
+            OtObj::VmArrShort { length, .. } => length,
+            OtObj::VmArrObj { length, .. } => length,
+        }
+    }
+
+    // Loads an element of a boolean, byte, char or short array, widened to int
+    pub fn narrow_arr_load(&self, pos: i32) -> JvmValue {
+        let p = pos as usize;
+        let v = match self {
+            OtObj::VmArrBoolean { elements, .. } => JvmValue::Boolean(elements[p]),
+            OtObj::VmArrByte { elements, .. } => JvmValue::Byte(elements[p]),
+            OtObj::VmArrChar { elements, .. } => JvmValue::Char(elements[p]),
+            OtObj::VmArrShort { elements, .. } => JvmValue::Short(elements[p]),
+            _ => panic!("Not a boolean, byte, char or short array: {}", self),
+        };
+        v.to_computational()
+    }
+
+    pub fn iastore(&mut self, pos: i32, v: i32) {
+        match self {
+            OtObj::VmArrInt { elements, .. } => elements[pos as usize] = v,
+            o => panic!("Non-int[] seen in heap during IASTORE at {}", o.get_id()),
+        }
+    }
+
+    // Stores into a boolean, byte, char or short array, narrowing the value
+    // to the element type of the array
+    pub fn narrow_arr_store(&mut self, pos: i32, v: JvmValue) {
+        let p = pos as usize;
+        match (self, v.to_computational()) {
+            (OtObj::VmArrBoolean { elements, .. }, JvmValue::Int(i)) => elements[p] = i & 1 == 1,
+            (OtObj::VmArrByte { elements, .. }, JvmValue::Int(i)) => elements[p] = i as i8,
+            (OtObj::VmArrChar { elements, .. }, JvmValue::Int(i)) => elements[p] = i as u16,
+            (OtObj::VmArrShort { elements, .. }, JvmValue::Int(i)) => elements[p] = i as i16,
+            (o, v) => panic!("Cannot store {} into {}", v, o),
+        }
+    }
+
+    pub fn obj_arr_load(&self, pos: i32) -> usize {
+        match self {
+            OtObj::VmArrObj { elements, .. } => elements[pos as usize],
+            _ => panic!("Not an object array: {}", self),
+        }
+    }
+
+    pub fn obj_arr_store(&mut self, pos: i32, v: usize) {
+        match self {
+            OtObj::VmArrObj { elements, .. } => elements[pos as usize] = v,
+            o => panic!("Cannot store {} into {}", v, o),
+        }
+    }
+
+    // Whether both are arrays with the same kind of elements, so can be
+    // copied between. Any two object arrays qualify, as their elements are
+    // checked one by one.
+    pub fn same_array_type(&self, other: &OtObj) -> bool {
+        match self {
+            OtObj::VmObj { .. } => false,
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other),
+        }
+    }
+
+    // A detached array holding len elements of this one, from pos
+    pub fn array_slice(&self, pos: usize, len: usize) -> OtObj {
+        let range = pos..pos + len;
+        match self {
+            OtObj::VmObj { .. } => panic!("Attempted to slice a normal object!"),
+            OtObj::VmArrInt { klassid, elements, .. } => OtObj::VmArrInt {
+                id: 0,
+                mark: 0u64,
+                klassid: *klassid,
+                length: len as i32,
+                elements: elements[range].to_vec(),
+            },
+            OtObj::VmArrLong { klassid, elements, .. } => OtObj::VmArrLong {
+                id: 0,
+                mark: 0u64,
+                klassid: *klassid,
+                length: len as i32,
+                elements: elements[range].to_vec(),
+            },
+            OtObj::VmArrBoolean { klassid, elements, .. } => OtObj::VmArrBoolean {
+                id: 0,
+                mark: 0u64,
+                klassid: *klassid,
+                length: len as i32,
+                elements: elements[range].to_vec(),
+            },
+            OtObj::VmArrByte { klassid, elements, .. } => OtObj::VmArrByte {
+                id: 0,
+                mark: 0u64,
+                klassid: *klassid,
+                length: len as i32,
+                elements: elements[range].to_vec(),
+            },
+            OtObj::VmArrChar { klassid, elements, .. } => OtObj::VmArrChar {
+                id: 0,
+                mark: 0u64,
+                klassid: *klassid,
+                length: len as i32,
+                elements: elements[range].to_vec(),
+            },
+            OtObj::VmArrShort { klassid, elements, .. } => OtObj::VmArrShort {
+                id: 0,
+                mark: 0u64,
+                klassid: *klassid,
+                length: len as i32,
+                elements: elements[range].to_vec(),
+            },
+            OtObj::VmArrObj { klassid, elements, .. } => OtObj::VmArrObj {
+                id: 0,
+                mark: 0u64,
+                klassid: *klassid,
+                length: len as i32,
+                elements: elements[range].to_vec(),
+            },
+        }
+    }
+
+    // Overwrites elements from pos on with all of from's
+    pub fn array_paste(&mut self, pos: usize, from: &OtObj) {
+        match (self, from) {
+            (OtObj::VmArrInt { elements, .. }, OtObj::VmArrInt { elements: src, .. }) => {
+                elements[pos..pos + src.len()].copy_from_slice(src)
+            }
+            (OtObj::VmArrLong { elements, .. }, OtObj::VmArrLong { elements: src, .. }) => {
+                elements[pos..pos + src.len()].copy_from_slice(src)
+            }
+            (OtObj::VmArrBoolean { elements, .. }, OtObj::VmArrBoolean { elements: src, .. }) => {
+                elements[pos..pos + src.len()].copy_from_slice(src)
+            }
+            (OtObj::VmArrByte { elements, .. }, OtObj::VmArrByte { elements: src, .. }) => {
+                elements[pos..pos + src.len()].copy_from_slice(src)
+            }
+            (OtObj::VmArrChar { elements, .. }, OtObj::VmArrChar { elements: src, .. }) => {
+                elements[pos..pos + src.len()].copy_from_slice(src)
+            }
+            (OtObj::VmArrShort { elements, .. }, OtObj::VmArrShort { elements: src, .. }) => {
+                elements[pos..pos + src.len()].copy_from_slice(src)
+            }
+            (OtObj::VmArrObj { elements, .. }, OtObj::VmArrObj { elements: src, .. }) => {
+                elements[pos..pos + src.len()].copy_from_slice(src)
+            }
+            (o, from) => panic!("Cannot copy {} into {}", from, o),
         }
     }
 }
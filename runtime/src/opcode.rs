@@ -1,33 +1,33 @@
-// AALOAD 0x32
-// AASTORE 0x53
+pub const AALOAD: u8 = 0x32;
+pub const AASTORE: u8 = 0x53;
 pub const ACONST_NULL: u8 = 0x01;
 pub const ALOAD: u8 = 0x19;
 pub const ALOAD_0: u8 = 0x2a;
 pub const ALOAD_1: u8 = 0x2b;
 pub const ALOAD_2: u8 = 0x2c;
 pub const ALOAD_3: u8 = 0x2d;
-// ANEWARRAY 0xbd
+pub const ANEWARRAY: u8 = 0xbd;
 pub const ARETURN: u8 = 0xb0;
-// ARRAYLENGTH 0xbe
-pub const ASTORE: u8 = 0x53;
+pub const ARRAYLENGTH: u8 = 0xbe;
+pub const ASTORE: u8 = 0x3a;
 pub const ASTORE_0: u8 = 0x4b;
 pub const ASTORE_1: u8 = 0x4c;
 pub const ASTORE_2: u8 = 0x4d;
 pub const ASTORE_3: u8 = 0x4e;
-// ATHROW 0xbf
-// BALOAD 0x33
-// BASTORE 0x54
+pub const ATHROW: u8 = 0xbf;
+pub const BALOAD: u8 = 0x33;
+pub const BASTORE: u8 = 0x54;
 pub const BIPUSH: u8 = 0x10;
 pub const BREAKPOINT: u8 = 0xca;
-// CALOAD 0x34
-// CASTORE 0x55
-// CHECKCAST 0xc0
+pub const CALOAD: u8 = 0x34;
+pub const CASTORE: u8 = 0x55;
+pub const CHECKCAST: u8 = 0xc0;
 pub const D2F: u8 = 0x90;
 pub const D2I: u8 = 0x8e;
 pub const D2L: u8 = 0x8f;
 pub const DADD: u8 = 0x63;
-// DALOAD 0x31
-// DASTORE 0x52
+pub const DALOAD: u8 = 0x31;
+pub const DASTORE: u8 = 0x52;
 pub const DCMPG: u8 = 0x98;
 pub const DCMPL: u8 = 0x97;
 pub const DCONST_0: u8 = 0x0e;
@@ -50,16 +50,16 @@ pub const DSTORE_3: u8 = 0x4a;
 pub const DSUB: u8 = 0x67;
 pub const DUP: u8 = 0x59;
 pub const DUP_X1: u8 = 0x5a;
-// DUP_X2 0x5b
-// DUP2 0x5c
-// DUP2_X1 0x5d
-// DUP2_X2 0x5e
+pub const DUP_X2: u8 = 0x5b;
+pub const DUP2: u8 = 0x5c;
+pub const DUP2_X1: u8 = 0x5d;
+pub const DUP2_X2: u8 = 0x5e;
 pub const F2D: u8 = 0x8d;
 pub const F2I: u8 = 0x8b;
 pub const F2L: u8 = 0x8c;
 pub const FADD: u8 = 0x62;
-// FALOAD 0x30
-// FASTORE 0x51
+pub const FALOAD: u8 = 0x30;
+pub const FASTORE: u8 = 0x51;
 pub const FCMPG: u8 = 0x96;
 pub const FCMPL: u8 = 0x95;
 pub const FCONST_0: u8 = 0x0b;
@@ -103,8 +103,8 @@ pub const ICONST_3: u8 = 0x06;
 pub const ICONST_4: u8 = 0x07;
 pub const ICONST_5: u8 = 0x08;
 pub const IDIV: u8 = 0x6c;
-// IF_ACMPEQ 0xa5
-// IF_ACMPNE 0xa6
+pub const IF_ACMPEQ: u8 = 0xa5;
+pub const IF_ACMPNE: u8 = 0xa6;
 pub const IF_ICMPEQ: u8 = 0x9f;
 pub const IF_ICMPGE: u8 = 0xa2;
 pub const IF_ICMPGT: u8 = 0xa3;
@@ -129,9 +129,9 @@ pub const IMPDEP1: u8 = 0xfe;
 pub const IMPDEP2: u8 = 0xff;
 pub const IMUL: u8 = 0x68;
 pub const INEG: u8 = 0x74;
-// INSTANCEOF 0xc1
-// INVOKEDYNAMIC 0xba
-// INVOKEINTERFACE 0xb9
+pub const INSTANCEOF: u8 = 0xc1;
+pub const INVOKEDYNAMIC: u8 = 0xba;
+pub const INVOKEINTERFACE: u8 = 0xb9;
 pub const INVOKESPECIAL: u8 = 0xb7;
 pub const INVOKESTATIC: u8 = 0xb8;
 pub const INVOKEVIRTUAL: u8 = 0xb6;
@@ -154,13 +154,14 @@ pub const L2D: u8 = 0x8a;
 pub const L2F: u8 = 0x89;
 pub const L2I: u8 = 0x88;
 pub const LADD: u8 = 0x61;
-// LALOAD 0x2f
+pub const LALOAD: u8 = 0x2f;
 pub const LAND: u8 = 0x7f;
-// LASTORE 0x50
+pub const LASTORE: u8 = 0x50;
 pub const LCMP: u8 = 0x94;
 pub const LCONST_0: u8 = 0x09;
 pub const LCONST_1: u8 = 0x0a;
 pub const LDC: u8 = 0x12;
+pub const LDC_W: u8 = 0x13;
 pub const LDC2_W: u8 = 0x14;
 pub const LDIV: u8 = 0x6d;
 pub const LLOAD: u8 = 0x16;
@@ -170,7 +171,7 @@ pub const LLOAD_2: u8 = 0x20;
 pub const LLOAD_3: u8 = 0x21;
 pub const LMUL: u8 = 0x69;
 pub const LNEG: u8 = 0x75;
-// LOOKUPSWITCH 0xab
+pub const LOOKUPSWITCH: u8 = 0xab;
 pub const LOR: u8 = 0x81;
 pub const LREM: u8 = 0x71;
 pub const LRETURN: u8 = 0xad;
@@ -186,7 +187,7 @@ pub const LUSHR: u8 = 0x7d;
 pub const LXOR: u8 = 0x83;
 pub const MONITORENTER: u8 = 0xc2;
 pub const MONITOREXIT: u8 = 0xc3;
-// MULTINEWARRAY 0xc5
+pub const MULTINEWARRAY: u8 = 0xc5;
 pub const NEW: u8 = 0xbb;
 pub const NEWARRAY: u8 = 0xbc;
 pub const NOP: u8 = 0x00;
@@ -196,53 +197,29 @@ pub const PUTFIELD: u8 = 0xb5;
 pub const PUTSTATIC: u8 = 0xb3;
 pub const RET: u8 = 0xa9;
 pub const RETURN: u8 = 0xb1;
-// SALOAD 0x35
-// SASTORE 0x56
+pub const SALOAD: u8 = 0x35;
+pub const SASTORE: u8 = 0x56;
 pub const SIPUSH: u8 = 0x11;
 pub const SWAP: u8 = 0x5f;
-// TABLESWITCH 0xaa
-// WIDE 0xc4
+pub const TABLESWITCH: u8 = 0xaa;
+pub const WIDE: u8 = 0xc4;
 
 // [UNUSED] 0cb - 0xfd
 
-fn num_params(c: u8) -> u8 {
+// Operand bytes following each opcode. TABLESWITCH and LOOKUPSWITCH are
+// variable length and WIDE depends on the opcode it modifies, so the
+// decoder handles those three itself.
+pub fn num_params(c: u8) -> usize {
     match c {
-        ALOAD => 1,
-        ASTORE => 1,
-        BIPUSH => 1,
-        DLOAD => 1,
-        DSTORE => 1,
-        FLOAD => 1,
-        FSTORE => 1,
-        GETFIELD => 2,
-        GETSTATIC => 2,
-        GOTO => 2,
-        IF_ICMPEQ => 2,
-        IFEQ => 2,
-        IFGE => 2,
-        IFGT => 2,
-        IFLE => 2,
-        IFLT => 2,
-        IFNE => 2,
-        IFNONNULL => 2,
-        IFNULL => 2,
-        IINC => 2,
-        ILOAD => 1,
-        INVOKESPECIAL => 2,
-        INVOKESTATIC => 2,
-        INVOKEVIRTUAL => 2,
-        ISTORE => 1,
-        LLOAD => 1,
-        LSTORE => 1,
-        NEW => 2,
-        NEWARRAY => 1,
-        JSR => 2,
-        JSR_W => 2,
-        LDC => 1,
-        PUTFIELD => 2,
-        PUTSTATIC => 2,
-        RET => 1,
-        SIPUSH => 2,
+        ALOAD | ASTORE | BIPUSH | DLOAD | DSTORE | FLOAD | FSTORE | ILOAD | ISTORE | LDC
+        | LLOAD | LSTORE | NEWARRAY | RET => 1,
+        ANEWARRAY | CHECKCAST | GETFIELD | GETSTATIC | GOTO | IF_ACMPEQ | IF_ACMPNE
+        | IF_ICMPEQ | IF_ICMPGE | IF_ICMPGT | IF_ICMPLE | IF_ICMPLT | IF_ICMPNE | IFEQ | IFGE
+        | IFGT | IFLE | IFLT | IFNE | IFNONNULL | IFNULL | IINC | INSTANCEOF | INVOKESPECIAL
+        | INVOKESTATIC | INVOKEVIRTUAL | JSR | LDC_W | LDC2_W | NEW | PUTFIELD | PUTSTATIC
+        | SIPUSH => 2,
+        MULTINEWARRAY => 3,
+        GOTO_W | INVOKEDYNAMIC | INVOKEINTERFACE | JSR_W => 4,
         _ => 0,
     }
 }
@@ -46,6 +46,12 @@ impl OtField {
         self.offset
     }
 
+    // As parsed, the offset counts every field the class declares. Once the
+    // klass is laid out, instance fields get their slot in the object.
+    pub fn with_offset(&self, offset: u16) -> OtField {
+        OtField { offset, ..self.clone() }
+    }
+
     pub fn set_attr(&self, _index: u16, _attr: CpAttr) -> () {}
 
     pub fn get_name(&self) -> String {
@@ -64,12 +70,28 @@ impl OtField {
         self.klass_name.clone() + "." + &self.name + ":" + &self.desc
     }
 
+    pub fn get_desc(&self) -> String {
+        self.desc.clone()
+    }
+
+    pub fn get_name_desc(&self) -> String {
+        self.name.clone() + ":" + &self.desc
+    }
+
+    // Narrows a stack value to this field's storage type
+    pub fn narrow(&self, v: JvmValue) -> JvmValue {
+        match self.desc.chars().next() {
+            Some(letter) => v.narrow_to(letter),
+            None => v,
+        }
+    }
+
     pub fn get_default(&self) -> JvmValue {
         match self.desc.as_str() {
             "Z" => JvmValue::Boolean(false),
             "B" => JvmValue::Byte(0),
             "S" => JvmValue::Short(0),
-            "C" => JvmValue::Char('\0'),
+            "C" => JvmValue::Char(0),
             "I" => JvmValue::Int(0i32),
             "J" => JvmValue::Long(0i64),
             "F" => JvmValue::Float(0.0),
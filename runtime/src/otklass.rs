@@ -1,26 +1,79 @@
-use std::cell::Cell;
 use std::collections::HashMap;
 use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
 
 use crate::constant_pool::*;
 use crate::otfield::OtField;
-use crate::otmethod::OtMethod;
+use crate::otmethod::{NativeMethod, OtMethod};
 use crate::InterpLocalVars;
 use crate::JvmValue;
 
 //////////// RUNTIME KLASS AND RELATED HANDLING
 
-#[derive(Debug, Clone)]
+// What a field, method or class entry in a klass's constant pool refers to.
+// Worked out by the repo the first time the entry is used, then cached on
+// the klass so later executions skip the string lookups entirely. Klasses
+// routinely refer to each other, so these are Weak, leaving the repo as
+// the only owner and letting its klasses go along with it.
+#[derive(Clone)]
+pub enum CpResolved {
+    InstanceField(OtField),
+    StaticField {
+        klass: Weak<OtKlass>,
+        slot: usize,
+        field: OtField,
+    },
+    Method {
+        klass: Weak<OtKlass>,
+        idx: usize,
+        arg_count: u8,
+    },
+    Klass(Weak<OtKlass>),
+}
+
+// A klass referred to by a resolved entry, which lives as long as the repo
+pub(crate) fn resolved_klass(klass: &Weak<OtKlass>) -> Arc<OtKlass> {
+    klass.upgrade().expect("Resolved klass has been dropped")
+}
+
+impl fmt::Debug for CpResolved {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpResolved::InstanceField(fld) => write!(f, "InstanceField({})", fld.get_fq_name_desc()),
+            CpResolved::StaticField { klass, slot, .. } => {
+                write!(f, "StaticField({}, {})", resolved_klass(klass).get_name(), slot)
+            }
+            CpResolved::Method { klass, idx, .. } => {
+                write!(f, "Method({:?})", resolved_klass(klass).get_method(*idx))
+            }
+            CpResolved::Klass(klass) => write!(f, "Klass({})", resolved_klass(klass).get_name()),
+        }
+    }
+}
+
+#[derive(Debug)]
 pub struct OtKlass {
-    id: Cell<usize>,
+    id: AtomicUsize,
     name: String,
     super_name: String,
+    // Names of the interfaces the klass itself declares
+    interfaces: Vec<String>,
     flags: u16,
     cp_entries: Vec<CpEntry>,
+    cp_resolved: Vec<OnceLock<CpResolved>>,
+    // The java/lang/Class object for the klass, see get_mirror()
+    mirror: OnceLock<usize>,
+    // Whether the klass overrides Object.finalize(), see is_finalizable()
+    finalizable: OnceLock<bool>,
+    // Set once <clinit> has been started, see begin_initialization()
+    initialized: AtomicBool,
     methods: Vec<OtMethod>,
     i_fields: Vec<OtField>,
+    // Instance fields including inherited ones, see instance_layout()
+    i_layout: OnceLock<Vec<OtField>>,
     s_fields: Vec<OtField>,
-    s_field_vals: Vec<Cell<JvmValue>>,
+    s_field_vals: Vec<Mutex<JvmValue>>,
     m_name_desc_lookup: HashMap<String, usize>,
     f_name_desc_lookup: HashMap<String, usize>,
 }
@@ -53,7 +106,7 @@ impl OtKlass {
             if f.is_static() {
                 let default_val = f.get_default();
                 s_fields.push(f);
-                s_field_vals.push(Cell::new(default_val));
+                s_field_vals.push(Mutex::new(default_val));
                 f_lookup.insert(f_name, s_fields.len() - 1);
             } else {
                 i_fields.push(f);
@@ -63,15 +116,28 @@ impl OtKlass {
         // dbg!(m_lookup.clone());
         // dbg!(f_lookup.clone());
         OtKlass {
-            id: Cell::new(0), // This indicates that the class has not yet been loaded into a repo
+            id: AtomicUsize::new(0), // This indicates that the class has not yet been loaded into a repo
             name: klass_name,
             super_name: super_klass,
+            interfaces: Vec::new(),
             flags,
             cp_entries: cp_entries.to_vec(),
-            methods: methods.to_vec(),
+            cp_resolved: cp_entries.iter().map(|_| OnceLock::new()).collect(),
+            mirror: OnceLock::new(),
+            finalizable: OnceLock::new(),
+            initialized: AtomicBool::new(false),
+            methods: methods
+                .iter()
+                .map(|m| {
+                    let mut m = m.clone();
+                    m.link(cp_entries);
+                    m
+                })
+                .collect(),
             i_fields: i_fields.to_vec(),
+            i_layout: OnceLock::new(),
             s_fields: s_fields.to_vec(),
-            s_field_vals: s_field_vals.to_vec(),
+            s_field_vals,
             // FIXME
             m_name_desc_lookup: m_lookup,
             f_name_desc_lookup: f_lookup,
@@ -135,24 +201,49 @@ impl OtKlass {
     /////////////////////////////////////
 
     pub fn make_default_values(&self) -> Vec<JvmValue> {
-        let mut out: Vec<JvmValue> = Vec::new();
-        let mut i = 0;
-        while i < self.i_fields.len() {
-            match self.i_fields.get(i) {
-                Some(f) => out.push(f.get_default()),
-                None => panic!("Error: field {} not found on {}", i, self.name),
-            };
-            i = i + 1;
+        match self.i_layout.get() {
+            Some(fields) => fields.iter().map(|f| f.get_default()).collect(),
+            None => panic!("Error: klass {} has not been laid out", self.name),
+        }
+    }
+
+    // The fields of an instance: those of the superclasses, which
+    // inherited() provides, then this klass's own. Each gets its slot in
+    // the object as its offset. Worked out on first use, as superclasses
+    // may not be loaded when the klass is.
+    pub fn instance_layout<F>(&self, inherited: F) -> &[OtField]
+    where
+        F: FnOnce() -> Vec<OtField>,
+    {
+        self.i_layout.get_or_init(|| {
+            let mut fields = inherited();
+            for f in self.i_fields.iter() {
+                let offset = fields.len() as u16;
+                fields.push(f.with_offset(offset));
+            }
+            fields
+        })
+    }
+
+    // The instance fields this klass declares, with their slots once it has
+    // been laid out
+    pub fn own_instance_fields(&self) -> &[OtField] {
+        match self.i_layout.get() {
+            Some(fields) => &fields[fields.len() - self.i_fields.len()..],
+            None => &self.i_fields,
         }
-        out
+    }
+
+    pub fn get_static_fields(&self) -> &[OtField] {
+        &self.s_fields
     }
 
     pub fn set_id(&self, new_id: usize) -> () {
-        self.id.set(new_id)
+        self.id.store(new_id, Ordering::SeqCst)
     }
 
     pub fn get_id(&self) -> usize {
-        self.id.get()
+        self.id.load(Ordering::SeqCst)
     }
 
     pub fn get_name(&self) -> String {
@@ -163,6 +254,29 @@ impl OtKlass {
         self.super_name.to_owned()
     }
 
+    pub fn is_interface(&self) -> bool {
+        self.flags & ACC_INTERFACE == ACC_INTERFACE
+    }
+
+    pub fn is_abstract(&self) -> bool {
+        self.flags & ACC_ABSTRACT == ACC_ABSTRACT
+    }
+
+    // True for the one caller that gets to run <clinit>, which it's
+    // marked as having done before it starts, as <clinit> may use the
+    // klass itself
+    pub fn begin_initialization(&self) -> bool {
+        !self.initialized.swap(true, Ordering::SeqCst)
+    }
+
+    pub fn with_interfaces(self, interfaces: Vec<String>) -> OtKlass {
+        OtKlass { interfaces, ..self }
+    }
+
+    pub fn get_interfaces(&self) -> &[String] {
+        &self.interfaces
+    }
+
     pub fn get_methods(&self) -> Vec<OtMethod> {
         self.methods.clone()
     }
@@ -170,7 +284,7 @@ impl OtKlass {
     pub fn set_native_method(
         &self,
         name_desc: String,
-        n_code: fn(&InterpLocalVars) -> Option<JvmValue>,
+        n_code: NativeMethod,
     ) {
         match self.get_method_by_name_and_desc(&name_desc) {
             Some(m2) => m2.set_native_code(n_code),
@@ -228,14 +342,20 @@ impl OtKlass {
     }
 
 
+    pub fn get_static_at(&self, slot: usize) -> JvmValue {
+        *self.s_field_vals[slot].lock().unwrap()
+    }
+
+    pub fn put_static_at(&self, slot: usize, f: &OtField, v: JvmValue) -> () {
+        *self.s_field_vals[slot].lock().unwrap() = f.narrow(v);
+    }
+
     pub fn get_static(&self, f: &OtField) -> JvmValue {
-        let idx = self.get_static_field_offset(f);
-        self.s_field_vals.get(idx).unwrap().get().clone()
+        self.get_static_at(self.get_static_field_offset(f))
     }
 
     pub fn put_static(&self, f: &OtField, v: JvmValue) -> () {
-        let idx = self.get_static_field_offset(f);
-        self.s_field_vals.get(idx).unwrap().set(v);
+        self.put_static_at(self.get_static_field_offset(f), f, v);
     }
 
 
@@ -256,6 +376,54 @@ impl OtKlass {
         )
     }
 
+    // NOTE: This is fully-qualified
+    pub fn get_method_index(&self, name_desc: &String) -> Option<usize> {
+        self.m_name_desc_lookup.get(name_desc).cloned()
+    }
+
+    // Adds a method the class file doesn't declare, before the klass goes
+    // into a repo
+    pub fn add_method(&mut self, mut meth: OtMethod) -> () {
+        meth.link(&self.cp_entries);
+        self.m_name_desc_lookup.insert(meth.get_fq_name_desc(), self.methods.len());
+        self.methods.push(meth);
+    }
+
+    pub fn get_method(&self, idx: usize) -> &OtMethod {
+        &self.methods[idx]
+    }
+
+    // Whether instances need finalizing, which check() works out the first
+    // time, as superclasses may not be loaded when the klass is
+    pub fn is_finalizable<F: FnOnce() -> bool>(&self, check: F) -> bool {
+        *self.finalizable.get_or_init(check)
+    }
+
+    // Objects the klass holds on to, in its static fields and as its
+    // mirror, which are roots for the collector
+    pub fn refs(&self) -> Vec<usize> {
+        let statics = self.s_field_vals.iter().filter_map(|v| match *v.lock().unwrap() {
+            JvmValue::ObjRef(id) if id != 0 => Some(id),
+            _ => None,
+        });
+        statics.chain(self.mirror.get().copied()).collect()
+    }
+
+    // The klass's Class object, allocated on first use. It is also what
+    // static synchronized methods lock.
+    pub fn get_mirror<F: FnOnce() -> usize>(&self, alloc: F) -> usize {
+        *self.mirror.get_or_init(alloc)
+    }
+
+    // Returns the cached resolution of a CP entry, running resolve() the
+    // first time round
+    pub fn resolved<F>(&self, cp_idx: u16, resolve: F) -> &CpResolved
+    where
+        F: FnOnce() -> CpResolved,
+    {
+        self.cp_resolved[cp_idx as usize].get_or_init(resolve)
+    }
+
     // NOTE: This is fully-qualified
     pub fn get_method_by_name_and_desc(&self, name_desc: &String) -> Option<&OtMethod> {
         let opt_idx = self.m_name_desc_lookup.get(name_desc);
@@ -303,6 +471,7 @@ impl OtKlass {
         let cp_entry = self.lookup_cp(cp_idx);
         let name_and_type = match cp_entry {
             CpEntry::MethodRef(mr) => self.lookup_cp(mr.nt_idx),
+            CpEntry::InterfaceMethodRef(mr) => self.lookup_cp(mr.nt_idx),
             _ => panic!(
                 "Attempt to count args of non-method in {} at index {} where {:?}",
                 self.name, cp_idx, self.cp_entries.get(cp_idx as usize)
@@ -328,8 +497,10 @@ impl OtKlass {
         match self.lookup_cp(i) {
             CpEntry::Utf8(s) => s,
             CpEntry::Class(c) => self.cp_as_string(c.0),
+            CpEntry::String(s) => self.cp_as_string(s.0),
             CpEntry::FieldRef(fr) => self.cp_as_string(fr.clz_idx) + "." + &self.cp_as_string(fr.nt_idx),
             CpEntry::MethodRef(mr) => self.cp_as_string(mr.clz_idx) + "." + &self.cp_as_string(mr.nt_idx),
+            CpEntry::InterfaceMethodRef(mr) => self.cp_as_string(mr.clz_idx) + "." + &self.cp_as_string(mr.nt_idx),
             CpEntry::NameAndType(nt) => self.cp_as_string(nt.name_idx) + ":" + &self.cp_as_string(nt.type_idx),
             _ => panic!(
                 "Unimplemented stringify of CP entry found in {} at index {}",
@@ -342,6 +513,33 @@ impl OtKlass {
 // flags: u16,
 // cp_entries: Vec<CpEntry>,
 // name_desc_lookup: HashMap<String, usize>,
+// The id and statics sit behind atomics and locks, so are copied by value
+impl Clone for OtKlass {
+    fn clone(&self) -> OtKlass {
+        OtKlass {
+            id: AtomicUsize::new(self.get_id()),
+            name: self.name.clone(),
+            super_name: self.super_name.clone(),
+            interfaces: self.interfaces.clone(),
+            flags: self.flags,
+            cp_entries: self.cp_entries.clone(),
+            cp_resolved: self.cp_resolved.clone(),
+            mirror: self.mirror.clone(),
+            finalizable: self.finalizable.clone(),
+            initialized: AtomicBool::new(self.initialized.load(Ordering::SeqCst)),
+            methods: self.methods.clone(),
+            i_fields: self.i_fields.clone(),
+            i_layout: self.i_layout.clone(),
+            s_fields: self.s_fields.clone(),
+            s_field_vals: (0..self.s_field_vals.len())
+                .map(|slot| Mutex::new(self.get_static_at(slot)))
+                .collect(),
+            m_name_desc_lookup: self.m_name_desc_lookup.clone(),
+            f_name_desc_lookup: self.f_name_desc_lookup.clone(),
+        }
+    }
+}
+
 impl fmt::Display for OtKlass {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
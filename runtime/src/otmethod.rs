@@ -1,11 +1,33 @@
-use std::cell::Cell;
 use std::fmt;
+use std::sync::{Arc, OnceLock};
 
 use crate::constant_pool::CpAttr;
+use crate::constant_pool::CpEntry;
 use crate::constant_pool::ACC_NATIVE;
+use crate::constant_pool::ACC_PRIVATE;
+use crate::constant_pool::ACC_STATIC;
+use crate::constant_pool::ACC_SYNCHRONIZED;
+use crate::instruction::{self, Instruction};
+use crate::otklass::OtKlass;
+use crate::vm::Vm;
 use crate::InterpLocalVars;
 use crate::JvmValue;
 
+// Code for a native method or intrinsic, which is handed the VM it runs in.
+// Plain fns and closures both do, see SharedKlassRepo::register_native().
+pub type NativeMethod = Arc<dyn Fn(&Vm, &InterpLocalVars) -> Option<JvmValue> + Send + Sync>;
+
+// An exception table entry. pcs are byte offsets as parsed and become
+// instruction indices once the method is linked; end is exclusive.
+#[derive(Clone, Debug)]
+pub struct ExceptionHandler {
+    pub start: usize,
+    pub end: usize,
+    pub handler: usize,
+    // CP index of the caught class, 0 catches everything
+    pub catch_type: u16,
+}
+
 #[derive(Clone)]
 pub struct OtMethod {
     klass_name: String,
@@ -15,7 +37,13 @@ pub struct OtMethod {
     name_idx: u16,
     desc_idx: u16,
     code: Vec<u8>,
-    native_code: Cell<Option<fn(&InterpLocalVars) -> Option<JvmValue>>>,
+    instrs: Arc<[Instruction]>,
+    max_stack: u16,
+    max_locals: u16,
+    handlers: Vec<ExceptionHandler>,
+    // Descriptor letters of the parameters, filled in by link()
+    arg_types: Vec<char>,
+    native_code: OnceLock<NativeMethod>,
     attrs: Vec<CpAttr>,
 }
 
@@ -36,7 +64,12 @@ impl OtMethod {
             name_desc: name_and_desc,
             attrs: Vec::new(),
             code: Vec::new(),
-            native_code: Cell::new(None),
+            instrs: Arc::new([]),
+            max_stack: 0,
+            max_locals: 0,
+            handlers: Vec::new(),
+            arg_types: Vec::new(),
+            native_code: OnceLock::new(),
             // FIXME
             name_idx: desc_idx,
             desc_idx,
@@ -49,8 +82,26 @@ impl OtMethod {
         self.code = code;
     }
 
-    pub fn get_code(&self) -> Vec<u8> {
-        self.code.clone()
+    // Decodes the bytecode against the constant pool of the owning klass.
+    // The raw bytes are not needed after this.
+    pub fn link(&mut self, cp_entries: &[CpEntry]) -> () {
+        let code = std::mem::take(&mut self.code);
+        let (instrs, offsets) = instruction::decode_mapped(&code, cp_entries);
+        for h in self.handlers.iter_mut() {
+            h.start = instruction::fix_target(&offsets, h.start);
+            h.end = instruction::fix_target(&offsets, h.end);
+            h.handler = instruction::fix_target(&offsets, h.handler);
+        }
+        self.instrs = instrs.into();
+        let desc = &self.name_desc[self.name.len() + 1..];
+        self.arg_types = OtKlass::parse_sig_for_args(desc.to_string())
+            .iter()
+            .map(|v| v.name())
+            .collect();
+    }
+
+    pub fn get_instructions(&self) -> &[Instruction] {
+        &self.instrs
     }
 
     pub fn get_klass_name(&self) -> String {
@@ -61,6 +112,15 @@ impl OtMethod {
         self.name_desc.clone()
     }
 
+    // Descriptor letter of the return type, 'A' for references, 'V' for void
+    pub fn get_return_type(&self) -> char {
+        match self.name_desc.rsplit(')').next().and_then(|r| r.chars().next()) {
+            Some('L') | Some('[') => 'A',
+            Some(c) => c,
+            None => panic!("Malformed descriptor {}", self.name_desc),
+        }
+    }
+
     pub fn get_fq_name_desc(&self) -> String {
         self.klass_name.clone() + "." + &self.name_desc.clone()
     }
@@ -73,20 +133,87 @@ impl OtMethod {
         self.flags & ACC_NATIVE == ACC_NATIVE
     }
 
-    pub fn set_native_code(&self, n_code: fn(&InterpLocalVars) -> Option<JvmValue>) {
+    pub fn is_static(&self) -> bool {
+        self.flags & ACC_STATIC == ACC_STATIC
+    }
+
+    pub fn is_private(&self) -> bool {
+        self.flags & ACC_PRIVATE == ACC_PRIVATE
+    }
+
+    pub fn is_synchronized(&self) -> bool {
+        self.flags & ACC_SYNCHRONIZED == ACC_SYNCHRONIZED
+    }
+
+    pub fn set_native_code(&self, n_code: NativeMethod) {
         if !self.is_native() {
             panic!("Should be unreachable - trying to store native code in a regular method")
         }
-        self.native_code.set(Some(n_code));
+        self.install_code(n_code);
+    }
+
+    // Runs native code in place of the bytecode, for library methods that
+    // need more of the VM than exists yet
+    pub fn set_intrinsic_code(&self, n_code: NativeMethod) {
+        self.install_code(n_code);
+    }
+
+    fn install_code(&self, n_code: NativeMethod) {
+        if self.native_code.set(n_code).is_err() {
+            panic!("Native code for {} installed twice", self)
+        }
     }
 
-    pub fn get_native_code(&self) -> Option<fn(&InterpLocalVars) -> Option<JvmValue>> {
-        self.native_code.get()
+    pub fn get_native_code(&self) -> Option<NativeMethod> {
+        self.native_code.get().cloned()
     }
 
-    // HACK Replace with proper local var size by parsing class attributes properly
-    pub fn get_local_var_size(&self) -> u8 {
-        255
+    // Natives and intrinsics are called directly rather than given a frame
+    pub fn runs_natively(&self) -> bool {
+        self.is_native() || self.native_code.get().is_some()
+    }
+
+    // Descriptor letters of the parameters, 'A' for references
+    pub fn get_arg_types(&self) -> &[char] {
+        &self.arg_types
+    }
+
+    // Local var slots the arguments take up, counting the receiver.
+    // Longs and doubles take two.
+    pub fn get_arg_slots(&self) -> u16 {
+        let receiver = if self.is_static() { 0 } else { 1 };
+        self.arg_types.iter().map(|t| arg_width(*t)).sum::<u16>() + receiver
+    }
+
+    pub fn set_max_stack(&mut self, max_stack: u16) -> () {
+        self.max_stack = max_stack;
+    }
+
+    pub fn get_max_stack(&self) -> u16 {
+        self.max_stack
+    }
+
+    pub fn set_max_locals(&mut self, max_locals: u16) -> () {
+        self.max_locals = max_locals;
+    }
+
+    pub fn get_local_var_size(&self) -> u16 {
+        self.max_locals
+    }
+
+    pub fn set_exception_handlers(&mut self, handlers: Vec<ExceptionHandler>) -> () {
+        self.handlers = handlers;
+    }
+
+    pub fn get_exception_handlers(&self) -> &[ExceptionHandler] {
+        &self.handlers
+    }
+}
+
+pub fn arg_width(arg_type: char) -> u16 {
+    match arg_type {
+        'J' | 'D' => 2,
+        _ => 1,
     }
 }
 
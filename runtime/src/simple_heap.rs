@@ -5,91 +5,470 @@ use crate::OtField;
 use crate::OtKlass;
 use crate::OtObj;
 
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::Arc;
 
+use crate::gc::GcState;
+use crate::histogram::Histogram;
+use crate::hprof;
+use crate::klass_repo::SharedKlassRepo;
+use crate::object::HASH_MASK;
+
+// Heap sizes in bytes, unless set with -Xms and -Xmx
+pub const DEFAULT_INITIAL_HEAP: usize = 64 * 1024 * 1024;
+pub const DEFAULT_MAX_HEAP: usize = 256 * 1024 * 1024;
+
+// How dead objects are got rid of, chosen at startup
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Collector {
+    // Frees them where they are, leaving holes for new objects
+    MarkSweep,
+    // Slides live objects down over them, so the heap stays dense
+    MarkCompact,
+}
+
+impl FromStr for Collector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Collector, String> {
+        match s {
+            "mark-sweep" => Ok(Collector::MarkSweep),
+            "mark-compact" => Ok(Collector::MarkCompact),
+            _ => Err(format!("Unknown collector {}, expected mark-sweep or mark-compact", s)),
+        }
+    }
+}
+
+// An object whose klass overrides finalize(), and the klass and index of
+// that method
+#[derive(Clone)]
+pub struct Finalizer {
+    pub obj: usize,
+    pub klass: Arc<OtKlass>,
+    pub method_idx: usize,
+}
+
+// Why System.arraycopy() could not copy everything asked for
+#[derive(Debug, PartialEq)]
+pub enum ArrayCopyError {
+    // A range falls outside its array
+    IndexOutOfBounds,
+    // Not arrays of the same kind, or an element the destination can't hold
+    ArrayStore,
+}
+
+// Object ids are handles: the collector may move an object to another
+// slot, but its id stays the same
 pub struct SharedSimpleHeap {
-    obj_count: AtomicUsize,
-    // Free list
-    // Alloc table
-    alloc: Vec<OtObj>,
+    // Object storage. None marks a slot freed by the collector.
+    objects: Vec<Option<OtObj>>,
+    // Handle table, indexed by object id: the slot each object is in. Id 0
+    // is the null object, and None marks a free id.
+    handles: Vec<Option<usize>>,
+    // Free lists
+    free_ids: Vec<usize>,
+    free_slots: Vec<usize>,
+    collector: Collector,
+    live: usize,
+    // Bytes taken by live objects
+    used: usize,
+    // What the heap has grown to. Growing it further asks for a collection.
+    total: usize,
+    // Java code can't allocate beyond this. The VM's own allocations can.
+    max: usize,
+    // Native handles: objects held by runtime code, with how many times
+    // each has been pinned
+    pinned: HashMap<usize, usize>,
+    // Objects to finalize once they are found unreachable
+    finalizable: Vec<Finalizer>,
+    // Unreachable objects waiting for finalize() to run, which keeps them
+    // and everything they refer to alive until it has
+    finalize_queue: VecDeque<Finalizer>,
+    // Where identity hashes are up to
+    hash_state: u64,
+    // Shared with the VM, which collects when asked to
+    gc: Arc<GcState>,
 }
 
 impl SharedSimpleHeap {
     pub fn of() -> SharedSimpleHeap {
-        let mut out = SharedSimpleHeap {
-            obj_count: AtomicUsize::new(1),
-            alloc: Vec::new(),
-        };
-        let null_obj = OtObj::get_null();
-        out.alloc.push(null_obj);
-        out
+        SharedSimpleHeap {
+            objects: vec![Some(OtObj::get_null())],
+            handles: vec![Some(0)],
+            free_ids: Vec::new(),
+            free_slots: Vec::new(),
+            collector: Collector::MarkSweep,
+            live: 0,
+            used: 0,
+            total: DEFAULT_INITIAL_HEAP,
+            max: DEFAULT_MAX_HEAP,
+            pinned: HashMap::new(),
+            finalizable: Vec::new(),
+            finalize_queue: VecDeque::new(),
+            hash_state: 0,
+            gc: Arc::new(GcState::default()),
+        }
+    }
+
+    pub fn gc_state(&self) -> Arc<GcState> {
+        self.gc.clone()
+    }
+
+    // -Xms and -Xmx
+    pub fn set_limits(&mut self, initial: usize, max: usize) -> () {
+        self.total = initial.max(self.used);
+        self.max = max;
+    }
+
+    pub fn set_collector(&mut self, collector: Collector) -> () {
+        self.collector = collector;
     }
 
     pub fn allocate_obj(&mut self, klass: &OtKlass) -> usize {
-        let klass_id = klass.get_id();
-        let obj_id: usize = self.obj_count.fetch_add(1, Ordering::SeqCst);
-        let out = OtObj::obj_of(klass_id, obj_id, klass.make_default_values());
-        self.alloc.push(out);
-        obj_id
+        self.allocate_obj_within(klass, usize::MAX).unwrap()
     }
 
     pub fn allocate_int_arr(&mut self, size: i32) -> usize {
-        let obj_id = self.obj_count.fetch_add(1, Ordering::SeqCst);
-        let out = OtObj::int_arr_of(size, obj_id);
-        self.alloc.push(out);
-        obj_id
+        self.allocate_int_arr_within(size, usize::MAX).unwrap()
+    }
+
+    pub fn allocate_narrow_arr(&mut self, letter: char, size: i32) -> usize {
+        self.allocate_narrow_arr_within(letter, size, usize::MAX).unwrap()
+    }
+
+    pub fn allocate_obj_arr(&mut self, klass: &OtKlass, size: i32) -> usize {
+        self.allocate_obj_arr_within(klass, size, usize::MAX).unwrap()
+    }
+
+    // The try_ versions are for allocations by Java code, and give None if
+    // the object would take the heap over its maximum size
+
+    pub fn try_allocate_obj(&mut self, klass: &OtKlass) -> Option<usize> {
+        self.allocate_obj_within(klass, self.max)
+    }
+
+    pub fn try_allocate_int_arr(&mut self, size: i32) -> Option<usize> {
+        self.allocate_int_arr_within(size, self.max)
+    }
+
+    pub fn try_allocate_narrow_arr(&mut self, letter: char, size: i32) -> Option<usize> {
+        self.allocate_narrow_arr_within(letter, size, self.max)
+    }
+
+    pub fn try_allocate_obj_arr(&mut self, klass: &OtKlass, size: i32) -> Option<usize> {
+        self.allocate_obj_arr_within(klass, size, self.max)
+    }
+
+    fn allocate_obj_within(&mut self, klass: &OtKlass, limit: usize) -> Option<usize> {
+        let values = klass.make_default_values();
+        let bytes = OtObj::obj_size(values.len());
+        self.store(bytes, limit, |obj_id| OtObj::obj_of(klass.get_id(), obj_id, values))
+    }
+
+    fn allocate_int_arr_within(&mut self, size: i32, limit: usize) -> Option<usize> {
+        let bytes = OtObj::arr_size('I', size);
+        self.store(bytes, limit, |obj_id| OtObj::int_arr_of(size, obj_id))
+    }
+
+    fn allocate_narrow_arr_within(&mut self, letter: char, size: i32, limit: usize) -> Option<usize> {
+        let bytes = OtObj::arr_size(letter, size);
+        self.store(bytes, limit, |obj_id| OtObj::narrow_arr_of(letter, size, obj_id))
+    }
+
+    fn allocate_obj_arr_within(&mut self, klass: &OtKlass, size: i32, limit: usize) -> Option<usize> {
+        let bytes = OtObj::arr_size('L', size);
+        self.store(bytes, limit, |obj_id| OtObj::obj_arr_of(klass.get_id(), size, obj_id))
+    }
+
+    // Puts a new object in a free slot if there is one, otherwise on the end
+    fn store<F: FnOnce(usize) -> OtObj>(&mut self, bytes: usize, limit: usize, make: F) -> Option<usize> {
+        if self.used.saturating_add(bytes) > limit {
+            return None;
+        }
+        let obj_id = self.free_ids.pop().unwrap_or(self.handles.len());
+        let obj = Some(make(obj_id));
+        let slot = match self.free_slots.pop() {
+            Some(slot) => {
+                self.objects[slot] = obj;
+                slot
+            }
+            None => {
+                self.objects.push(obj);
+                self.objects.len() - 1
+            }
+        };
+        if obj_id == self.handles.len() {
+            self.handles.push(Some(slot));
+        } else {
+            self.handles[obj_id] = Some(slot);
+        }
+        self.live += 1;
+        self.used += bytes;
+        if self.used > self.total {
+            self.total = self.used.max(self.max.min(2 * self.total));
+            self.gc.request();
+        }
+        Some(obj_id)
+    }
+
+    pub fn live_objects(&self) -> usize {
+        self.live
+    }
+
+    pub fn used_bytes(&self) -> usize {
+        self.used
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        self.total
+    }
+
+    pub fn max_bytes(&self) -> usize {
+        self.max
+    }
+
+    // A shallow copy of object id, within the maximum heap size
+    pub fn try_clone_obj(&mut self, id: usize) -> Option<usize> {
+        let size = self.get_obj(id).size();
+        let max = self.max;
+        let copy = self.get_obj(id).copy_as(0);
+        self.store(size, max, move |obj_id| copy.with_id(obj_id))
     }
 
     pub fn get_obj(&self, id: usize) -> &OtObj {
-        match self.alloc.get(id) {
+        match self.slot_of(id).and_then(|slot| self.objects[slot].as_ref()) {
             Some(val) => val,
             None => panic!("Error: object {} not found", id),
         }
     }
 
-    // FIXME Handle storage properly
-    pub fn put_field(&self, id: usize, f: OtField, v: JvmValue) -> () {
-        // Get object from heap
-        match self.alloc.get(id) {
-            Some(val) => val.put_field(f.get_offset() as usize, v),
+    fn get_obj_mut(&mut self, id: usize) -> &mut OtObj {
+        let slot = self.slot_of(id);
+        match slot.and_then(move |slot| self.objects[slot].as_mut()) {
+            Some(val) => val,
             None => panic!("Error: object {} not found", id),
-        };
+        }
+    }
+
+    // Where the object is stored for now, which compaction can change
+    pub fn slot_of(&self, id: usize) -> Option<usize> {
+        self.handles.get(id).copied().flatten()
+    }
+
+    // Slots in use or free, i.e. how far the heap has spread
+    pub fn slots(&self) -> usize {
+        self.objects.len()
+    }
+
+    // Live objects, in storage order
+    pub fn objects(&self) -> impl Iterator<Item = &OtObj> {
+        self.objects.iter().skip(1).flatten()
+    }
+
+    pub fn pinned(&self) -> impl Iterator<Item = usize> + '_ {
+        self.pinned.keys().copied()
+    }
+
+    // Live objects grouped by class, biggest first
+    pub fn histogram(&self, repo: &SharedKlassRepo) -> Histogram {
+        Histogram::of(self, repo)
+    }
+
+    // Writes an HPROF heap dump, describing objects by the klasses in repo
+    pub fn dump_hprof<W: Write>(&self, repo: &SharedKlassRepo, roots: &[usize], out: W) -> io::Result<()> {
+        hprof::write(self, repo, roots, out)
+    }
+
+    // Keeps an object alive for as long as runtime code holds its id
+    pub fn pin(&mut self, id: usize) -> () {
+        *self.pinned.entry(id).or_insert(0) += 1;
+    }
+
+    pub fn unpin(&mut self, id: usize) -> () {
+        match self.pinned.get_mut(&id) {
+            Some(1) => {
+                self.pinned.remove(&id);
+            }
+            Some(count) => *count -= 1,
+            None => panic!("Error: object {} is not pinned", id),
+        }
+    }
+
+    pub fn register_finalizer(&mut self, finalizer: Finalizer) -> () {
+        self.finalizable.push(finalizer);
+    }
+
+    pub fn queued_finalizers(&self) -> usize {
+        self.finalize_queue.len()
+    }
+
+    // Takes the first object queued for finalization
+    pub fn next_finalizer(&mut self) -> Option<Finalizer> {
+        self.finalize_queue.pop_front()
+    }
+
+    // Marks everything reachable from roots and the pinned objects, then
+    // frees the rest. The mark word is taken by the monitor, so marks go in
+    // a bitmap on the side. Returns how many objects were freed.
+    pub fn collect(&mut self, roots: Vec<usize>) -> usize {
+        let mut marked = vec![false; self.handles.len()];
+        marked[0] = true;
+        let mut grey = roots;
+        grey.extend(self.pinned.keys());
+        grey.extend(self.finalize_queue.iter().map(|f| f.obj));
+        self.mark(&mut marked, grey);
+
+        // Finalizable objects that are unreachable get queued, and brought
+        // back to life until their finalize() has run
+        let (unreachable, reachable): (Vec<Finalizer>, Vec<Finalizer>) =
+            self.finalizable.drain(..).partition(|f| !marked[f.obj]);
+        self.finalizable = reachable;
+        self.mark(&mut marked, unreachable.iter().map(|f| f.obj).collect());
+        self.finalize_queue.extend(unreachable);
+
+        let mut freed = 0;
+        for (id, handle) in self.handles.iter_mut().enumerate() {
+            if let (Some(slot), false) = (*handle, marked[id]) {
+                self.used -= self.objects[slot].take().unwrap().size();
+                *handle = None;
+                self.free_ids.push(id);
+                self.free_slots.push(slot);
+                freed += 1;
+            }
+        }
+        self.live -= freed;
+        if self.collector == Collector::MarkCompact {
+            self.compact();
+        }
+        freed
+    }
+
+    fn mark(&self, marked: &mut [bool], mut grey: Vec<usize>) -> () {
+        while let Some(id) = grey.pop() {
+            if marked[id] {
+                continue;
+            }
+            marked[id] = true;
+            grey.extend(self.get_obj(id).refs());
+        }
+    }
+
+    // Slides live objects down into the free slots below them, keeping
+    // their order, and repoints their handles
+    fn compact(&mut self) -> () {
+        let mut to = 0;
+        for from in 0..self.objects.len() {
+            if let Some(obj) = self.objects[from].take() {
+                self.handles[obj.get_id()] = Some(to);
+                self.objects[to] = Some(obj);
+                to += 1;
+            }
+        }
+        self.objects.truncate(to);
+        self.objects.shrink_to_fit();
+        self.free_slots.clear();
+    }
+
+    // FIXME Handle storage properly
+    pub fn put_field(&self, id: usize, f: &OtField, v: JvmValue) -> () {
+        self.get_obj(id).put_field(f.get_offset() as usize, f.narrow(v));
     }
 
     pub fn get_field(&self, id: usize, offset: u16) -> JvmValue {
-        // Get object from heap
-        let obj = match self.alloc.get(id) {
-            Some(val) => val,
-            None => panic!("Error: object {} not found", id),
-        };
-        obj.get_field_value(offset as usize)
+        self.get_obj(id).get_field_value(offset as usize)
     }
 
     pub fn iastore(&mut self, id: usize, pos: i32, v: i32) -> () {
-        let p = pos as usize;
-        let obj = match self.alloc.get(id) {
-            Some(val) => val,
+        self.get_obj_mut(id).iastore(pos, v)
+    }
+
+    pub fn identity_hash(&mut self, id: usize) -> i32 {
+        let slot = match self.slot_of(id) {
+            Some(slot) => slot,
             None => panic!("Error: object {} not found", id),
         };
-        let t = match obj {
-            OtObj::VmArrInt {
-                id: i,
-                mark: m,
-                klassid: kid,
-                length: _,
-                elements: elts,
-            } => (i, m, kid, elts),
-            _ => panic!("Non-int[] seen in heap during IASTORE at {}", id),
-        };
-        let mut elts = t.3.clone();
-        elts[pos as usize] = v;
-        let obj = OtObj::VmArrInt {
-            id: *t.0,
-            mark: *t.1,
-            klassid: *t.2,
-            length: elts.len() as i32,
-            elements: elts,
+        let state = &mut self.hash_state;
+        self.objects[slot].as_mut().unwrap().identity_hash(|| next_hash(state))
+    }
+
+    pub fn try_monitor_enter(&mut self, id: usize, thread: u32) -> bool {
+        self.get_obj_mut(id).try_monitor_enter(thread)
+    }
+
+    pub fn monitor_exit(&mut self, id: usize, thread: u32) -> Option<bool> {
+        self.get_obj_mut(id).monitor_exit(thread)
+    }
+
+    pub fn monitor_release(&mut self, id: usize, thread: u32) -> Option<u32> {
+        self.get_obj_mut(id).monitor_release(thread)
+    }
+
+    pub fn try_monitor_restore(&mut self, id: usize, thread: u32, count: u32) -> bool {
+        self.get_obj_mut(id).try_monitor_restore(thread, count)
+    }
+
+    pub fn narrow_arr_store(&mut self, id: usize, pos: i32, v: JvmValue) -> () {
+        self.get_obj_mut(id).narrow_arr_store(pos, v)
+    }
+
+    pub fn obj_arr_store(&mut self, id: usize, pos: i32, v: usize) -> () {
+        self.get_obj_mut(id).obj_arr_store(pos, v)
+    }
+
+    // System.arraycopy(): copies length elements of src from src_pos into
+    // dest from dest_pos, as if through a temporary array when src and dest
+    // are the same. Elements of object arrays are checked against dest's
+    // element type, and those before the first that fails are still copied.
+    pub fn arraycopy(
+        &mut self,
+        repo: &SharedKlassRepo,
+        src: usize,
+        src_pos: i32,
+        dest: usize,
+        dest_pos: i32,
+        length: i32,
+    ) -> Result<(), ArrayCopyError> {
+        let (from, to) = (self.get_obj(src), self.get_obj(dest));
+        if !from.same_array_type(to) {
+            return Err(ArrayCopyError::ArrayStore);
+        }
+        let fits = |pos: i32, len: i32| pos as i64 + length as i64 <= len as i64;
+        if src_pos < 0 || dest_pos < 0 || length < 0 || !fits(src_pos, from.length()) || !fits(dest_pos, to.length()) {
+            return Err(ArrayCopyError::IndexOutOfBounds);
+        }
+
+        let (src_pos, length) = (src_pos as usize, length as usize);
+        let storable = match from {
+            OtObj::VmArrObj { elements, .. } if src != dest => {
+                let element_klass = to.get_klassid();
+                elements[src_pos..src_pos + length]
+                    .iter()
+                    .take_while(|&&e| e == 0 || repo.can_store(element_klass, self.get_obj(e)))
+                    .count()
+            }
+            _ => length,
         };
-        self.alloc[id] = obj;
+        let slice = from.array_slice(src_pos, storable);
+        self.get_obj_mut(dest).array_paste(dest_pos as usize, &slice);
+        if storable < length {
+            return Err(ArrayCopyError::ArrayStore);
+        }
+        Ok(())
+    }
+}
+
+// Identity hashes are the top bits of a SplitMix64 sequence, skipping 0
+fn next_hash(state: &mut u64) -> u32 {
+    loop {
+        *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = *state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        let hash = ((z ^ (z >> 31)) & HASH_MASK) as u32;
+        if hash != 0 {
+            return hash;
+        }
     }
 }
@@ -1,6 +1,7 @@
 use super::*;
 
 use std::path::Path;
+use std::sync::Arc;
 use ocelotter_util::file_to_bytes;
 
 #[test]
@@ -80,3 +81,472 @@ fn check_system_current_timemillis() {
 // assert_eq!("SampleInvoke", k.get_name());
 // assert_eq!("java/lang/Object", k.get_super_name());
 // assert_eq!(4, k.get_methods().len());
+
+#[test]
+fn test_narrow_and_widen_values() {
+    assert_eq!(Some(-56), JvmValue::Int(200).narrow_to('B').as_byte());
+    assert_eq!(Some(4464), JvmValue::Int(70000).narrow_to('S').as_short());
+    assert_eq!(Some(0xd800), JvmValue::Int(0x1d800).narrow_to('C').as_char());
+    assert_eq!(Some(false), JvmValue::Int(2).narrow_to('Z').as_bool());
+    assert_eq!(Some(200), JvmValue::Int(200).narrow_to('I').as_int());
+
+    assert_eq!(Some(-56), JvmValue::Byte(-56).to_computational().as_int());
+    assert_eq!(Some(0xffff), JvmValue::Char(0xffff).to_computational().as_int());
+    assert_eq!(Some(1), JvmValue::Boolean(true).to_computational().as_int());
+    assert_eq!(Some(3), JvmValue::Long(3).to_computational().as_long());
+
+    let mut lvt = InterpLocalVars::of(1);
+    lvt.store(0, JvmValue::Short(-1));
+    assert_eq!(Some(-1), lvt.load(0).as_int());
+}
+
+#[test]
+fn test_decode_resolves_operands_and_targets() {
+    use crate::instruction::{decode, Instruction};
+
+    let code = vec![
+        opcode::NOP,                  // 0
+        opcode::BIPUSH, 0xff,         // 1
+        opcode::SIPUSH, 0x80, 0x00,   // 3
+        opcode::GOTO, 0xff, 0xfa,     // 6, back to 0
+        opcode::IINC, 1, 0xfe,        // 9
+        opcode::TABLESWITCH, 0, 0, 0, // 12, padded to a multiple of 4
+        0, 0, 0, 20,                  // default, to 32
+        0, 0, 0, 1,                   // low
+        0, 0, 0, 1,                   // high
+        0xff, 0xff, 0xff, 0xfd,       // 1, back to 9
+        opcode::IRETURN,              // 32
+    ];
+
+    let instrs = decode(&code, &[]);
+    assert_eq!(7, instrs.len());
+    assert!(matches!(instrs[1], Instruction::Iconst(-1)));
+    assert!(matches!(instrs[2], Instruction::Iconst(-32768)));
+    assert!(matches!(instrs[3], Instruction::Goto(0)));
+    assert!(matches!(instrs[4], Instruction::Iinc(1, -2)));
+    match &instrs[5] {
+        Instruction::TableSwitch(ts) => {
+            assert_eq!(1, ts.low);
+            assert_eq!(vec![4], ts.targets);
+            assert_eq!(6, ts.default);
+        }
+        other => panic!("Expected TABLESWITCH, got {:?}", other),
+    }
+    assert!(matches!(instrs[6], Instruction::ReturnValue));
+}
+
+#[test]
+fn test_resolved_cp_entries_are_cached() {
+    use crate::instruction::Instruction;
+
+    let bytes = match file_to_bytes(Path::new("../resources/test/Counter.class")) {
+        Ok(buf) => buf,
+        _ => panic!("Error reading Counter"),
+    };
+    let mut parser = klass_parser::OtKlassParser::of(bytes, "Counter.class".to_string());
+    parser.parse();
+    let mut repo = SharedKlassRepo::of();
+    repo.add_klass(&parser.klass());
+    let k = repo.lookup_klass(&"Counter".to_string());
+
+    // bumpTwice starts with INVOKESTATIC Counter.bump
+    let twice = k
+        .get_method_by_name_and_desc(&"Counter.bumpTwice:()I".to_string())
+        .unwrap();
+    let invoke_idx = match twice.get_instructions()[0] {
+        Instruction::InvokeStatic(idx) => idx,
+        ref other => panic!("Expected INVOKESTATIC, got {:?}", other),
+    };
+    let (callee_klass, m_idx, arg_count) = repo.resolve_method(&k, invoke_idx);
+    assert_eq!("Counter", callee_klass.get_name());
+    assert_eq!("Counter.bump:()I", callee_klass.get_method(m_idx).get_fq_name_desc());
+    assert_eq!(0, arg_count);
+    let (again, _, _) = repo.resolve_method(&k, invoke_idx);
+    assert!(Arc::ptr_eq(&callee_klass, &again));
+
+    // bump starts with GETSTATIC Counter.count
+    let bump = k
+        .get_method_by_name_and_desc(&"Counter.bump:()I".to_string())
+        .unwrap();
+    let getstatic_idx = match bump.get_instructions()[0] {
+        Instruction::GetStatic(idx) => idx,
+        ref other => panic!("Expected GETSTATIC, got {:?}", other),
+    };
+    let (target, slot, field) = repo.resolve_static_field(&k, getstatic_idx);
+    assert!(Arc::ptr_eq(&k, &target));
+    target.put_static_at(slot, field, JvmValue::Int(42));
+    let (_, slot, _) = repo.resolve_static_field(&k, getstatic_idx);
+    assert_eq!(Some(42), k.get_static_at(slot).as_int());
+}
+
+#[test]
+fn test_frame_stack_lists_innermost_first() {
+    use crate::frame::{Frame, FrameStack};
+
+    let bytes = match file_to_bytes(Path::new("../resources/test/Counter.class")) {
+        Ok(buf) => buf,
+        _ => panic!("Error reading Counter"),
+    };
+    let mut parser = klass_parser::OtKlassParser::of(bytes, "Counter.class".to_string());
+    parser.parse();
+    let mut repo = SharedKlassRepo::of();
+    repo.add_klass(&parser.klass());
+    let k = repo.lookup_klass(&"Counter".to_string());
+
+    let twice = k.get_method_index(&"Counter.bumpTwice:()I".to_string()).unwrap();
+    let bump = k.get_method_index(&"Counter.bump:()I".to_string()).unwrap();
+    let mut frames = FrameStack::of();
+    assert!(frames.push(Frame::of(k.clone(), twice, InterpLocalVars::of(0))));
+    frames.top().unwrap().pc = 1;
+    assert!(frames.push(Frame::of(k.clone(), bump, InterpLocalVars::of(0))));
+
+    assert_eq!(2, frames.depth());
+    assert_eq!(
+        "\tat Counter.bump:()I @ 0\n\tat Counter.bumpTwice:()I @ 1\n",
+        frames.to_string()
+    );
+}
+
+#[test]
+fn test_frame_stack_enforces_max_size() {
+    use crate::frame::{Frame, FrameStack};
+
+    let bytes = match file_to_bytes(Path::new("../resources/test/Counter.class")) {
+        Ok(buf) => buf,
+        _ => panic!("Error reading Counter"),
+    };
+    let mut parser = klass_parser::OtKlassParser::of(bytes, "Counter.class".to_string());
+    parser.parse();
+    let k = std::sync::Arc::new(parser.klass());
+    let bump = k.get_method_index(&"Counter.bump:()I".to_string()).unwrap();
+
+    let size = Frame::of(k.clone(), bump, InterpLocalVars::of(0)).size();
+    let mut frames = FrameStack::with_max_size(2 * size);
+    assert!(frames.push(Frame::of(k.clone(), bump, InterpLocalVars::of(0))));
+    assert!(frames.push(Frame::of(k.clone(), bump, InterpLocalVars::of(0))));
+    assert!(!frames.push(Frame::of(k.clone(), bump, InterpLocalVars::of(0))));
+    assert_eq!(2, frames.depth());
+
+    frames.pop();
+    assert!(frames.push(Frame::of(k.clone(), bump, InterpLocalVars::of(0))));
+}
+
+#[test]
+fn test_monitors_are_reentrant_and_exclusive() {
+    use crate::monitor::{current_thread_id, monitor_enter, monitor_exit};
+    use std::thread;
+    use std::time::Duration;
+
+    let vm = Vm::of();
+    let id = vm.heap().allocate_int_arr(0);
+    let me = current_thread_id();
+    monitor_enter(&vm, id);
+    let hash = vm.heap().identity_hash(id);
+    monitor_enter(&vm, id);
+    {
+        let heap = vm.heap();
+        assert_eq!(me, heap.get_obj(id).monitor_owner());
+        assert_eq!(2, heap.get_obj(id).monitor_count());
+    }
+
+    let shared = vm.clone();
+    let other = thread::spawn(move || {
+        monitor_enter(&shared, id);
+        let owner = shared.heap().get_obj(id).monitor_owner();
+        assert!(monitor_exit(&shared, id));
+        owner
+    });
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(me, vm.heap().get_obj(id).monitor_owner());
+
+    assert!(monitor_exit(&vm, id));
+    assert!(monitor_exit(&vm, id));
+    let other_id = other.join().unwrap();
+    assert_ne!(me, other_id);
+    assert_eq!(0, vm.heap().get_obj(id).monitor_owner());
+    assert_eq!(0, vm.heap().get_obj(id).monitor_count());
+
+    // The hash shares the mark word, but outlives the monitor
+    assert_ne!(0, hash);
+    assert_eq!(hash, vm.heap().identity_hash(id));
+
+    // Not held any more
+    assert!(!monitor_exit(&vm, id));
+}
+
+#[test]
+fn test_thread_ids_are_reused_to_fit_mark_words() {
+    use crate::monitor::ThreadId;
+
+    let vm = Vm::of();
+    let id = vm.heap().allocate_int_arr(0);
+    // More threads than a mark word has owner bits for, one after another
+    for _ in 0..(1 << 20) + 10 {
+        let thread = ThreadId::allocate();
+        let mut heap = vm.heap();
+        assert!(heap.try_monitor_enter(id, thread.get()));
+        assert_eq!(Some(true), heap.monitor_exit(id, thread.get()));
+    }
+    let spawned = std::thread::spawn(crate::monitor::current_thread_id).join().unwrap();
+    assert!(spawned < 1 << 20);
+}
+
+#[test]
+fn test_wait_gives_up_monitor_until_notified() {
+    use crate::monitor::{interrupt, monitor_enter, monitor_exit, notify, sleep, wait, WaitError};
+    use std::thread;
+    use std::time::Duration;
+
+    let vm = Vm::of();
+    let id = vm.heap().allocate_int_arr(0);
+    // Stand-ins for the waiting threads' Thread objects
+    let (waiter, sleeper) = (1_000_001, 1_000_002);
+
+    assert_eq!(Err(WaitError::NotOwner), wait(&vm, id, waiter, None));
+    assert!(!notify(&vm, id, false));
+
+    monitor_enter(&vm, id);
+    monitor_enter(&vm, id);
+    let shared = vm.clone();
+    let other = thread::spawn(move || {
+        // Only gets the monitor once the waiter has let go of both holds
+        monitor_enter(&shared, id);
+        assert!(notify(&shared, id, false));
+        assert!(monitor_exit(&shared, id));
+    });
+    assert_eq!(Ok(()), wait(&vm, id, waiter, None));
+    other.join().unwrap();
+    assert_eq!(2, vm.heap().get_obj(id).monitor_count());
+    assert!(monitor_exit(&vm, id));
+    assert!(monitor_exit(&vm, id));
+
+    interrupt(&vm, sleeper);
+    assert_eq!(Err(WaitError::Interrupted), sleep(&vm, sleeper, Duration::from_secs(60)));
+}
+
+#[test]
+fn test_collect_frees_unreachable_objects() {
+    use crate::simple_heap::SharedSimpleHeap;
+
+    let bytes = file_to_bytes(Path::new("../resources/test/Garbage.class")).unwrap();
+    let mut parser = klass_parser::OtKlassParser::of(bytes, "Garbage.class".to_string());
+    parser.parse();
+    let k = parser.klass();
+    let next = k
+        .instance_layout(Vec::new)
+        .iter()
+        .find(|f| f.get_name_desc() == "next:LGarbage;")
+        .unwrap()
+        .clone();
+
+    // A private heap, so other tests' objects don't come into it
+    let mut heap = SharedSimpleHeap::of();
+    let head = heap.allocate_obj(&k);
+    let tail = heap.allocate_obj(&k);
+    let unreachable = heap.allocate_obj(&k);
+    let pinned = heap.allocate_int_arr(1);
+    let dropped = heap.allocate_int_arr(1);
+    heap.put_field(head, &next, JvmValue::ObjRef(tail));
+    heap.put_field(unreachable, &next, JvmValue::ObjRef(head));
+    heap.pin(pinned);
+
+    assert_eq!(2, heap.collect(vec![head]));
+    assert_eq!(3, heap.live_objects());
+    assert!(matches!(heap.get_field(head, next.get_offset()), JvmValue::ObjRef(id) if id == tail));
+
+    // Freed slots are handed out again
+    let mut reused = vec![heap.allocate_int_arr(0), heap.allocate_int_arr(0)];
+    reused.sort();
+    assert_eq!(vec![unreachable, dropped], reused);
+
+    heap.unpin(pinned);
+    assert_eq!(5, heap.collect(vec![]));
+    assert_eq!(0, heap.live_objects());
+}
+
+#[test]
+fn test_mark_compact_moves_objects_but_keeps_their_ids() {
+    use crate::simple_heap::{Collector, SharedSimpleHeap};
+
+    let mut heap = SharedSimpleHeap::of();
+    heap.set_collector(Collector::MarkCompact);
+    let ids: Vec<usize> = (0..6)
+        .map(|i| {
+            let id = heap.allocate_int_arr(1);
+            heap.iastore(id, 0, i);
+            id
+        })
+        .collect();
+    let kept = vec![ids[1], ids[3], ids[5]];
+    let slots_before: Vec<usize> = kept.iter().map(|&id| heap.slot_of(id).unwrap()).collect();
+    let hash = heap.identity_hash(ids[5]);
+
+    assert_eq!(3, heap.collect(kept.clone()));
+    assert_eq!(4, heap.slots());
+    for (i, &id) in kept.iter().enumerate() {
+        assert!(heap.slot_of(id).unwrap() < slots_before[i]);
+        assert!(matches!(heap.get_obj(id), OtObj::VmArrInt { elements, .. } if elements[0] == 2 * i as i32 + 1));
+    }
+    for &id in [ids[0], ids[2], ids[4]].iter() {
+        assert_eq!(None, heap.slot_of(id));
+    }
+    assert_eq!(hash, heap.identity_hash(ids[5]));
+
+    // New objects go on the end, and freed ids are handed out again
+    let fresh = heap.allocate_int_arr(1);
+    assert!([ids[0], ids[2], ids[4]].contains(&fresh));
+    assert_eq!(Some(4), heap.slot_of(fresh));
+}
+
+#[test]
+fn test_heap_limits_allocations_by_java_code() {
+    use crate::simple_heap::SharedSimpleHeap;
+
+    let mut heap = SharedSimpleHeap::of();
+    heap.set_limits(1024, 4096);
+    assert_eq!(None, heap.try_allocate_int_arr(1024));
+    assert_eq!(0, heap.used_bytes());
+
+    let small = heap.try_allocate_int_arr(16).unwrap();
+    let size = heap.get_obj(small).size();
+    assert!(size >= 64);
+    assert_eq!(size, heap.used_bytes());
+
+    // The VM's own allocations can go over, growing the heap
+    heap.allocate_int_arr(1024);
+    assert!(heap.used_bytes() > 4096);
+    assert_eq!(heap.used_bytes(), heap.total_bytes());
+    assert_eq!(None, heap.try_allocate_int_arr(0));
+
+    heap.collect(vec![small]);
+    assert_eq!(size, heap.used_bytes());
+    assert!(heap.try_allocate_int_arr(16).is_some());
+}
+
+#[test]
+fn test_hprof_dump_describes_every_object() {
+    use crate::simple_heap::SharedSimpleHeap;
+    use byteorder::{BigEndian, ByteOrder};
+
+    let bytes = file_to_bytes(Path::new("../resources/test/Garbage.class")).unwrap();
+    let mut parser = klass_parser::OtKlassParser::of(bytes, "Garbage.class".to_string());
+    parser.parse();
+    let mut repo = SharedKlassRepo::of();
+    repo.add_klass(&parser.klass());
+    let k = repo.lookup_klass(&"Garbage".to_string());
+    let next = k.instance_layout(Vec::new)[0].clone();
+    let kept = k.get_static_field_by_name_and_desc(&"Garbage.kept:LGarbage;".to_string()).unwrap();
+
+    let mut heap = SharedSimpleHeap::of();
+    let head = heap.allocate_obj(&k);
+    let tail = heap.allocate_obj(&k);
+    heap.put_field(head, &next, JvmValue::ObjRef(tail));
+    k.put_static(kept, JvmValue::ObjRef(head));
+    let chars = heap.allocate_narrow_arr('C', 2);
+    heap.pin(chars);
+    heap.allocate_int_arr(3);
+    let garbages = heap.allocate_obj_arr(&k, 2);
+    heap.obj_arr_store(garbages, 1, head);
+
+    let mut dump = vec![];
+    heap.dump_hprof(&repo, &[tail], &mut dump).unwrap();
+    assert!(dump.starts_with(b"JAVA PROFILE 1.0.2\0"));
+    assert_eq!(8, BigEndian::read_u32(&dump[19..]));
+
+    // Walks the records, counting them by tag, sub-records of heap dump
+    // segments included
+    let value_size = |t: u8| match t {
+        4 | 8 => 1,
+        5 | 9 => 2,
+        6 | 10 => 4,
+        _ => 8,
+    };
+    let mut tags = std::collections::HashMap::new();
+    let mut names = vec![];
+    let mut at = 31;
+    while at < dump.len() {
+        let (tag, len) = (dump[at], BigEndian::read_u32(&dump[at + 5..]) as usize);
+        let body = &dump[at + 9..at + 9 + len];
+        *tags.entry(tag as u32).or_insert(0) += 1;
+        if tag == 0x01 {
+            names.push(String::from_utf8(body[8..].to_vec()).unwrap());
+        }
+        let mut s = 0;
+        while tag == 0x1C && s < len {
+            let (sub, b) = (body[s], &body[s + 1..]);
+            *tags.entry(0x100 | sub as u32).or_insert(0) += 1;
+            s += 1 + match sub {
+                0xFF | 0x05 => 8,
+                0x01 => 16,
+                0x20 => {
+                    let mut c = 7 * 8 + 4 + 4;
+                    assert_eq!(0, BigEndian::read_u16(&b[c..]));
+                    c += 2;
+                    let statics = BigEndian::read_u16(&b[c..]);
+                    c += 2;
+                    for _ in 0..statics {
+                        c += 8 + 1 + value_size(b[c + 8]);
+                    }
+                    c + 2 + 9 * BigEndian::read_u16(&b[c..]) as usize
+                }
+                0x21 => 8 + 4 + 8 + 4 + BigEndian::read_u32(&b[20..]) as usize,
+                0x22 => {
+                    let length = BigEndian::read_u32(&b[12..]) as usize;
+                    assert_eq!(head as u64, BigEndian::read_u64(&b[32..]));
+                    8 + 4 + 4 + 8 + length * 8
+                }
+                0x23 => {
+                    let length = BigEndian::read_u32(&b[12..]) as usize;
+                    8 + 4 + 4 + 1 + length * value_size(b[16])
+                }
+                other => panic!("Unexpected heap dump sub-record {:#x}", other),
+            };
+        }
+        at += 9 + len;
+    }
+    assert_eq!(dump.len(), at);
+    assert!(names.contains(&"Garbage".to_string()));
+    assert!(names.contains(&"kept".to_string()));
+    assert!(names.contains(&"[LGarbage;".to_string()));
+    assert_eq!(Some(&2), tags.get(&0x02));
+    assert_eq!(Some(&1), tags.get(&0x2C));
+    assert_eq!(Some(&1), tags.get(&0x1FF));
+    assert_eq!(Some(&1), tags.get(&0x101));
+    assert_eq!(Some(&2), tags.get(&0x120));
+    assert_eq!(Some(&2), tags.get(&0x121));
+    assert_eq!(Some(&1), tags.get(&0x122));
+    assert_eq!(Some(&2), tags.get(&0x123));
+}
+
+#[test]
+fn test_histogram_groups_objects_by_class() {
+    use crate::simple_heap::SharedSimpleHeap;
+
+    let bytes = file_to_bytes(Path::new("../resources/test/Garbage.class")).unwrap();
+    let mut parser = klass_parser::OtKlassParser::of(bytes, "Garbage.class".to_string());
+    parser.parse();
+    let mut repo = SharedKlassRepo::of();
+    repo.add_klass(&parser.klass());
+    let k = repo.lookup_klass(&"Garbage".to_string());
+    k.instance_layout(Vec::new);
+
+    let mut heap = SharedSimpleHeap::of();
+    for _ in 0..3 {
+        heap.allocate_obj(&k);
+    }
+    let big = heap.allocate_int_arr(100);
+    heap.allocate_int_arr(1);
+    heap.allocate_narrow_arr('C', 1);
+    heap.allocate_obj_arr(&k, 0);
+
+    let histo = heap.histogram(&repo);
+    let names: Vec<&str> = histo.entries().iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(vec!["[I", "Garbage", "[C", "[LGarbage;"], names);
+    let ints = histo.get("[I").unwrap();
+    assert_eq!(2, ints.instances);
+    assert_eq!(heap.get_obj(big).size() + OtObj::arr_size('I', 1), ints.bytes);
+    let garbage = histo.get("Garbage").unwrap();
+    assert_eq!(3, garbage.instances);
+    assert_eq!(3 * OtObj::obj_size(2), garbage.bytes);
+    assert!(histo.to_string().contains("   2:              3"));
+}
@@ -0,0 +1,234 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::monitor::{self, WaitError};
+use crate::otfield::OtField;
+use crate::vm::Vm;
+use crate::InterpLocalVars;
+use crate::JvmValue;
+
+// Each started java.lang.Thread runs on an OS thread of its own, which
+// runs the thread's run() with the interpreter the VM was bootstrapped
+// with. Heap ids of Thread objects identify the Java threads.
+//
+// In green mode, all Java threads share the OS thread that turned it on
+// and the interpreter's scheduler switches between them. Threads started
+// there are queued for the scheduler.
+//
+// Natives that would block suspend the thread instead, leaving the
+// interpreter to block the OS thread, or switch away from a green thread,
+// once it is back in its loop.
+
+const THREAD_KLASS: &str = "java/lang/Thread";
+const NORM_PRIORITY: i32 = 5;
+
+// Each VM's threads
+#[derive(Debug, Default)]
+pub struct Threads {
+    // Threads that have been started and whose run() hasn't finished
+    alive: Mutex<HashSet<usize>>,
+}
+
+thread_local! {
+    // This OS thread's Thread object in each VM it has asked for one in,
+    // by VM id
+    static CURRENT_THREAD: RefCell<HashMap<usize, usize>> = RefCell::new(HashMap::new());
+    static GREEN: Cell<bool> = const { Cell::new(false) };
+    // Green threads started since the scheduler last looked
+    static STARTED: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+    // Set by a native for the scheduler to act on once it returns
+    static SUSPEND: Cell<Option<Suspend>> = const { Cell::new(None) };
+}
+
+// Why a native suspended the thread
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Suspend {
+    Yield,
+    // In wait() on obj, having let go of its monitor count times, or in
+    // sleep() when obj is 0
+    Wait {
+        obj: usize,
+        count: u32,
+        timeout: Option<Duration>,
+    },
+}
+
+pub fn set_green(green: bool) -> () {
+    GREEN.with(|g| g.set(green));
+}
+
+pub fn is_green() -> bool {
+    GREEN.with(|g| g.get())
+}
+
+pub fn take_started() -> Vec<usize> {
+    STARTED.with(|s| s.replace(Vec::new()))
+}
+
+pub fn take_suspend() -> Option<Suspend> {
+    SUSPEND.with(|s| s.take())
+}
+
+fn suspend(why: Suspend) -> () {
+    SUSPEND.with(|s| s.set(Some(why)));
+}
+
+// Switches this OS thread to another green thread's Thread object
+pub fn set_current_thread(vm: &Vm, id: usize) -> () {
+    CURRENT_THREAD.with(|t| t.borrow_mut().insert(vm.id(), id));
+}
+
+// Threads not started from Java, such as the one running main, get a
+// Thread object the first time they ask for one
+pub fn current_thread(vm: &Vm) -> usize {
+    if let Some(id) = CURRENT_THREAD.with(|t| t.borrow().get(&vm.id()).copied()) {
+        return id;
+    }
+    let repo = vm.repo();
+    let klass = repo.lookup_klass(&THREAD_KLASS.to_string());
+    repo.instance_fields(&klass);
+    let id = {
+        let mut heap = vm.heap();
+        let id = heap.allocate_obj(&klass);
+        heap.pin(id);
+        id
+    };
+    init_thread(vm, id, 0);
+    vm.threads.alive.lock().unwrap().insert(id);
+    set_current_thread(vm, id);
+    id
+}
+
+// Stands in for Thread's constructors, which need strings and thread
+// groups. FIXME No name or group is set.
+pub fn init_thread(vm: &Vm, id: usize, target: usize) -> () {
+    let priority = thread_field(vm, "priority:I");
+    let runnable = thread_field(vm, "target:Ljava/lang/Runnable;");
+    let heap = vm.heap();
+    heap.put_field(id, &priority, JvmValue::Int(NORM_PRIORITY));
+    heap.put_field(id, &runnable, JvmValue::ObjRef(target));
+}
+
+fn thread_field(vm: &Vm, name_desc: &str) -> OtField {
+    let repo = vm.repo();
+    let klass = repo.lookup_klass(&THREAD_KLASS.to_string());
+    let field = repo
+        .instance_fields(&klass)
+        .iter()
+        .find(|f| f.get_name_desc() == name_desc)
+        .cloned();
+    field.unwrap_or_else(|| panic!("No field {} found on {}", name_desc, THREAD_KLASS))
+}
+
+// Calls run() on the thread object, virtually, on a new OS thread.
+// Returns false if the thread is already running.
+pub fn start(vm: &Vm, id: usize) -> bool {
+    if !vm.threads.alive.lock().unwrap().insert(id) {
+        return false;
+    }
+    // Other threads may only know of a running thread by its id
+    vm.heap().pin(id);
+    if is_green() {
+        STARTED.with(|s| s.borrow_mut().push(id));
+        return true;
+    }
+    let mut vm = vm.clone();
+    std::thread::spawn(move || {
+        let _alive = Alive(vm.clone(), id);
+        set_current_thread(&vm, id);
+
+        let klass = vm.repo().klass_by_id(vm.heap().get_obj(id).get_klassid());
+        let (run_klass, m_idx) = vm
+            .repo()
+            .find_method(&klass, "run:()V")
+            .unwrap_or_else(|| panic!("No run() found on {}", klass.get_name()));
+        let mut vars = InterpLocalVars::of(1);
+        vars.store(0, JvmValue::ObjRef(id));
+        vm.interpret(run_klass.get_method(m_idx), &mut vars);
+    });
+    true
+}
+
+pub fn is_alive(vm: &Vm, id: usize) -> bool {
+    vm.threads.alive.lock().unwrap().contains(&id)
+}
+
+pub fn sleep(vm: &Vm, millis: u64) -> Result<(), WaitError> {
+    let me = current_thread(vm);
+    let time = Duration::from_millis(millis);
+    monitor::begin_sleep(vm, me).map_err(|e| interrupted(vm, me, e))?;
+    suspend(Suspend::Wait { obj: 0, count: 0, timeout: Some(time) });
+    Ok(())
+}
+
+// Object.wait() on obj, a timeout of None waiting for as long as it takes
+pub fn wait(vm: &Vm, obj: usize, timeout: Option<Duration>) -> Result<(), WaitError> {
+    let me = current_thread(vm);
+    let count = monitor::begin_wait(vm, obj, me).map_err(|e| interrupted(vm, me, e))?;
+    suspend(Suspend::Wait { obj, count, timeout });
+    Ok(())
+}
+
+// Blocks an OS thread for the rest of the wait() or sleep() a native
+// suspended it for
+pub fn end_wait(vm: &Vm, obj: usize, count: u32, timeout: Option<Duration>) -> Result<(), WaitError> {
+    let me = current_thread(vm);
+    monitor::end_wait(vm, obj, me, count, timeout).map_err(|e| interrupted(vm, me, e))
+}
+
+// Sends the thread back round the interpreter's loop before the native's
+// caller carries on, green or not, e.g. to run queued finalizers
+pub fn yield_to_interpreter() -> () {
+    suspend(Suspend::Yield);
+}
+
+pub fn yield_now() -> () {
+    if is_green() {
+        suspend(Suspend::Yield);
+    } else {
+        std::thread::yield_now();
+    }
+}
+
+// Intrinsic for Thread.interrupt(), which in the library only sets the
+// flag isInterrupted() reads
+pub fn interrupt(vm: &Vm, id: usize) -> () {
+    let flag = thread_field(vm, "interruptRequested:Z");
+    vm.heap().put_field(id, &flag, JvmValue::Boolean(true));
+    monitor::interrupt(vm, id);
+}
+
+// Throwing InterruptedException clears the thread's interrupted flag
+fn interrupted(vm: &Vm, id: usize, e: WaitError) -> WaitError {
+    if e == WaitError::Interrupted {
+        clear_interrupt(vm, id);
+    }
+    e
+}
+
+pub fn clear_interrupt(vm: &Vm, id: usize) -> () {
+    let flag = thread_field(vm, "interruptRequested:Z");
+    vm.heap().put_field(id, &flag, JvmValue::Boolean(false));
+}
+
+// Marks the thread as finished and wakes any threads in join(), which wait
+// on the Thread object. The caller holds its monitor.
+pub fn finish(vm: &Vm, id: usize) -> () {
+    vm.threads.alive.lock().unwrap().remove(&id);
+    monitor::notify(vm, id, true);
+    vm.heap().unpin(id);
+}
+
+// Marks a started thread as finished when its OS thread is done with it,
+// including when run() ends in an uncaught exception
+struct Alive(Vm, usize);
+
+impl Drop for Alive {
+    fn drop(&mut self) {
+        monitor::monitor_enter(&self.0, self.1);
+        finish(&self.0, self.1);
+        monitor::monitor_exit(&self.0, self.1);
+    }
+}
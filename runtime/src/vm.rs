@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, RwLock};
+
+use crate::constant_pool::CpEntry;
+use crate::gc::GcState;
+use crate::klass_repo::{Mirrored, SharedKlassRepo};
+use crate::monitor::Monitors;
+use crate::otklass::OtKlass;
+use crate::otmethod::OtMethod;
+use crate::simple_heap::SharedSimpleHeap;
+use crate::thread::Threads;
+use crate::InterpLocalVars;
+use crate::JvmValue;
+
+// A Java virtual machine: its heap, the klasses loaded into it, and the
+// state of the threads running them. VMs share nothing, so any number of
+// them can run side by side in one process, and everything a VM holds is
+// freed once the last handle on it is dropped.
+
+// Runs a method to completion on the calling thread, see bootstrap()
+pub type InterpCallback = fn(&mut Vm, &OtMethod, &mut InterpLocalVars) -> Option<JvmValue>;
+
+// Tells VMs apart in per-OS-thread state, such as the current Thread
+static NEXT_VM_ID: AtomicUsize = AtomicUsize::new(1);
+
+#[derive(Debug, Default)]
+struct Mirrors {
+    // Every mirror handed out, by object id
+    of: HashMap<usize, Mirrored>,
+    // Array types have no klass to keep their mirror on
+    arrays: HashMap<String, usize>,
+}
+
+// Cloning a Vm gives another handle on the same VM, so each OS thread
+// running its Java code can have one
+#[derive(Clone)]
+pub struct Vm {
+    id: usize,
+    repo: SharedKlassRepo,
+    heap: Arc<Mutex<SharedSimpleHeap>>,
+    // String constants, by their contents
+    strings: Arc<Mutex<HashMap<String, usize>>>,
+    mirrors: Arc<RwLock<Mirrors>>,
+    pub(crate) gc: Arc<GcState>,
+    pub(crate) monitors: Arc<Monitors>,
+    pub(crate) threads: Arc<Threads>,
+    interpreter: Arc<OnceLock<InterpCallback>>,
+}
+
+impl Vm {
+    pub fn of() -> Vm {
+        let heap = SharedSimpleHeap::of();
+        Vm {
+            id: NEXT_VM_ID.fetch_add(1, Ordering::SeqCst),
+            repo: SharedKlassRepo::of(),
+            gc: heap.gc_state(),
+            heap: Arc::new(Mutex::new(heap)),
+            strings: Arc::new(Mutex::new(HashMap::new())),
+            mirrors: Arc::new(RwLock::new(Mirrors::default())),
+            monitors: Arc::new(Monitors::default()),
+            threads: Arc::new(Threads::default()),
+            interpreter: Arc::new(OnceLock::new()),
+        }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn repo(&self) -> &SharedKlassRepo {
+        &self.repo
+    }
+
+    pub fn heap(&self) -> MutexGuard<'_, SharedSimpleHeap> {
+        self.heap.lock().unwrap()
+    }
+
+    // Loads the bootstrap classes into the repo, then runs what static
+    // initializers they need with the interpreter, i_callback
+    pub fn bootstrap(&mut self, i_callback: InterpCallback) -> () {
+        if self.interpreter.set(i_callback).is_err() {
+            panic!("VM has already been bootstrapped")
+        }
+        self.repo.bootstrap();
+
+        // // This requires the file descriptor handling to already exist
+        // self.run_clinit_method(&"java/lang/System".to_string());
+        self.run_clinit_method(&"java/io/FileDescriptor".to_string());
+    }
+
+    // Runs a method on the calling thread with the interpreter passed to
+    // bootstrap(), e.g. the body of a newly started Java thread
+    pub fn interpret(&mut self, meth: &OtMethod, lvt: &mut InterpLocalVars) -> Option<JvmValue> {
+        let i_callback = *self
+            .interpreter
+            .get()
+            .expect("VM has not been bootstrapped");
+        i_callback(self, meth, lvt)
+    }
+
+    fn run_clinit_method(&mut self, klass_name: &String) {
+        let m_str = klass_name.to_owned() + ".<clinit>:()V";
+        let k = self.repo.lookup_klass(klass_name);
+        k.begin_initialization();
+        let clinit = match k.get_method_by_name_and_desc(&m_str) {
+            Some(value) => value.clone(),
+            // FIXME Make this a clean exit
+            None => panic!("Error: Clinit method not found {}", klass_name),
+        };
+        // FIXME Parameter passing
+        let mut vars = InterpLocalVars::of(5);
+        self.interpret(&clinit, &mut vars);
+    }
+
+    // Objects the VM itself holds on to, as roots for the collector
+    pub fn roots(&self) -> Vec<usize> {
+        let mut roots = self.repo.static_refs();
+        roots.extend(self.strings.lock().unwrap().values());
+        roots
+    }
+
+    //////////////////////////////////////////////
+    // Strings
+
+    // A new java/lang/String holding s
+    pub fn new_string(&self, s: &str) -> usize {
+        let klass = self.repo.lookup_klass(&"java/lang/String".to_string());
+        let fields = self.repo.instance_fields(&klass);
+        let chars: Vec<u16> = s.encode_utf16().collect();
+
+        let mut heap = self.heap();
+        let value = heap.allocate_narrow_arr('C', chars.len() as i32);
+        for (i, &c) in chars.iter().enumerate() {
+            heap.narrow_arr_store(value, i as i32, JvmValue::Int(c as i32));
+        }
+        let string = heap.allocate_obj(&klass);
+        for f in fields {
+            match f.get_name().as_str() {
+                "value" => heap.put_field(string, f, JvmValue::ObjRef(value)),
+                "count" => heap.put_field(string, f, JvmValue::Int(chars.len() as i32)),
+                _ => (),
+            }
+        }
+        string
+    }
+
+    // The one String object with these contents, for string constants
+    pub fn intern(&self, s: &str) -> usize {
+        let mut strings = self.strings.lock().unwrap();
+        if let Some(&id) = strings.get(s) {
+            return id;
+        }
+        let id = self.new_string(s);
+        strings.insert(s.to_string(), id);
+        id
+    }
+
+    // The String object LDC pushes for a CP string entry
+    pub fn resolve_string(&self, klass: &OtKlass, idx: u16) -> usize {
+        match klass.lookup_cp(idx) {
+            CpEntry::String(s) => self.intern(&klass.cp_as_string(s.0)),
+            other => panic!(
+                "CP index {} on {} is {:?}, not a string",
+                idx,
+                klass.get_name(),
+                other
+            ),
+        }
+    }
+
+    //////////////////////////////////////////////
+    // Class objects
+
+    // The Class object for klass, made the first time it's asked for
+    pub fn mirror(&self, klass: &OtKlass) -> usize {
+        klass.get_mirror(|| {
+            let id = self.new_mirror();
+            self.mirrors
+                .write()
+                .unwrap()
+                .of
+                .insert(id, Mirrored::Klass(klass.get_id()));
+            id
+        })
+    }
+
+    // The Class object for an array type. These are pinned, as no klass
+    // holds on to them.
+    pub fn array_mirror(&self, desc: &str) -> usize {
+        let mut mirrors = self.mirrors.write().unwrap();
+        if let Some(&id) = mirrors.arrays.get(desc) {
+            return id;
+        }
+        let id = self.new_mirror();
+        self.heap().pin(id);
+        mirrors.arrays.insert(desc.to_string(), id);
+        mirrors.of.insert(id, Mirrored::Array(desc.to_string()));
+        id
+    }
+
+    // The Class object for a klass or array type by internal name
+    pub fn mirror_by_name(&self, name: &str) -> usize {
+        if name.starts_with('[') {
+            self.array_mirror(name)
+        } else {
+            self.mirror(&self.repo.lookup_klass(&name.to_string()))
+        }
+    }
+
+    pub fn mirror_for(&self, mirrored: &Mirrored) -> usize {
+        match mirrored {
+            Mirrored::Klass(id) => self.mirror(&self.repo.klass_by_id(*id)),
+            Mirrored::Array(desc) => self.array_mirror(desc),
+        }
+    }
+
+    // What a Class object stands for, None if id isn't one
+    pub fn mirrored(&self, id: usize) -> Option<Mirrored> {
+        self.mirrors.read().unwrap().of.get(&id).cloned()
+    }
+
+    // The Class object LDC pushes for a CP class entry
+    pub fn resolve_mirror(&self, klass: &OtKlass, idx: u16) -> usize {
+        match klass.lookup_cp(idx) {
+            CpEntry::Class(c) if klass.cp_as_string(c.0).starts_with('[') => {
+                self.array_mirror(&klass.cp_as_string(c.0))
+            }
+            _ => self.mirror(&self.repo.resolve_klass(klass, idx)),
+        }
+    }
+
+    fn new_mirror(&self) -> usize {
+        let class = self.repo.lookup_klass(&"java/lang/Class".to_string());
+        self.repo.instance_fields(&class);
+        self.heap().allocate_obj(&class)
+    }
+}
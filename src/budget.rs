@@ -0,0 +1,82 @@
+use std::fmt;
+use std::time::Instant;
+
+// How many instructions run between checks of the deadline
+const SLICE: u64 = 10_000;
+
+// Limits on how much a run of the interpreter may do, for executing code
+// that can't be trusted to terminate
+#[derive(Clone, Debug)]
+pub struct Budget {
+    fuel: u64,
+    deadline: Option<Instant>,
+}
+
+impl Budget {
+    pub fn unlimited() -> Budget {
+        Budget::of(None, None)
+    }
+
+    pub fn of(max_instructions: Option<u64>, deadline: Option<Instant>) -> Budget {
+        Budget {
+            fuel: max_instructions.unwrap_or(u64::MAX),
+            deadline,
+        }
+    }
+
+    pub fn remaining_instructions(&self) -> u64 {
+        self.fuel
+    }
+
+    // Hands out the next batch of instructions the interpreter may run
+    // before coming back to check the deadline
+    pub(crate) fn next_slice(&mut self) -> Result<u64, ExecError> {
+        if let Some(deadline) = self.deadline {
+            if Instant::now() >= deadline {
+                return Err(ExecError::DeadlineExceeded);
+            }
+        }
+        if self.fuel == 0 {
+            return Err(ExecError::OutOfFuel);
+        }
+        let slice = self.fuel.min(SLICE);
+        self.fuel -= slice;
+        Ok(slice)
+    }
+
+    // Gives back what was left of a slice when the run finished early
+    pub(crate) fn refund(&mut self, unused: u64) {
+        self.fuel += unused;
+    }
+
+    // Carves out up to n instructions, under the same deadline, for one
+    // turn of a green thread. What the turn doesn't use is refunded.
+    pub(crate) fn take(&mut self, n: u64) -> Budget {
+        let fuel = self.fuel.min(n);
+        self.fuel -= fuel;
+        Budget {
+            fuel,
+            deadline: self.deadline,
+        }
+    }
+}
+
+// Why a run stopped before the program finished. The frame stack is left
+// as it was at the point of stopping.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExecError {
+    OutOfFuel,
+    DeadlineExceeded,
+    // Every green thread is waiting on another, with no timeouts to end it
+    Deadlock,
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExecError::OutOfFuel => write!(f, "instruction limit reached"),
+            ExecError::DeadlineExceeded => write!(f, "deadline exceeded"),
+            ExecError::Deadlock => write!(f, "deadlock between green threads"),
+        }
+    }
+}
@@ -2,291 +2,612 @@
 #![allow(dead_code)]
 #![allow(unused_variables)]
 
-use ocelotter_runtime::constant_pool::*;
+use std::sync::Arc;
+
+use ocelotter_runtime::frame::{Frame, FrameStack};
+use ocelotter_runtime::gc;
+use ocelotter_runtime::instruction::Instruction;
 use ocelotter_runtime::interp_stack::InterpEvalStack;
 use ocelotter_runtime::klass_repo::SharedKlassRepo;
+use ocelotter_runtime::monitor;
+use ocelotter_runtime::native_methods;
+use ocelotter_runtime::object::OtObj;
 use ocelotter_runtime::otklass::OtKlass;
-use ocelotter_runtime::otmethod::OtMethod;
+use ocelotter_runtime::otmethod::{self, OtMethod};
+use ocelotter_runtime::simple_heap::Finalizer;
+use ocelotter_runtime::thread;
+use ocelotter_runtime::vm::Vm;
 use ocelotter_runtime::*;
 
-mod opcode;
+pub mod budget;
+pub mod scheduler;
+
+use budget::{Budget, ExecError};
 
 pub fn exec_method(
-    repo: &mut SharedKlassRepo,
+    vm: &mut Vm,
     meth: &OtMethod,
     lvt: &mut InterpLocalVars,
 ) -> Option<JvmValue> {
-    if meth.is_native() {
-        // Explicit type hint here to document the type of n_f
-        let n_f: fn(&InterpLocalVars) -> Option<JvmValue> = meth.get_native_code().expect(
-            &format!("Native code not found {}", meth.get_fq_name_desc()),
-        );
-
-        // FIXME Parameter passing
-        n_f(lvt)
+    exec_method_on(vm, meth, lvt, &mut FrameStack::of(), &mut Budget::unlimited())
+        .expect("Ran out of an unlimited budget")
+}
+
+// As exec_method, but on a given thread stack, e.g. one sized by -Xss, and
+// stopping with an ExecError if the budget runs out
+pub fn exec_method_on(
+    vm: &mut Vm,
+    meth: &OtMethod,
+    lvt: &mut InterpLocalVars,
+    frames: &mut FrameStack,
+    budget: &mut Budget,
+) -> Result<Option<JvmValue>, ExecError> {
+    let _running = gc::running(vm);
+    let klass = vm.repo().lookup_klass(&meth.get_klass_name());
+    if meth.runs_natively() {
+        let ret = loop {
+            match exec_native_method(vm, &klass, meth, lvt) {
+                Ok(ret) => break ret,
+                Err(lock) => {
+                    let refs = lvt.values().iter().filter_map(|v| match v {
+                        JvmValue::ObjRef(id) if *id != 0 => Some(*id),
+                        _ => None,
+                    });
+                    block(vm, refs.collect(), Stop::Blocked(lock));
+                }
+            }
+        };
+        if let Some(klass_name) = native_methods::take_exception() {
+            uncaught_exception(klass_name, "");
+        }
+        if let Some(why) = thread::take_suspend() {
+            if let Some(klass_name) = block(vm, vec![], Stop::Suspended(why)) {
+                uncaught_exception(klass_name, "");
+            }
+        }
+        // Whatever Java code the native asked for runs before it returns
+        if let Some((klass, idx, mut vars)) = native_methods::take_invoke() {
+            vars.grow_to(klass.get_method(idx).get_local_var_size());
+            if !frames.push(Frame::of(klass, idx, vars)) {
+                uncaught_exception(STACK_OVERFLOW_ERROR, &frames.to_string());
+            }
+            exec_frames(vm, frames, budget)?;
+        }
+        Ok(ret)
     } else {
-        exec_bytecode_method(repo, meth.get_klass_name(), &meth.get_code(), lvt)
+        if !frames.push(entry_frame(klass, meth, lvt)) {
+            uncaught_exception(STACK_OVERFLOW_ERROR, &frames.to_string());
+        }
+        exec_frames(vm, frames, budget)
     }
 }
 
-pub fn exec_bytecode_method(
-    repo: &mut SharedKlassRepo,
-    klass_name: String,
-    instr: &Vec<u8>,
-    lvt: &mut InterpLocalVars,
-) -> Option<JvmValue> {
-    let mut current = 0;
-    let mut eval = InterpEvalStack::of();
+// The entry frame takes over the caller's local vars
+pub(crate) fn entry_frame(klass: Arc<OtKlass>, meth: &OtMethod, lvt: &mut InterpLocalVars) -> Frame {
+    let m_idx = klass
+        .get_method_index(&meth.get_fq_name_desc())
+        .unwrap_or_else(|| panic!("No method {} found on {}", meth, klass.get_name()));
+    lvt.grow_to(meth.get_local_var_size());
+    let args = std::mem::replace(lvt, InterpLocalVars::of(0));
+    Frame::of(klass, m_idx, args)
+}
 
-    loop {
-        // let my_klass_name = klass_name.clone();
-        let ins: u8 = *instr
-            .get(current)
-            .expect(&format!("Byte {} has no value", current));
+// Err(lock) if a green thread finds the method's monitor taken
+fn exec_native_method(
+    vm: &Vm,
+    klass: &OtKlass,
+    meth: &OtMethod,
+    lvt: &mut InterpLocalVars,
+) -> Result<Option<JvmValue>, usize> {
+    // Explicit type hint here to document the type of n_f
+    let n_f: otmethod::NativeMethod = match meth.get_native_code() {
+        Some(n_f) => n_f,
+        // Nothing registered for it, see SharedKlassRepo::register_native()
+        None => return Ok(native_methods::throw(native_methods::UNSATISFIED_LINK_ERROR)),
+    };
 
-        current += 1;
+    let lock = if meth.is_synchronized() {
+        let lock = monitor_object(vm, klass, meth, lvt);
+        if !monitor::enter(vm, lock) {
+            return Err(lock);
+        }
+        Some(lock)
+    } else {
+        None
+    };
 
-        // dbg!(ins);
-        match ins {
-            opcode::ACONST_NULL => eval.aconst_null(),
+    // Natives may hand back e.g. a Boolean; narrow to the declared
+    // return type, then widen for the caller's operand stack
+    let ret = n_f(vm, lvt).map(|v| v.narrow_to(meth.get_return_type()).to_computational());
 
-            opcode::ALOAD => {
-                eval.push(lvt.load(instr[current]));
-                current += 1;
-            }
-            opcode::ALOAD_0 => eval.push(lvt.load(0)),
+    if let Some(lock) = lock {
+        monitor::monitor_exit(vm, lock);
+    }
+    Ok(ret)
+}
 
-            opcode::ALOAD_1 => eval.push(lvt.load(1)),
+// The object a synchronized method locks: the receiver, or for static
+// methods the Class object
+fn monitor_object(vm: &Vm, klass: &OtKlass, meth: &OtMethod, lvt: &InterpLocalVars) -> usize {
+    if meth.is_static() {
+        vm.mirror(klass)
+    } else {
+        match lvt.load(0) {
+            JvmValue::ObjRef(id) => id,
+            other => panic!("Non-object receiver {} for synchronized method {}", other, meth),
+        }
+    }
+}
 
-            opcode::ALOAD_2 => eval.push(lvt.load(2)),
+// What an invoke or a return asks of the frame stack
+enum FrameExit {
+    Call(Frame),
+    Return(Option<JvmValue>),
+    // The current slice of the budget is used up, or a collection is due
+    Yield,
+    // The VM raises an exception of the named class at pc - 1
+    Throw(&'static str),
+    // A green thread needs the monitor of this object, and will try again
+    // from the same pc
+    Blocked(usize),
+    // A green thread called a native that gave up the OS thread
+    Suspend(thread::Suspend),
+    // An allocation didn't fit in the heap, and will be tried again from
+    // the same pc after a collection
+    OutOfMemory,
+}
 
-            opcode::ALOAD_3 => eval.push(lvt.load(3)),
+// Where a run of frames stopped. Only green threads stop other than by
+// returning.
+pub(crate) enum Stop {
+    Returned(Option<JvmValue>),
+    Blocked(usize),
+    Suspended(thread::Suspend),
+}
 
-            opcode::ARETURN => break Some(eval.pop()),
-            opcode::ASTORE => {
-                lvt.store(instr[current], eval.pop());
-                current += 1;
+// Runs frames until the bottom one returns. Java calls push a Frame rather
+// than recursing, so call depth doesn't use up the native stack.
+//
+// If the budget runs out, the frames are left as they are, so the caller
+// can see where the program had got to or carry on with a new budget.
+pub fn exec_frames(
+    vm: &mut Vm,
+    frames: &mut FrameStack,
+    budget: &mut Budget,
+) -> Result<Option<JvmValue>, ExecError> {
+    let collector = vm.clone();
+    loop {
+        match run_frames(vm, frames, budget, &mut |frames, wait| gc::collect(&collector, frames.refs(), wait))? {
+            Stop::Returned(ret) => return Ok(ret),
+            stop => {
+                if let Some(klass_name) = block(vm, frames.refs().collect(), stop) {
+                    throw_vm_exception(vm, frames, klass_name);
+                }
             }
-            opcode::ASTORE_0 => lvt.store(0, eval.pop()),
-
-            opcode::ASTORE_1 => lvt.store(1, eval.pop()),
-
-            opcode::ASTORE_2 => lvt.store(2, eval.pop()),
-
-            opcode::ASTORE_3 => lvt.store(3, eval.pop()),
+        }
+    }
+}
 
-            opcode::BIPUSH => {
-                eval.iconst(instr[current] as i32);
-                current += 1;
+// OS threads block where green threads would be switched away from, once
+// back in the interpreter, handing what they refer to to the collector
+// meanwhile. Returns an exception to raise in the thread, if any.
+fn block(vm: &Vm, mut refs: Vec<usize>, stop: Stop) -> Option<&'static str> {
+    match stop {
+        Stop::Returned(_) | Stop::Suspended(thread::Suspend::Yield) => None,
+        Stop::Blocked(lock) => {
+            refs.push(lock);
+            gc::blocking(vm, refs, || monitor::await_release(vm, lock));
+            None
+        }
+        Stop::Suspended(thread::Suspend::Wait { obj, count, timeout }) => {
+            refs.extend(Some(obj).filter(|&obj| obj != 0));
+            match gc::blocking(vm, refs, || thread::end_wait(vm, obj, count, timeout)) {
+                Err(monitor::WaitError::Interrupted) => Some(native_methods::INTERRUPTED_EXCEPTION),
+                _ => None,
             }
+        }
+    }
+}
 
-            opcode::D2F => {
-                match eval.pop() {
-                    JvmValue::Double(v) => eval.push(JvmValue::Float(v as f32)),
-                    _ => panic!("Value not of long type found for D2F at {}", (current - 1)),
-                };
+// Collects the heap, given the frames being run, which are not all the
+// roots when green threads share the OS thread. If told to wait, it stops
+// for another OS thread to collect when it can't itself, see gc::collect().
+pub(crate) type Collector<'a> = &'a mut dyn FnMut(&FrameStack, bool) -> Option<usize>;
+
+pub(crate) fn run_frames(
+    vm: &mut Vm,
+    frames: &mut FrameStack,
+    budget: &mut Budget,
+    collect: Collector,
+) -> Result<Stop, ExecError> {
+    let mut slice = 0;
+    loop {
+        if slice == 0 {
+            slice = budget.next_slice()?;
+        }
+        if gc::requested(vm) {
+            collect(frames, false);
+        }
+        // One finalizer at a time, each run on top of whatever the thread
+        // was doing, which carries on once the queue is empty
+        if gc::finalization_pending(vm) && !frames.finalizing() {
+            if let Some(fin) = gc::next_finalizer(vm) {
+                let mut vars = InterpLocalVars::of(fin.klass.get_method(fin.method_idx).get_local_var_size());
+                vars.store(0, JvmValue::ObjRef(fin.obj));
+                let mut frame = Frame::of(fin.klass, fin.method_idx, vars);
+                frame.finalizer = true;
+                // Without room for it, the finalizer is skipped, as it
+                // would only have thrown StackOverflowError
+                let _ = frames.push(frame);
             }
-
-            opcode::D2I => {
-                match eval.pop() {
-                    JvmValue::Double(v) => eval.push(JvmValue::Int(v as i32)),
-                    _ => panic!("Value not of long type found for D2I at {}", (current - 1)),
-                };
+        }
+        let frame = frames.top().expect("No frame to execute");
+        // Synchronized methods take their monitor before their first
+        // instruction, which for green threads may mean coming back later
+        let meth = frame.get_method();
+        if meth.is_synchronized() && frame.monitor.is_none() {
+            let lock = monitor_object(vm, frame.get_klass(), meth, &frame.lvt);
+            if !monitor::enter(vm, lock) {
+                budget.refund(slice);
+                break Ok(Stop::Blocked(lock));
             }
-
-            opcode::D2L => {
-                match eval.pop() {
-                    JvmValue::Double(v) => eval.push(JvmValue::Long(v as i64)),
-                    _ => panic!("Value not of long type found for D2L at {}", (current - 1)),
-                };
+            frame.monitor = Some(lock);
+        }
+        match exec_frame(vm, frame, &mut slice) {
+            FrameExit::Call(callee) => {
+                if !frames.push(callee) {
+                    throw_vm_exception(vm, frames, STACK_OVERFLOW_ERROR);
+                }
             }
-
-            opcode::DADD => eval.dadd(),
-
-            opcode::DCMPG => eval.dcmpg(),
-
-            opcode::DCMPL => eval.dcmpl(),
-
-            opcode::DCONST_0 => eval.dconst(0.0),
-
-            opcode::DCONST_1 => eval.dconst(1.0),
-
-            opcode::DDIV => eval.ddiv(),
-
-            opcode::DLOAD => {
-                eval.push(lvt.load(instr[current]));
-                current += 1;
+            FrameExit::Return(ret) => {
+                pop_frame(vm, frames);
+                match frames.top() {
+                    // Void methods leave nothing on the caller's stack
+                    Some(caller) => {
+                        if let Some(val) = ret {
+                            caller.eval.push(val);
+                        }
+                    }
+                    None => {
+                        budget.refund(slice);
+                        break Ok(Stop::Returned(ret));
+                    }
+                }
+            }
+            FrameExit::Yield => (),
+            FrameExit::Throw(klass_name) => throw_vm_exception(vm, frames, klass_name),
+            FrameExit::Blocked(lock) => {
+                budget.refund(slice);
+                break Ok(Stop::Blocked(lock));
             }
+            FrameExit::Suspend(why) => {
+                budget.refund(slice);
+                break Ok(Stop::Suspended(why));
+            }
+            // Only tried again if the collection might have made room
+            FrameExit::OutOfMemory => {
+                if collect(frames, true).unwrap_or(0) == 0 {
+                    frames.top().unwrap().pc += 1;
+                    throw_vm_exception(vm, frames, OUT_OF_MEMORY_ERROR);
+                }
+            }
+        }
+    }
+}
 
-            opcode::DLOAD_0 => eval.push(lvt.load(0)),
+// Gives up the monitor of a synchronized method, if it was taken. Structured locking
+// (JVMS 2.11.10) isn't enforced, so a method that has already let go of
+// its monitor just returns.
+fn pop_frame(vm: &Vm, frames: &mut FrameStack) {
+    if let Some(lock) = frames.pop().and_then(|f| f.monitor) {
+        monitor::monitor_exit(vm, lock);
+    }
+}
 
-            opcode::DLOAD_1 => eval.push(lvt.load(1)),
+pub(crate) const STACK_OVERFLOW_ERROR: &str = "java/lang/StackOverflowError";
+const ARITHMETIC_EXCEPTION: &str = "java/lang/ArithmeticException";
+const ARRAY_STORE_EXCEPTION: &str = "java/lang/ArrayStoreException";
+const ILLEGAL_MONITOR_STATE_EXCEPTION: &str = "java/lang/IllegalMonitorStateException";
+const NULL_POINTER_EXCEPTION: &str = "java/lang/NullPointerException";
+const OUT_OF_MEMORY_ERROR: &str = "java/lang/OutOfMemoryError";
+const VERIFY_ERROR: &str = "java/lang/VerifyError";
+
+// Raises an exception from within the VM itself, in the top frame at the
+// instruction before its pc
+pub(crate) fn throw_vm_exception(vm: &Vm, frames: &mut FrameStack, klass_name: &str) {
+    let repo = vm.repo();
+    let ex_klass = repo.lookup_klass(&klass_name.to_string());
+    repo.instance_fields(&ex_klass);
+    // FIXME Constructor is not run, so no message or stack trace
+    let ex_id = vm.heap().allocate_obj(&ex_klass);
+    let trace = frames.to_string();
+
+    if !unwind(vm, frames, &ex_klass, ex_id) {
+        uncaught_exception(klass_name, &trace);
+    }
+}
 
-            opcode::DLOAD_2 => eval.push(lvt.load(2)),
+// Pops frames until one has a handler covering the throwing instruction,
+// and sets that frame up to run the handler. Returns false if the
+// exception escapes the bottom frame.
+fn unwind(vm: &Vm, frames: &mut FrameStack, ex_klass: &OtKlass, ex_id: usize) -> bool {
+    let repo = vm.repo();
+    while let Some(frame) = frames.top() {
+        let thrown_at = frame.pc - 1;
+        let handler = frame
+            .get_method()
+            .get_exception_handlers()
+            .iter()
+            .find(|h| {
+                h.start <= thrown_at
+                    && thrown_at < h.end
+                    && (h.catch_type == 0
+                        || is_subklass_of(repo, ex_klass, &repo.resolve_klass(frame.get_klass(), h.catch_type).get_name()))
+            })
+            .map(|h| h.handler);
+
+        if let Some(pc) = handler {
+            frame.eval.clear();
+            frame.eval.push(JvmValue::ObjRef(ex_id));
+            frame.pc = pc;
+            return true;
+        }
+        // Exceptions thrown by finalize() are ignored
+        let finalizer = frame.finalizer;
+        pop_frame(vm, frames);
+        if finalizer {
+            return true;
+        }
+    }
+    false
+}
 
-            opcode::DLOAD_3 => eval.push(lvt.load(3)),
+fn is_subklass_of(repo: &SharedKlassRepo, klass: &OtKlass, super_name: &String) -> bool {
+    let mut name = klass.get_name();
+    loop {
+        if &name == super_name {
+            return true;
+        }
+        if name == "java/lang/Object" {
+            return false;
+        }
+        name = repo.lookup_klass(&name).get_super_name();
+    }
+}
 
-            opcode::DMUL => eval.dmul(),
+// FIXME Should be a clean exit rather than a panic
+pub(crate) fn uncaught_exception(klass_name: &str, trace: &str) -> ! {
+    panic!("Exception in thread \"main\" {}\n{}", klass_name, trace)
+}
 
-            opcode::DNEG => eval.dneg(),
+// Interprets the top frame from its saved pc until it calls a bytecode
+// method, returns or uses up its slice
+fn exec_frame(vm: &Vm, frame: &mut Frame, slice: &mut u64) -> FrameExit {
+    let repo = vm.repo();
+    let klass = frame.get_klass().clone();
+    let instrs = klass.get_method(frame.get_method_index()).get_instructions();
+    let mut current = frame.pc;
+    let lvt = &mut frame.lvt;
+    let eval = &mut frame.eval;
 
-            opcode::DREM => eval.drem(),
+    loop {
+        if *slice == 0 {
+            frame.pc = current;
+            return FrameExit::Yield;
+        }
+        *slice -= 1;
 
-            opcode::DRETURN => break Some(eval.pop()),
+        let ins = &instrs[current];
 
-            opcode::DSTORE => {
-                lvt.store(instr[current], eval.pop());
-                current += 1;
+        current += 1;
+
+        match ins {
+            Instruction::Aaload => {
+                let pos_to_load = match eval.pop() {
+                    JvmValue::Int(v) => v,
+                    _ => panic!("Non-int seen on stack during AALOAD at {}", current - 1),
+                };
+                let arrayid = match eval.pop() {
+                    JvmValue::ObjRef(v) => v,
+                    _ => panic!("Non-objref seen on stack during AALOAD at {}", current - 1),
+                };
+                if arrayid == 0 {
+                    frame.pc = current;
+                    return FrameExit::Throw(NULL_POINTER_EXCEPTION);
+                }
+                eval.push(JvmValue::ObjRef(vm.heap().get_obj(arrayid).obj_arr_load(pos_to_load)));
             }
 
-            opcode::DSTORE_0 => lvt.store(0, eval.pop()),
+            Instruction::Aastore => {
+                let val_to_store = match eval.pop() {
+                    JvmValue::ObjRef(v) => v,
+                    _ => panic!("Non-objref seen on stack during AASTORE at {}", current - 1),
+                };
+                let pos_to_store = match eval.pop() {
+                    JvmValue::Int(v) => v,
+                    _ => panic!("Non-int seen on stack during AASTORE at {}", current - 1),
+                };
+                let obj_id = match eval.pop() {
+                    JvmValue::ObjRef(v) => v,
+                    _ => panic!("Non-objref seen on stack during AASTORE at {}", current - 1),
+                };
+                if obj_id == 0 {
+                    frame.pc = current;
+                    return FrameExit::Throw(NULL_POINTER_EXCEPTION);
+                }
 
-            opcode::DSTORE_1 => lvt.store(1, eval.pop()),
+                let mut heap = vm.heap();
+                let element_klass = heap.get_obj(obj_id).get_klassid();
+                if val_to_store != 0 && !repo.can_store(element_klass, heap.get_obj(val_to_store)) {
+                    frame.pc = current;
+                    return FrameExit::Throw(ARRAY_STORE_EXCEPTION);
+                }
+                heap.obj_arr_store(obj_id, pos_to_store, val_to_store);
+            }
 
-            opcode::DSTORE_2 => lvt.store(2, eval.pop()),
+            Instruction::AconstNull => eval.aconst_null(),
 
-            opcode::DSTORE_3 => lvt.store(3, eval.pop()),
+            Instruction::Arraylength => {
+                let arrayid = match eval.pop() {
+                    JvmValue::ObjRef(v) => v,
+                    _ => panic!("Non-objref seen on stack during ARRAYLENGTH at {}", current - 1),
+                };
+                if arrayid == 0 {
+                    frame.pc = current;
+                    return FrameExit::Throw(NULL_POINTER_EXCEPTION);
+                }
+                eval.push(JvmValue::Int(vm.heap().get_obj(arrayid).length()));
+            }
 
-            opcode::DSUB => eval.dsub(),
+            Instruction::Baload | Instruction::Caload | Instruction::Saload => {
+                let pos_to_load = match eval.pop() {
+                    JvmValue::Int(v) => v,
+                    _ => panic!("Non-int seen on stack during {:?} at {}", ins, current - 1),
+                };
+                let arrayid = match eval.pop() {
+                    JvmValue::ObjRef(v) => v,
+                    _ => panic!("Non-objref seen on stack during {:?} at {}", ins, current - 1),
+                };
+                if arrayid == 0 {
+                    frame.pc = current;
+                    return FrameExit::Throw(NULL_POINTER_EXCEPTION);
+                }
+                eval.push(vm.heap().get_obj(arrayid).narrow_arr_load(pos_to_load));
+            }
 
-            opcode::DUP => eval.dup(),
+            Instruction::Bastore | Instruction::Castore | Instruction::Sastore => {
+                let val_to_store = eval.pop();
+                let pos_to_store = match eval.pop() {
+                    JvmValue::Int(v) => v,
+                    _ => panic!("Non-int seen on stack during {:?} at {}", ins, current - 1),
+                };
+                let obj_id = match eval.pop() {
+                    JvmValue::ObjRef(v) => v,
+                    _ => panic!("Non-objref seen on stack during {:?} at {}", ins, current - 1),
+                };
+                if obj_id == 0 {
+                    frame.pc = current;
+                    return FrameExit::Throw(NULL_POINTER_EXCEPTION);
+                }
 
-            opcode::DUP_X1 => eval.dup_x1(),
+                vm.heap().narrow_arr_store(obj_id, pos_to_store, val_to_store);
+            }
 
-            //            opcode::DUP2 => eval.dup2(),
-            opcode::F2D => eval.f2d(),
+            Instruction::D2f => eval.d2f(),
 
-            opcode::F2I => eval.f2i(),
+            Instruction::D2i => eval.d2i(),
 
-            opcode::F2L => eval.f2l(),
+            Instruction::D2l => eval.d2l(),
 
-            opcode::FADD => eval.fadd(),
+            Instruction::Dadd => eval.dadd(),
 
-            opcode::FCMPG => eval.fcmpg(),
+            Instruction::Dcmpg => eval.dcmpg(),
 
-            opcode::FCMPL => eval.fcmpl(),
+            Instruction::Dcmpl => eval.dcmpl(),
 
-            opcode::FCONST_0 => eval.fconst(0.0),
+            Instruction::Dconst(v) => eval.dconst(*v),
 
-            opcode::FCONST_1 => eval.fconst(1.0),
+            Instruction::Ddiv => eval.ddiv(),
 
-            opcode::FCONST_2 => eval.fconst(2.0),
+            Instruction::Dmul => eval.dmul(),
 
-            opcode::FDIV => eval.fdiv(),
+            Instruction::Dneg => eval.dneg(),
 
-            opcode::FLOAD => {
-                eval.push(lvt.load(instr[current]));
-                current += 1;
-            }
+            Instruction::Drem => eval.drem(),
 
-            opcode::FLOAD_0 => eval.push(lvt.load(0)),
+            Instruction::Dsub => eval.dsub(),
 
-            opcode::FLOAD_1 => eval.push(lvt.load(1)),
+            Instruction::Dup => eval.dup(),
 
-            opcode::FLOAD_2 => eval.push(lvt.load(2)),
+            Instruction::DupX1 => eval.dup_x1(),
 
-            opcode::FLOAD_3 => eval.push(lvt.load(3)),
+            Instruction::F2d => eval.f2d(),
 
-            opcode::FMUL => eval.fmul(),
+            Instruction::F2i => eval.f2i(),
 
-            opcode::FNEG => eval.fneg(),
+            Instruction::F2l => eval.f2l(),
 
-            opcode::FREM => eval.frem(),
+            Instruction::Fadd => eval.fadd(),
 
-            opcode::FRETURN => break Some(eval.pop()),
+            Instruction::Fcmpg => eval.fcmpg(),
 
-            opcode::FSTORE => {
-                lvt.store(instr[current], eval.pop());
-                current += 1;
-            }
+            Instruction::Fcmpl => eval.fcmpl(),
 
-            opcode::FSTORE_0 => lvt.store(0, eval.pop()),
+            Instruction::Fconst(v) => eval.fconst(*v),
 
-            opcode::FSTORE_1 => lvt.store(1, eval.pop()),
+            Instruction::Fdiv => eval.fdiv(),
 
-            opcode::FSTORE_2 => lvt.store(2, eval.pop()),
+            Instruction::Fmul => eval.fmul(),
 
-            opcode::FSTORE_3 => lvt.store(3, eval.pop()),
+            Instruction::Fneg => eval.fneg(),
 
-            opcode::FSUB => eval.fsub(),
+            Instruction::Frem => eval.frem(),
 
-            opcode::GETFIELD => {
-                let cp_lookup = ((instr[current] as u16) << 8) + instr[current + 1] as u16;
-                current += 2;
+            Instruction::Fsub => eval.fsub(),
 
+            Instruction::GetField(cp_lookup) => {
                 let recvp: JvmValue = eval.pop();
                 let obj_id = match recvp {
                     JvmValue::ObjRef(v) => v,
                     _ => panic!("Not an object ref at {}", (current - 1)),
                 };
-                let heap = HEAP.lock().unwrap();
-                let obj = heap.get_obj(obj_id).clone();
-                let getf = repo.lookup_instance_field(&klass_name, cp_lookup);
+                if obj_id == 0 {
+                    frame.pc = current;
+                    return FrameExit::Throw(NULL_POINTER_EXCEPTION);
+                }
+                let heap = vm.heap();
+                let obj = heap.get_obj(obj_id);
+                let getf = repo.resolve_instance_field(&klass, *cp_lookup);
 
                 let ret = obj.get_field_value(getf.get_offset() as usize);
-                eval.push(ret);
-            }
-            opcode::GETSTATIC => {
-                let cp_lookup = ((instr[current] as u16) << 8) + instr[current + 1] as u16;
-                current += 2;
-
-                let getf = repo.lookup_static_field(&klass_name, cp_lookup).clone();
-                let klass = repo.lookup_klass(&getf.get_klass_name()).clone();
-
-                let ret = klass.get_static(&getf);
-                eval.push(ret.clone());
-            }
-            opcode::GOTO => {
-                current += ((instr[current] as usize) << 8) + instr[current + 1] as usize
+                eval.push(ret.to_computational());
             }
-            opcode::GOTO_W => {
-                current += ((instr[current] as usize) << 24)
-                    + ((instr[current + 1] as usize) << 16)
-                    + ((instr[current + 2] as usize) << 8)
-                    + instr[current + 3] as usize
+            Instruction::GetStatic(cp_lookup) => {
+                let (target, slot, _) = repo.resolve_static_field(&klass, *cp_lookup);
+                eval.push(target.get_static_at(slot).to_computational());
             }
+            Instruction::Goto(target) => current = *target,
 
-            opcode::I2B => eval.i2b(),
+            Instruction::I2b => eval.i2b(),
 
-            opcode::I2C => eval.i2c(),
+            Instruction::I2c => eval.i2c(),
 
-            opcode::I2D => eval.i2d(),
+            Instruction::I2d => eval.i2d(),
 
-            opcode::I2F => eval.i2f(),
+            Instruction::I2f => eval.i2f(),
 
-            opcode::I2L => eval.i2l(),
+            Instruction::I2l => eval.i2l(),
 
-            opcode::I2S => eval.i2s(),
+            Instruction::I2s => eval.i2s(),
 
-            opcode::IADD => eval.iadd(),
+            Instruction::Iadd => eval.iadd(),
 
-            opcode::IALOAD => {
+            Instruction::Iaload => {
                 let pos_to_load = match eval.pop() {
                     JvmValue::Int(v) => v,
-                    _ => panic!("Non-int seen on stack during IASTORE at {}", current - 1),
+                    _ => panic!("Non-int seen on stack during IALOAD at {}", current - 1),
                 };
                 let arrayid = match eval.pop() {
                     JvmValue::ObjRef(v) => v,
-                    _ => panic!("Non-objref seen on stack during IASTORE at {}", current - 1),
+                    _ => panic!("Non-objref seen on stack during IALOAD at {}", current - 1),
                 };
-                dbg!(arrayid.clone());
-
-                let unwrapped_val = match HEAP.lock().unwrap().get_obj(arrayid) {
-                    ocelotter_runtime::object::OtObj::VmArrInt {
-                        id: _,
-                        mark: _,
-                        klassid: _,
-                        length: _,
-                        elements: elts,
-                    } => elts[pos_to_load as usize],
-                    _ => panic!("Non-int[] seen on stack during IASTORE at {}", current - 1),
+                if arrayid == 0 {
+                    frame.pc = current;
+                    return FrameExit::Throw(NULL_POINTER_EXCEPTION);
+                }
+
+                let unwrapped_val = match vm.heap().get_obj(arrayid) {
+                    ocelotter_runtime::object::OtObj::VmArrInt { elements: elts, .. } => {
+                        elts[pos_to_load as usize]
+                    }
+                    _ => panic!("Non-int[] seen on stack during IALOAD at {}", current - 1),
                 };
                 eval.push(JvmValue::Int(unwrapped_val));
             }
 
-            opcode::IAND => eval.iand(),
+            Instruction::Iand => eval.iand(),
 
-            opcode::IASTORE => {
+            Instruction::Iastore => {
                 let val_to_store = match eval.pop() {
                     JvmValue::Int(v) => v,
                     _ => panic!("Non-int seen on stack during IASTORE at {}", current - 1),
@@ -299,440 +620,331 @@ pub fn exec_bytecode_method(
                     JvmValue::ObjRef(v) => v,
                     _ => panic!("Non-objref seen on stack during IASTORE at {}", current - 1),
                 };
+                if obj_id == 0 {
+                    frame.pc = current;
+                    return FrameExit::Throw(NULL_POINTER_EXCEPTION);
+                }
 
-                HEAP.lock()
-                    .unwrap()
-                    .iastore(obj_id, pos_to_store, val_to_store);
+                vm.heap().iastore(obj_id, pos_to_store, val_to_store);
             }
 
-            opcode::ICONST_0 => eval.iconst(0),
+            Instruction::Iconst(v) => eval.iconst(*v),
 
-            opcode::ICONST_1 => eval.iconst(1),
-
-            opcode::ICONST_2 => eval.iconst(2),
-
-            opcode::ICONST_3 => eval.iconst(3),
-
-            opcode::ICONST_4 => eval.iconst(4),
-
-            opcode::ICONST_5 => eval.iconst(5),
-
-            opcode::ICONST_M1 => eval.iconst(-1),
-
-            opcode::IDIV => eval.idiv(),
-
-            opcode::IF_ICMPEQ => {
-                let jump_to = ((instr[current] as usize) << 8) + instr[current + 1] as usize;
-                if massage_to_int_and_compare(eval.pop(), eval.pop(), |i: i32, j: i32| -> bool {
-                    i == j
-                }) {
-                    current += jump_to - 1;
-                } else {
-                    current += 2;
+            Instruction::Idiv => {
+                if !eval.idiv() {
+                    frame.pc = current;
+                    return FrameExit::Throw(ARITHMETIC_EXCEPTION);
                 }
             }
 
-            opcode::IF_ICMPGE => {
-                let jump_to = ((instr[current] as usize) << 8) + instr[current + 1] as usize;
-                if massage_to_int_and_compare(eval.pop(), eval.pop(), |i: i32, j: i32| -> bool {
-                    i >= j
-                }) {
-                    current += jump_to - 1;
-                } else {
-                    current += 2;
+            Instruction::IfIcmpEq(target) => {
+                let v2 = eval.pop();
+                let v1 = eval.pop();
+                if massage_to_int_and_compare(v1, v2, |i: i32, j: i32| -> bool { i == j }) {
+                    current = *target;
                 }
             }
 
-            opcode::IF_ICMPGT => {
-                let jump_to = ((instr[current] as usize) << 8) + instr[current + 1] as usize;
-                if massage_to_int_and_compare(eval.pop(), eval.pop(), |i: i32, j: i32| -> bool {
-                    i > j
-                }) {
-                    current += jump_to - 1;
-                } else {
-                    current += 2;
+            Instruction::IfIcmpGe(target) => {
+                let v2 = eval.pop();
+                let v1 = eval.pop();
+                if massage_to_int_and_compare(v1, v2, |i: i32, j: i32| -> bool { i >= j }) {
+                    current = *target;
                 }
             }
 
-            opcode::IF_ICMPLE => {
-                let jump_to = ((instr[current] as usize) << 8) + instr[current + 1] as usize;
-                if massage_to_int_and_compare(eval.pop(), eval.pop(), |i: i32, j: i32| -> bool {
-                    i <= j
-                }) {
-                    current += jump_to - 1;
-                } else {
-                    current += 2;
+            Instruction::IfIcmpGt(target) => {
+                let v2 = eval.pop();
+                let v1 = eval.pop();
+                if massage_to_int_and_compare(v1, v2, |i: i32, j: i32| -> bool { i > j }) {
+                    current = *target;
                 }
             }
 
-            opcode::IF_ICMPLT => {
-                let jump_to = ((instr[current] as usize) << 8) + instr[current + 1] as usize;
-                if massage_to_int_and_compare(eval.pop(), eval.pop(), |i: i32, j: i32| -> bool {
-                    i < j
-                }) {
-                    current += jump_to - 1;
-                } else {
-                    current += 2;
+            Instruction::IfIcmpLe(target) => {
+                let v2 = eval.pop();
+                let v1 = eval.pop();
+                if massage_to_int_and_compare(v1, v2, |i: i32, j: i32| -> bool { i <= j }) {
+                    current = *target;
                 }
             }
 
-            opcode::IF_ICMPNE => {
-                let jump_to = ((instr[current] as usize) << 8) + instr[current + 1] as usize;
-                if massage_to_int_and_compare(eval.pop(), eval.pop(), |i: i32, j: i32| -> bool {
-                    i == j
-                }) {
-                    current += 2;
-                } else {
-                    current += jump_to - 1;
+            Instruction::IfIcmpLt(target) => {
+                let v2 = eval.pop();
+                let v1 = eval.pop();
+                if massage_to_int_and_compare(v1, v2, |i: i32, j: i32| -> bool { i < j }) {
+                    current = *target;
                 }
             }
-            opcode::IFEQ => {
-                let jump_to = ((instr[current] as usize) << 8) + instr[current + 1] as usize;
-                let i = match eval.pop() {
-                    JvmValue::Int(v) => v,
-                    _ => panic!("Non-int seen on stack during IFEQ at {}", current - 1),
-                };
-                if i == 0 {
-                    current += jump_to - 1;
-                } else {
-                    current += 2;
+
+            Instruction::IfIcmpNe(target) => {
+                let v2 = eval.pop();
+                let v1 = eval.pop();
+                if massage_to_int_and_compare(v1, v2, |i: i32, j: i32| -> bool { i != j }) {
+                    current = *target;
                 }
             }
-            opcode::IFGE => {
-                let jump_to = ((instr[current] as usize) << 8) + instr[current + 1] as usize;
-                let v = match eval.pop() {
-                    JvmValue::Int(i) => i,
-                    _ => panic!("Non-int seen on stack during IFGE at {}", current - 1),
+            Instruction::IfAcmpEq(target) | Instruction::IfAcmpNe(target) => {
+                let v2 = eval.pop();
+                let v1 = eval.pop();
+                let same = match (v1, v2) {
+                    (JvmValue::ObjRef(r1), JvmValue::ObjRef(r2)) => r1 == r2,
+                    _ => panic!("Value not of reference type found for {:?} at {}", ins, current - 1),
                 };
-                //                dbg!(v);
-                //                dbg!(current, jump_to);
-                if v >= 0 {
-                    current += jump_to - 1;
-                } else {
-                    current += 2;
+                if same == matches!(ins, Instruction::IfAcmpEq(_)) {
+                    current = *target;
                 }
             }
-            opcode::IFGT => {
-                let jump_to = ((instr[current] as usize) << 8) + instr[current + 1] as usize;
+            Instruction::IfEq(target)
+            | Instruction::IfNe(target)
+            | Instruction::IfLt(target)
+            | Instruction::IfGe(target)
+            | Instruction::IfGt(target)
+            | Instruction::IfLe(target) => {
                 let v = match eval.pop() {
                     JvmValue::Int(v) => v,
-                    _ => panic!("Non-int seen on stack during IFGT at {}", current - 1),
+                    _ => panic!("Non-int seen on stack during {:?} at {}", ins, current - 1),
                 };
-                if v > 0 {
-                    current += jump_to - 1;
-                } else {
-                    current += 2;
-                }
-            }
-            opcode::IFLE => {
-                let jump_to = ((instr[current] as usize) << 8) + instr[current + 1] as usize;
-                let v = match eval.pop() {
-                    JvmValue::Int(i) => i,
-                    _ => panic!("Non-int seen on stack during IFLE at {}", current - 1),
+                let taken = match ins {
+                    Instruction::IfEq(_) => v == 0,
+                    Instruction::IfNe(_) => v != 0,
+                    Instruction::IfLt(_) => v < 0,
+                    Instruction::IfGe(_) => v >= 0,
+                    Instruction::IfGt(_) => v > 0,
+                    _ => v <= 0,
                 };
-                //                dbg!(v);
-                //                dbg!(current, jump_to);
-                //                dbg!(instr[current], instr[current + 1]);
-                if v <= 0 {
-                    current += jump_to - 1;
-                } else {
-                    current += 2;
+                if taken {
+                    current = *target;
                 }
             }
-            opcode::IFLT => {
-                let jump_to = ((instr[current] as usize) << 8) + instr[current + 1] as usize;
-                let v = match eval.pop() {
-                    JvmValue::Int(v) => v,
-                    _ => panic!("Non-int seen on stack during IFGT at {}", current - 1),
-                };
-                if v < 0 {
-                    current += jump_to - 1;
-                } else {
-                    current += 2;
-                }
-            }
-            opcode::IFNE => {
-                let jump_to = ((instr[current] as usize) << 8) + instr[current + 1] as usize;
-                let i = match eval.pop() {
-                    JvmValue::Int(v) => v,
-                    _ => panic!("Non-int seen on stack during IFEQ at {}", current - 1),
-                };
-                if i == 0 {
-                    current += jump_to - 1;
-                } else {
-                    current += 2;
+            Instruction::IfNonNull(target) => match eval.pop() {
+                JvmValue::ObjRef(v) => {
+                    if v > 0 {
+                        current = *target;
+                    }
                 }
-            }
-            opcode::IFNONNULL => {
-                let jump_to = ((instr[current] as usize) << 8) + instr[current + 1] as usize;
-
-                match eval.pop() {
-                    JvmValue::ObjRef(v) => {
-                        if v > 0 {
-                            current += jump_to - 1;
-                        } else {
-                            current += 2;
-                        }
+                _ => panic!(
+                    "Value not of reference type found for IFNONNULL at {}",
+                    (current - 1)
+                ),
+            },
+            Instruction::IfNull(target) => match eval.pop() {
+                JvmValue::ObjRef(v) => {
+                    if v == 0 {
+                        current = *target;
                     }
-                    _ => panic!(
-                        "Value not of reference type found for IFNULL at {}",
-                        (current - 1)
-                    ),
-                };
-            }
-            opcode::IFNULL => {
-                let jump_to = ((instr[current] as usize) << 8) + instr[current + 1] as usize;
-
-                match eval.pop() {
-                    JvmValue::ObjRef(v) => {
-                        if v == 0 {
-                            current += jump_to - 1;
-                        } else {
-                            current += 2;
+                }
+                _ => panic!(
+                    "Value not of reference type found for IFNULL at {}",
+                    (current - 1)
+                ),
+            },
+            Instruction::Iinc(idx, incr) => lvt.iinc(*idx, *incr),
+
+            Instruction::Imul => eval.imul(),
+
+            Instruction::Ineg => eval.ineg(),
+
+            Instruction::InvokeSpecial(cp_lookup)
+            | Instruction::InvokeStatic(cp_lookup)
+            | Instruction::InvokeVirtual(cp_lookup)
+            | Instruction::InvokeInterface(cp_lookup) => {
+                let (resolved_klass, m_idx, _) = repo.resolve_method(&klass, *cp_lookup);
+                let vars = pop_args(resolved_klass.get_method(m_idx), eval);
+                let (callee_klass, callee_idx) = match ins {
+                    Instruction::InvokeVirtual(_) | Instruction::InvokeInterface(_) => match vars.load(0) {
+                        JvmValue::ObjRef(0) => {
+                            frame.pc = current;
+                            return FrameExit::Throw(NULL_POINTER_EXCEPTION);
                         }
-                    }
-                    _ => panic!(
-                        "Value not of reference type found for IFNULL at {}",
-                        (current - 1)
-                    ),
+                        JvmValue::ObjRef(id) => select_override(vm, &resolved_klass, m_idx, id),
+                        other => panic!("Non-object receiver {} seen during {:?} at {}", other, ins, current - 1),
+                    },
+                    _ => (resolved_klass, m_idx),
                 };
+                if let Some(exit) = dispatch_invoke(vm, callee_klass, callee_idx, vars, eval) {
+                    // A blocked call is made again once the monitor is free
+                    frame.pc = match exit {
+                        FrameExit::Blocked(_) => current - 1,
+                        _ => current,
+                    };
+                    return exit;
+                }
             }
-            opcode::IINC => {
-                lvt.iinc(instr[current], instr[current + 1]);
-                current += 2;
-            }
-
-            opcode::ILOAD => {
-                eval.push(lvt.load(instr[current]));
-                current += 1
-            }
-
-            opcode::ILOAD_0 => eval.push(lvt.load(0)),
-
-            opcode::ILOAD_1 => eval.push(lvt.load(1)),
-
-            opcode::ILOAD_2 => eval.push(lvt.load(2)),
-
-            opcode::ILOAD_3 => eval.push(lvt.load(3)),
-
-            opcode::IMUL => eval.imul(),
-
-            opcode::INEG => eval.ineg(),
-
-            opcode::INVOKESPECIAL => {
-                let cp_lookup = ((instr[current] as u16) << 8) + instr[current + 1] as u16;
-                current += 2;
-                let current_klass = repo.lookup_klass(&klass_name).clone();
-                dispatch_invoke(repo, current_klass, cp_lookup, &mut eval, 1);
-            }
-            opcode::INVOKESTATIC => {
-                let cp_lookup = ((instr[current] as u16) << 8) + instr[current + 1] as u16;
-                current += 2;
-                let current_klass = repo.lookup_klass(&klass_name).clone();
-                //                dbg!(cp_lookup);
-                let arg_count = current_klass.get_method_arg_count(cp_lookup);
-                dispatch_invoke(repo, current_klass, cp_lookup, &mut eval, arg_count);
-            }
-            opcode::INVOKEVIRTUAL => {
-                // FIXME DOES NOT ACTUALLY DO VIRTUAL LOOKUP YET
-                let cp_lookup = ((instr[current] as u16) << 8) + instr[current + 1] as u16;
-                current += 2;
-                let current_klass = repo.lookup_klass(&klass_name).clone();
-                dbg!(current_klass.clone());
-                dispatch_invoke(repo, current_klass, cp_lookup, &mut eval, 1);
-            }
-            opcode::IOR => eval.ior(),
-
-            opcode::IREM => eval.irem(),
-
-            opcode::IRETURN => break Some(eval.pop()),
-
-            opcode::ISHL => eval.ishl(),
-
-            opcode::ISHR => eval.ishr(),
+            Instruction::Ior => eval.ior(),
 
-            opcode::ISTORE => {
-                lvt.store(instr[current], eval.pop());
-                current += 1;
+            Instruction::Irem => {
+                if !eval.irem() {
+                    frame.pc = current;
+                    return FrameExit::Throw(ARITHMETIC_EXCEPTION);
+                }
             }
-            opcode::ISTORE_0 => lvt.store(0, eval.pop()),
-
-            opcode::ISTORE_1 => lvt.store(1, eval.pop()),
 
-            opcode::ISTORE_2 => lvt.store(2, eval.pop()),
+            Instruction::Ishl => eval.ishl(),
 
-            opcode::ISTORE_3 => lvt.store(3, eval.pop()),
+            Instruction::Ishr => eval.ishr(),
 
-            opcode::ISUB => eval.isub(),
+            Instruction::Isub => eval.isub(),
 
-            opcode::IUSHR => eval.iushr(),
+            Instruction::Iushr => eval.iushr(),
 
-            opcode::IXOR => eval.ixor(),
+            Instruction::Ixor => eval.ixor(),
 
-            opcode::L2D => eval.l2d(),
+            Instruction::L2d => eval.l2d(),
 
-            opcode::L2F => eval.l2f(),
+            Instruction::L2f => eval.l2f(),
 
-            opcode::L2I => eval.l2i(),
+            Instruction::L2i => eval.l2i(),
 
-            opcode::LADD => eval.ladd(),
+            Instruction::Ladd => eval.ladd(),
 
-            opcode::LAND => eval.land(),
+            Instruction::Land => eval.land(),
 
-            opcode::LCMP => eval.lcmp(),
+            Instruction::Lcmp => eval.lcmp(),
 
-            opcode::LCONST_0 => eval.lconst(0),
+            Instruction::Lconst(v) => eval.lconst(*v),
 
-            opcode::LCONST_1 => eval.lconst(1),
+            Instruction::LdcClass(cp_lookup) => eval.push(JvmValue::ObjRef(vm.resolve_mirror(&klass, *cp_lookup))),
 
-            opcode::LDC => {
-                let cp_lookup = instr[current] as u16;
-                current += 1;
-                let current_klass = repo.lookup_klass(&klass_name).clone();
+            Instruction::LdcString(cp_lookup) => eval.push(JvmValue::ObjRef(vm.resolve_string(&klass, *cp_lookup))),
 
-                match current_klass.lookup_cp(cp_lookup) {
-                    // FIXME Actually look up the class object properly
-                    CpEntry::Class(_) => eval.aconst_null(),
-                    CpEntry::Double(dcon) => eval.dconst(dcon),
-                    CpEntry::Integer(icon) => eval.iconst(icon),
-                    // FIXME Actually look up the class object properly
-                    CpEntry::String(idx) => eval.aconst_null(),
-                    _ => panic!(
-                        "Non-handled entry found in LDC op {} at CP index {}",
-                        current_klass.get_name(),
-                        cp_lookup
-                    ),
+            Instruction::Ldiv => {
+                if !eval.ldiv() {
+                    frame.pc = current;
+                    return FrameExit::Throw(ARITHMETIC_EXCEPTION);
                 }
             }
-            opcode::LDC2_W => {
-                let cp_lookup = ((instr[current] as u16) << 8) + instr[current + 1] as u16;
-                current += 2;
-                let current_klass = repo.lookup_klass(&klass_name).clone();
-
-                let entry: CpEntry = current_klass.lookup_cp(cp_lookup);
-                //                dbg!("Index: {} of type {}", cp_lookup, entry.name());
-                //                dbg!(current_klass.clone());
-                match entry {
-                    // FIXME Actually look up the class object properly
-                    CpEntry::Class(_) => eval.aconst_null(),
-                    CpEntry::Double(dcon) => eval.dconst(dcon),
-                    CpEntry::Integer(icon) => eval.iconst(icon),
-                    // FIXME Actually look up the string object properly
-                    CpEntry::String(_) => eval.aconst_null(),
-                    _ => panic!(
-                        "Non-handled entry found in LDC op {} at CP index {}",
-                        current_klass.get_name(),
-                        cp_lookup
-                    ),
-                }
-            }
-
-            opcode::LDIV => eval.ldiv(),
-
-            opcode::LLOAD => {
-                eval.push(lvt.load(instr[current]));
-                current += 1
-            }
-
-            opcode::LLOAD_0 => eval.push(lvt.load(0)),
-
-            opcode::LLOAD_1 => eval.push(lvt.load(1)),
-
-            opcode::LLOAD_2 => eval.push(lvt.load(2)),
-
-            opcode::LLOAD_3 => eval.push(lvt.load(3)),
 
-            opcode::LMUL => eval.lmul(),
+            Instruction::Lmul => eval.lmul(),
 
-            opcode::LNEG => eval.lneg(),
+            Instruction::Lneg => eval.lneg(),
 
-            opcode::LOR => eval.lor(),
+            Instruction::Load(idx) => eval.push(lvt.load(*idx)),
 
-            opcode::LREM => eval.lrem(),
-
-            opcode::LRETURN => break Some(eval.pop()),
-
-            opcode::LSHL => eval.lshl(),
-
-            opcode::LSHR => eval.lshr(),
-
-            opcode::LSTORE => {
-                lvt.store(instr[current], eval.pop());
-                current += 1;
+            Instruction::LookupSwitch(ls) => {
+                let key = eval.pop().as_int().expect("Non-int seen on stack during LOOKUPSWITCH");
+                current = ls
+                    .pairs
+                    .iter()
+                    .find(|(k, _)| *k == key)
+                    .map_or(ls.default, |(_, t)| *t);
             }
 
-            opcode::LSTORE_0 => lvt.store(0, eval.pop()),
+            Instruction::Lor => eval.lor(),
 
-            opcode::LSTORE_1 => lvt.store(1, eval.pop()),
+            Instruction::Lrem => {
+                if !eval.lrem() {
+                    frame.pc = current;
+                    return FrameExit::Throw(ARITHMETIC_EXCEPTION);
+                }
+            }
 
-            opcode::LSTORE_2 => lvt.store(2, eval.pop()),
+            Instruction::Lshl => eval.lshl(),
 
-            opcode::LSTORE_3 => lvt.store(3, eval.pop()),
+            Instruction::Lshr => eval.lshr(),
 
-            opcode::LSUB => eval.lsub(),
+            Instruction::Lsub => eval.lsub(),
 
-            opcode::LUSHR => eval.lushr(),
+            Instruction::Lushr => eval.lushr(),
 
-            opcode::LXOR => eval.lxor(),
+            Instruction::Lxor => eval.lxor(),
 
-            // FIXME TEMP
-            opcode::MONITORENTER => {
-                eval.pop();
-            }
-            // FIXME TEMP
-            opcode::MONITOREXIT => {
-                eval.pop();
+            Instruction::MonitorEnter | Instruction::MonitorExit => {
+                let obj_id = match eval.pop() {
+                    JvmValue::ObjRef(v) => v,
+                    _ => panic!("Non-objref seen on stack during {:?} at {}", ins, current - 1),
+                };
+                if obj_id == 0 {
+                    frame.pc = current;
+                    return FrameExit::Throw(NULL_POINTER_EXCEPTION);
+                }
+                if let Instruction::MonitorEnter = ins {
+                    if !monitor::enter(vm, obj_id) {
+                        eval.push(JvmValue::ObjRef(obj_id));
+                        frame.pc = current - 1;
+                        return FrameExit::Blocked(obj_id);
+                    }
+                } else if !monitor::monitor_exit(vm, obj_id) {
+                    frame.pc = current;
+                    return FrameExit::Throw(ILLEGAL_MONITOR_STATE_EXCEPTION);
+                }
             }
-            opcode::NEW => {
-                let cp_lookup = ((instr[current] as u16) << 8) + instr[current + 1] as u16;
-                current += 2;
-                let current_klass = repo.lookup_klass(&klass_name).clone();
-
-                let alloc_klass_name = match current_klass.lookup_cp(cp_lookup) {
-                    // FIXME Find class name from constant pool of the current class
-                    CpEntry::Class(c) => current_klass.cp_as_string(c.0), // "DUMMY_CLASS".to_string(),
-                    _ => panic!(
-                        "Non-class found in {} at CP index {}",
-                        current_klass.get_name(),
-                        cp_lookup
-                    ),
+            Instruction::New(cp_lookup) => {
+                let object_klass = repo.resolve_klass(&klass, *cp_lookup);
+                let finalizer = repo.finalizer(&object_klass);
+
+                let obj_id = match vm.heap().try_allocate_obj(&object_klass) {
+                    Some(id) => id,
+                    None => {
+                        frame.pc = current - 1;
+                        return FrameExit::OutOfMemory;
+                    }
                 };
-                //                dbg!(alloc_klass_name.clone());
-                let object_klass = repo.lookup_klass(&alloc_klass_name).clone();
-
-                let obj_id = HEAP.lock().unwrap().allocate_obj(&object_klass);
+                if let Some((klass, method_idx)) = finalizer {
+                    vm.heap().register_finalizer(Finalizer {
+                        obj: obj_id,
+                        klass,
+                        method_idx,
+                    });
+                }
                 eval.push(JvmValue::ObjRef(obj_id));
+                if gc::requested(vm) {
+                    frame.pc = current;
+                    return FrameExit::Yield;
+                }
             }
-            opcode::NEWARRAY => {
-                let arr_type = instr[current];
-                current += 1;
-
+            Instruction::NewArray(arr_type) => {
+                let arr_size = match eval.pop() {
+                    JvmValue::Int(v) => v,
+                    _ => panic!("Not an int on the stack at {}", (current - 1)),
+                };
                 // FIXME Other primitive array types needed
                 let arr_id = match arr_type {
-                    // boolean: 4
-                    // char: 5
-                    // float: 6
-                    // double: 7
-                    // byte: 8
-                    // short: 9
-                    // int: 10
-                    // long: 11
-                    10 => match eval.pop() {
-                        JvmValue::Int(arr_size) => {
-                            HEAP.lock().unwrap().allocate_int_arr(arr_size)
-                        }
-                        _ => panic!("Not an int on the stack at {}", (current - 1)),
-                    },
+                    'Z' | 'C' | 'B' | 'S' => vm.heap().try_allocate_narrow_arr(*arr_type, arr_size),
+                    'I' => vm.heap().try_allocate_int_arr(arr_size),
                     _ => panic!("Unsupported primitive array type at {}", (current - 1)),
                 };
+                let arr_id = match arr_id {
+                    Some(id) => id,
+                    None => {
+                        eval.push(JvmValue::Int(arr_size));
+                        frame.pc = current - 1;
+                        return FrameExit::OutOfMemory;
+                    }
+                };
 
                 eval.push(JvmValue::ObjRef(arr_id));
+                if gc::requested(vm) {
+                    frame.pc = current;
+                    return FrameExit::Yield;
+                }
+            }
+            Instruction::ANewArray(cp_lookup) => {
+                let arr_size = match eval.pop() {
+                    JvmValue::Int(v) => v,
+                    _ => panic!("Not an int on the stack at {}", (current - 1)),
+                };
+                let element_klass = repo.resolve_klass(&klass, *cp_lookup);
+                let arr_id = match vm.heap().try_allocate_obj_arr(&element_klass, arr_size) {
+                    Some(id) => id,
+                    None => {
+                        eval.push(JvmValue::Int(arr_size));
+                        frame.pc = current - 1;
+                        return FrameExit::OutOfMemory;
+                    }
+                };
+
+                eval.push(JvmValue::ObjRef(arr_id));
+                if gc::requested(vm) {
+                    frame.pc = current;
+                    return FrameExit::Yield;
+                }
             }
-            opcode::NOP => (),
-            opcode::POP => {
+            Instruction::Nop => (),
+            Instruction::Pop => {
                 eval.pop();
             }
-            opcode::POP2 => {
+            Instruction::Pop2 => {
                 let _discard: JvmValue = eval.pop();
                 // FIXME Change to type match
                 // if (discard.type == JVMType.J || discard.type == JVMType.D) {
@@ -740,10 +952,7 @@ pub fn exec_bytecode_method(
                 // }
                 eval.pop();
             }
-            opcode::PUTFIELD => {
-                let cp_lookup = ((instr[current] as u16) << 8) + instr[current + 1] as u16;
-                current += 2;
-
+            Instruction::PutField(cp_lookup) => {
                 let val = eval.pop();
 
                 let recvp: JvmValue = eval.pop();
@@ -751,43 +960,48 @@ pub fn exec_bytecode_method(
                     JvmValue::ObjRef(v) => v,
                     _ => panic!("Not an object ref at {}", (current - 1)),
                 };
+                if obj_id == 0 {
+                    frame.pc = current;
+                    return FrameExit::Throw(NULL_POINTER_EXCEPTION);
+                }
 
-                let putf = repo.lookup_instance_field(&klass_name, cp_lookup);
+                let putf = repo.resolve_instance_field(&klass, *cp_lookup);
 
-                HEAP.lock().unwrap().put_field(obj_id, putf, val);
+                vm.heap().put_field(obj_id, putf, val);
+            }
+            Instruction::PutStatic(cp_lookup) => {
+                let (target, slot, puts) = repo.resolve_static_field(&klass, *cp_lookup);
+                target.put_static_at(slot, puts, eval.pop());
             }
-            opcode::PUTSTATIC => {
-                let cp_lookup = ((instr[current] as u16) << 8) + instr[current + 1] as u16;
-                current += 2;
+            Instruction::Return => return FrameExit::Return(None),
 
-                let puts = repo.lookup_static_field(&klass_name, cp_lookup);
-                let klass = repo.lookup_klass(&puts.get_klass_name()).clone();
+            Instruction::ReturnValue => return FrameExit::Return(Some(eval.pop())),
 
-                klass.put_static(&puts, eval.pop());
-            }
-            opcode::RETURN => break None,
-            opcode::SIPUSH => {
-                let vtmp = ((instr[current] as i32) << 8) + instr[current + 1] as i32;
-                eval.iconst(vtmp);
-                current += 2;
-            }
-            opcode::SWAP => {
+            Instruction::Store(idx) => lvt.store(*idx, eval.pop()),
+
+            Instruction::Swap => {
                 let val1 = eval.pop();
                 let val2 = eval.pop();
                 eval.push(val1);
                 eval.push(val2);
             }
-            // Disallowed opcodes
-            opcode::BREAKPOINT => break Some(JvmValue::Boolean(false)),
-            opcode::IMPDEP1 => break Some(JvmValue::Boolean(false)),
-            opcode::IMPDEP2 => break Some(JvmValue::Boolean(false)),
-            opcode::JSR => break Some(JvmValue::Boolean(false)),
-            opcode::JSR_W => break Some(JvmValue::Boolean(false)),
-            opcode::RET => break Some(JvmValue::Boolean(false)),
-
-            _ => panic!(
-                "Illegal opcode byte: {} encountered at position {}. Stopping.",
-                ins,
+
+            Instruction::TableSwitch(ts) => {
+                let idx = eval.pop().as_int().expect("Non-int seen on stack during TABLESWITCH");
+                current = match idx.checked_sub(ts.low) {
+                    Some(i) if i >= 0 && (i as usize) < ts.targets.len() => ts.targets[i as usize],
+                    _ => ts.default,
+                };
+            }
+            // Disallowed opcodes, which a verifier would have turned away
+            Instruction::Disallowed(_) => {
+                frame.pc = current;
+                return FrameExit::Throw(VERIFY_ERROR);
+            }
+
+            Instruction::Unsupported(op) => panic!(
+                "Unsupported opcode byte: {} encountered at instruction {}. Stopping.",
+                op,
                 (current - 1)
             ),
         }
@@ -804,35 +1018,96 @@ fn massage_to_int_and_compare(v1: JvmValue, v2: JvmValue, f: fn(i: i32, j: i32)
     }
 }
 
-fn dispatch_invoke(
-    repo: &mut SharedKlassRepo,
-    current_klass: OtKlass,
-    cp_lookup: u16,
-    eval: &mut InterpEvalStack,
-    additional_args: u8,
-) -> () {
-    let fq_name_desc = current_klass.cp_as_string(cp_lookup);
-    let klz_idx = match current_klass.lookup_cp(cp_lookup) {
-        CpEntry::MethodRef(mr) => mr.clz_idx,
-        _ => panic!(
-            "Non-methodref found in {} at CP index {}",
-            current_klass.get_name(),
-            cp_lookup
-        ),
-    };
-    let dispatch_klass_name = current_klass.cp_as_string(klz_idx);
+// Moves the arguments, and the receiver if there is one, from the caller's
+// stack into locals for the callee
+fn pop_args(callee: &OtMethod, eval: &mut InterpEvalStack) -> InterpLocalVars {
+    let mut slot = callee.get_arg_slots();
+    let mut vars = InterpLocalVars::of(slot);
+    for arg_type in callee.get_arg_types().iter().rev() {
+        // Longs and doubles are one value on the stack but take two locals
+        slot -= otmethod::arg_width(*arg_type);
+        vars.store(slot as u8, eval.pop());
+    }
+    if !callee.is_static() {
+        vars.store(0, eval.pop());
+    }
+    vars
+}
 
-    let callee = repo.lookup_method_exact(&dispatch_klass_name, fq_name_desc);
+// Undoes pop_args, for a call that has to be made again
+fn push_args(callee: &OtMethod, vars: &InterpLocalVars, eval: &mut InterpEvalStack) {
+    let mut slot = 0;
+    if !callee.is_static() {
+        eval.push(vars.load(0));
+        slot = 1;
+    }
+    for arg_type in callee.get_arg_types() {
+        eval.push(vars.load(slot as u8));
+        slot += otmethod::arg_width(*arg_type);
+    }
+}
 
-    // FIXME - General setup requires call args from the stack
-    let mut vars = InterpLocalVars::of(255);
-    if additional_args > 0 {
-        vars.store(0, eval.pop());
+// The method a virtual call ends up in: the nearest override of the
+// resolved method, starting from the receiver's klass
+fn select_override(
+    vm: &Vm,
+    resolved_klass: &Arc<OtKlass>,
+    m_idx: usize,
+    receiver: usize,
+) -> (Arc<OtKlass>, usize) {
+    let klass_id = match vm.heap().get_obj(receiver) {
+        obj @ OtObj::VmObj { .. } => obj.get_klassid(),
+        // FIXME Arrays don't have klasses yet
+        _ => 0,
+    };
+    if klass_id == 0 || klass_id == resolved_klass.get_id() {
+        return (resolved_klass.clone(), m_idx);
     }
-    // Explicit use of match expression to be clear about the semantics
-    match exec_method(repo, &callee, &mut vars) {
-        Some(val) => eval.push(val),
-        None => (),
+    let name_desc = resolved_klass.get_method(m_idx).get_desc();
+    vm.repo()
+        .find_method(&vm.repo().klass_by_id(klass_id), &name_desc)
+        .unwrap_or_else(|| (resolved_klass.clone(), m_idx))
+}
+
+// Natives run straight away, and None means carry on with the caller
+// unless they threw. Bytecode methods get a new Frame for the caller to push.
+fn dispatch_invoke(
+    vm: &Vm,
+    callee_klass: Arc<OtKlass>,
+    m_idx: usize,
+    mut vars: InterpLocalVars,
+    eval: &mut InterpEvalStack,
+) -> Option<FrameExit> {
+    let callee = callee_klass.get_method(m_idx);
+    if callee.runs_natively() {
+        let ret = match exec_native_method(vm, &callee_klass, callee, &mut vars) {
+            Ok(ret) => ret,
+            Err(lock) => {
+                push_args(callee, &vars, eval);
+                return Some(FrameExit::Blocked(lock));
+            }
+        };
+        if let Some(klass_name) = native_methods::take_exception() {
+            return Some(FrameExit::Throw(klass_name));
+        }
+        if let Some(why) = thread::take_suspend() {
+            return Some(FrameExit::Suspend(why));
+        }
+        // Void methods leave nothing on the caller's stack
+        if let Some(val) = ret {
+            eval.push(val);
+        }
+        if let Some((klass, idx, mut vars)) = native_methods::take_invoke() {
+            vars.grow_to(klass.get_method(idx).get_local_var_size());
+            return Some(FrameExit::Call(Frame::of(klass, idx, vars)));
+        }
+        if gc::requested(vm) {
+            return Some(FrameExit::Yield);
+        }
+        None
+    } else {
+        vars.grow_to(callee.get_local_var_size());
+        Some(FrameExit::Call(Frame::of(callee_klass, m_idx, vars)))
     }
 }
 
@@ -1,13 +1,17 @@
+use std::fs::File;
+use std::io::BufWriter;
 use std::path::Path;
+use std::process;
 
+use ocelotter_runtime::frame::FrameStack;
 use ocelotter_runtime::klass_parser::*;
-use ocelotter_runtime::klass_repo::SharedKlassRepo;
 use ocelotter_runtime::InterpLocalVars;
 use ocelotter_runtime::JvmValue::*;
+use ocelotter_runtime::vm::Vm;
 use ocelotter_util::file_to_bytes;
-use structopt::StructOpt;
 
-use ocelotter::exec_method;
+use ocelotter::scheduler::GreenScheduler;
+use ocelotter::{exec_method, exec_method_on};
 use ocelotter_util::ZipFiles;
 use options::Options;
 
@@ -15,57 +19,91 @@ mod options;
 
 pub fn main() {
     // Parse any command-line arguments
-    let options = Options::from_args();
-
-    let mut repo = SharedKlassRepo::of();
-    repo.bootstrap(exec_method);
+    let options = Options::from_jvm_args();
+    if options.initial_heap() > options.max_heap() {
+        eprintln!("Initial heap size set to a larger value than the maximum heap size");
+        process::exit(1);
+    }
+    let mut vm = Vm::of();
+    {
+        let mut heap = vm.heap();
+        heap.set_limits(options.initial_heap(), options.max_heap());
+        heap.set_collector(options.gc);
+    }
+    vm.bootstrap(exec_method);
 
     let fq_klass_name = options.fq_klass_name();
     let f_name = options.f_name();
 
     if let Some(file) = &options.classpath {
+        vm.repo().add_classpath(Path::new(file));
         ZipFiles::new(file)
-            .into_iter()
-            .filter(|f| match f {
-                Ok((name, _)) if name.ends_with(".class") => true,
-                _ => false,
-            })
+            .filter(|f| matches!(f, Ok((name, _)) if name.ends_with(".class")))
             .for_each(|z| {
                 if let Ok((name, bytes)) = z {
                     let mut parser = OtKlassParser::of(bytes, name);
                     parser.parse();
-                    repo.add_klass(&parser.klass());
+                    vm.repo().add_klass(&parser.klass());
                 }
             });
     //Not using a classpath jar, just a class
     } else {
         let bytes = file_to_bytes(Path::new(&fq_klass_name))
-            .expect(&format!("Problem reading {}", &fq_klass_name));
+            .unwrap_or_else(|_| panic!("Problem reading {}", &fq_klass_name));
         let mut parser = OtKlassParser::of(bytes, fq_klass_name.clone());
         parser.parse();
         let k = parser.klass();
-        repo.add_klass(&k);
+        vm.repo().add_klass(&k);
+        // Whatever else it needs is looked for alongside it
+        let dir = Path::new(&fq_klass_name)
+            .parent()
+            .filter(|d| !d.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+        vm.repo().add_classpath(dir);
     }
 
     // FIXME Real main() signature required, dummying for ease of testing
     let main_str: String = f_name.clone() + ".main2:([Ljava/lang/String;)I";
-    let main_klass = repo.lookup_klass(&f_name);
+    let main_klass = vm.repo().lookup_klass(&f_name);
     let main = main_klass
         .get_method_by_name_and_desc(&main_str)
-        .expect(&format!(
-            "Error: Main method not found {}",
-            main_str.clone()
-        ));
+        .unwrap_or_else(|| panic!("Error: Main method not found {}", main_str));
 
     // FIXME Parameter passing
     let mut vars = InterpLocalVars::of(5);
 
-    let ret = exec_method(&mut repo, &main, &mut vars)
-        .map(|return_value| match return_value {
-            Int(i) => i,
-            _ => panic!("Error executing {} - non-int value returned", &f_name),
-        })
-        .expect(&format!("Error executing {} - no value returned", &f_name));
+    let mut budget = options.budget();
+    let ret = if options.green_threads {
+        let mut sched = GreenScheduler::of(options.seed, options.quantum, options.stack_size());
+        sched
+            .run(&mut vm, main, &mut vars, &mut budget)
+            .map_err(|e| e.to_string())
+    } else {
+        let mut frames = FrameStack::with_max_size(options.stack_size());
+        exec_method_on(&mut vm, main, &mut vars, &mut frames, &mut budget)
+            .map_err(|e| format!("{}\n{}", e, frames))
+    };
+
+    if options.histogram {
+        println!("{}", vm.heap().histogram(vm.repo()));
+    }
+    if let Some(path) = &options.heap_dump {
+        let written = File::create(path)
+            .and_then(|f| vm.heap().dump_hprof(vm.repo(), &[], BufWriter::new(f)));
+        if let Err(e) = written {
+            eprintln!("Problem writing heap dump to {}: {}", path.display(), e);
+        }
+    }
+
+    let ret = match ret {
+        Ok(Some(Int(i))) => i,
+        Ok(Some(_)) => panic!("Error executing {} - non-int value returned", &f_name),
+        Ok(None) => panic!("Error executing {} - no value returned", &f_name),
+        Err(e) => {
+            eprintln!("Execution of {} stopped: {}", &f_name, e);
+            process::exit(2);
+        }
+    };
 
     println!("Ret: {}", ret);
 }
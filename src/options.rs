@@ -1,3 +1,10 @@
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use ocelotter::budget::Budget;
+use ocelotter_runtime::frame::DEFAULT_STACK_SIZE;
+use ocelotter_runtime::simple_heap::{Collector, DEFAULT_INITIAL_HEAP, DEFAULT_MAX_HEAP};
+use ocelotter_util::parse_mem_size;
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -7,19 +14,97 @@ pub struct Options {
     /// class search path of directories and zip/jar files
     pub classpath: Option<String>,
 
+    #[structopt(long = "Xms", parse(try_from_str = parse_mem_size))]
+    /// initial heap size, e.g. -Xms64m
+    pub initial_heap: Option<usize>,
+
+    #[structopt(long = "Xmx", parse(try_from_str = parse_mem_size))]
+    /// maximum heap size, e.g. -Xmx256m
+    pub max_heap: Option<usize>,
+
+    #[structopt(long, default_value = "mark-sweep")]
+    /// garbage collector, mark-sweep or mark-compact
+    pub gc: Collector,
+
+    #[structopt(long = "Xss", parse(try_from_str = parse_mem_size))]
+    /// maximum stack size for each thread, e.g. -Xss512k
+    pub stack_size: Option<usize>,
+
+    #[structopt(long)]
+    /// stop with an error after executing this many bytecodes
+    pub max_instructions: Option<u64>,
+
+    #[structopt(long)]
+    /// stop with an error after running for this many milliseconds
+    pub timeout_ms: Option<u64>,
+
+    #[structopt(long, parse(from_os_str))]
+    /// write an HPROF heap dump to this file when the program ends
+    pub heap_dump: Option<PathBuf>,
+
+    #[structopt(long)]
+    /// print a histogram of the heap's objects by class when the program
+    /// ends
+    pub histogram: bool,
+
+    #[structopt(long)]
+    /// run all Java threads on one OS thread, switching between them
+    /// reproducibly
+    pub green_threads: bool,
+
+    #[structopt(long, default_value = "0")]
+    /// seed for the green thread scheduler
+    pub seed: u64,
+
+    #[structopt(long, default_value = "100")]
+    /// most bytecodes a green thread runs before the scheduler may switch
+    pub quantum: u64,
+
     #[structopt()]
     /// Class name
     pub classname: Vec<String>,
 }
 
 impl Options {
+    // Accepts the java launcher's -Xss512k form as well as --Xss=512k
+    pub fn from_jvm_args() -> Options {
+        Options::from_iter(std::env::args().map(|arg| {
+            match arg.find(|c: char| c.is_ascii_digit()) {
+                Some(i) if arg.starts_with("-X") => format!("-{}={}", &arg[..i], &arg[i..]),
+                _ => arg,
+            }
+        }))
+    }
+
+    // The deadline runs from when this is called
+    pub fn budget(&self) -> Budget {
+        let deadline = self
+            .timeout_ms
+            .map(|ms| Instant::now() + Duration::from_millis(ms));
+        Budget::of(self.max_instructions, deadline)
+    }
+
+    // A small enough -Xmx on its own brings the initial size down with it
+    pub fn initial_heap(&self) -> usize {
+        self.initial_heap
+            .unwrap_or_else(|| DEFAULT_INITIAL_HEAP.min(self.max_heap()))
+    }
+
+    pub fn max_heap(&self) -> usize {
+        self.max_heap.unwrap_or(DEFAULT_MAX_HEAP)
+    }
+
+    pub fn stack_size(&self) -> usize {
+        self.stack_size.unwrap_or(DEFAULT_STACK_SIZE)
+    }
+
     pub fn fq_klass_name(&self) -> String {
         format!("{}.class", self.f_name())
     }
 
     pub fn f_name(&self) -> String {
         self.classname
-            .get(0)
+            .first()
             .expect("Classname should be specified")
             .into()
     }
@@ -0,0 +1,339 @@
+use ocelotter_runtime::frame::{Frame, FrameStack};
+use ocelotter_runtime::gc;
+use ocelotter_runtime::monitor::{self, WaitError};
+use ocelotter_runtime::otmethod::OtMethod;
+use ocelotter_runtime::thread::{self, Suspend};
+use ocelotter_runtime::vm::Vm;
+use ocelotter_runtime::*;
+
+use crate::budget::{Budget, ExecError};
+use crate::{
+    entry_frame, run_frames, throw_vm_exception, uncaught_exception, Stop, STACK_OVERFLOW_ERROR,
+};
+
+// Green threads: every Java thread runs on the OS thread that called
+// GreenScheduler::run, which switches between them after a random number
+// of bytecodes, or when one blocks on a monitor, waits or sleeps. The
+// choices come from a seeded generator and time is virtual, so the same
+// seed always gives the same interleaving.
+
+// Virtual time advances by a millisecond per this many bytecodes run, and
+// jumps forward when every thread is waiting for a timeout
+const INSTRUCTIONS_PER_MS: u64 = 10_000;
+
+const INTERRUPTED_EXCEPTION: &str = "java/lang/InterruptedException";
+
+pub struct GreenScheduler {
+    rng: SplitMix64,
+    quantum: u64,
+    stack_size: usize,
+    // Virtual time, in bytecodes
+    clock: u64,
+    threads: Vec<GreenThread>,
+    // Which thread ran each turn, by the order the threads were started in
+    trace: Vec<usize>,
+}
+
+struct GreenThread {
+    vm_id: u32,
+    // The Thread object
+    obj: usize,
+    frames: FrameStack,
+    state: State,
+    // Exception to raise when the thread next runs
+    pending: Option<&'static str>,
+    // Threads started from Java own their id, the first runs under its OS
+    // thread's
+    owned_id: Option<monitor::ThreadId>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Runnable,
+    // Waiting for another thread to let go of this monitor
+    Blocked(usize),
+    // In wait() on obj, or sleep() if obj is 0
+    Parked {
+        obj: usize,
+        count: u32,
+        deadline: Option<u64>,
+    },
+    // Out of wait(), taking the monitor back count times
+    Reentering {
+        obj: usize,
+        count: u32,
+    },
+    // run() has returned, but threads in join() are yet to be woken
+    Exiting,
+    Done,
+}
+
+impl GreenScheduler {
+    // Each turn runs between 1 and quantum bytecodes
+    pub fn of(seed: u64, quantum: u64, stack_size: usize) -> GreenScheduler {
+        assert!(quantum > 0, "Green thread quantum must be at least 1");
+        GreenScheduler {
+            rng: SplitMix64(seed),
+            quantum,
+            stack_size,
+            clock: 0,
+            threads: vec![],
+            trace: vec![],
+        }
+    }
+
+    pub fn trace(&self) -> &[usize] {
+        &self.trace
+    }
+
+    // Runs meth as the main thread until it and every thread it started
+    // have finished, and returns what meth returned
+    pub fn run(
+        &mut self,
+        vm: &mut Vm,
+        meth: &OtMethod,
+        lvt: &mut InterpLocalVars,
+        budget: &mut Budget,
+    ) -> Result<Option<JvmValue>, ExecError> {
+        let _running = gc::running(vm);
+        let vm_id = monitor::current_thread_id();
+        let obj = thread::current_thread(vm);
+
+        // FIXME Native entry methods aren't supported
+        let klass = vm.repo().lookup_klass(&meth.get_klass_name());
+        let mut frames = FrameStack::with_max_size(self.stack_size);
+        if !frames.push(entry_frame(klass, meth, lvt)) {
+            uncaught_exception(STACK_OVERFLOW_ERROR, "");
+        }
+        self.threads.push(GreenThread::of(vm_id, obj, frames));
+
+        thread::set_green(true);
+        let ret = self.schedule(vm, budget);
+        thread::set_green(false);
+        monitor::set_current_thread_id(vm_id);
+        thread::set_current_thread(vm, obj);
+        ret
+    }
+
+    fn schedule(
+        &mut self,
+        vm: &mut Vm,
+        budget: &mut Budget,
+    ) -> Result<Option<JvmValue>, ExecError> {
+        let mut main_ret = None;
+        loop {
+            for obj in thread::take_started() {
+                let started = self.start(vm, obj);
+                self.threads.push(started);
+            }
+            let progress = self.wake(vm);
+            if self.threads.iter().all(|t| t.state == State::Done) {
+                return Ok(main_ret);
+            }
+
+            let runnable: Vec<usize> = (0..self.threads.len())
+                .filter(|&i| self.threads[i].state == State::Runnable)
+                .collect();
+            if runnable.is_empty() {
+                if progress {
+                    continue;
+                }
+                match self.next_deadline() {
+                    Some(deadline) => self.clock = deadline,
+                    None => return Err(ExecError::Deadlock),
+                }
+                continue;
+            }
+
+            let idx = runnable[self.rng.below(runnable.len() as u64) as usize];
+            let turn = 1 + self.rng.below(self.quantum);
+            self.trace.push(idx);
+
+            let t = &mut self.threads[idx];
+            t.switch_to(vm);
+            if let Some(klass_name) = t.pending.take() {
+                throw_vm_exception(vm, &mut t.frames, klass_name);
+            }
+            // The running thread's frames are taken out for the turn, so
+            // the collector can see the other threads' too
+            let mut frames = std::mem::replace(&mut t.frames, FrameStack::of());
+            let mut slice = budget.take(turn);
+            let threads = &self.threads;
+            let collector = vm.clone();
+            let stop = run_frames(vm, &mut frames, &mut slice, &mut |running, wait| {
+                let mut roots = thread_refs(threads);
+                roots.extend(running.refs());
+                gc::collect(&collector, roots, wait)
+            });
+            let t = &mut self.threads[idx];
+            t.frames = frames;
+            let unused = slice.remaining_instructions();
+            budget.refund(unused);
+            self.clock += turn - unused;
+
+            match stop {
+                // The main thread's Thread object belongs to the OS thread,
+                // so stays alive
+                Ok(Stop::Returned(ret)) if idx == 0 => {
+                    main_ret = ret;
+                    t.state = State::Done;
+                }
+                Ok(Stop::Returned(_)) => t.state = State::Exiting,
+                Ok(Stop::Blocked(lock)) => t.state = State::Blocked(lock),
+                Ok(Stop::Suspended(Suspend::Yield)) => (),
+                Ok(Stop::Suspended(Suspend::Wait {
+                    obj,
+                    count,
+                    timeout,
+                })) => {
+                    let now = self.clock;
+                    let deadline =
+                        timeout.map(|d| now + d.as_millis() as u64 * INSTRUCTIONS_PER_MS);
+                    t.state = State::Parked {
+                        obj,
+                        count,
+                        deadline,
+                    };
+                }
+                // Only the turn is over
+                Err(ExecError::OutOfFuel) if budget.remaining_instructions() > 0 => (),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // A thread just started from Java, about to call run() on its object
+    fn start(&self, vm: &Vm, obj: usize) -> GreenThread {
+        let repo = vm.repo();
+        let klass = repo.klass_by_id(vm.heap().get_obj(obj).get_klassid());
+        let (run_klass, m_idx) = repo
+            .find_method(&klass, "run:()V")
+            .unwrap_or_else(|| panic!("No run() found on {}", klass.get_name()));
+        let mut vars = InterpLocalVars::of(run_klass.get_method(m_idx).get_local_var_size());
+        vars.store(0, JvmValue::ObjRef(obj));
+
+        let mut frames = FrameStack::with_max_size(self.stack_size);
+        if !frames.push(Frame::of(run_klass, m_idx, vars)) {
+            uncaught_exception(STACK_OVERFLOW_ERROR, "");
+        }
+        let id = monitor::ThreadId::allocate();
+        let vm_id = id.get();
+        GreenThread {
+            owned_id: Some(id),
+            ..GreenThread::of(vm_id, obj, frames)
+        }
+    }
+
+    // Moves threads on that no longer have to wait, in the order they were
+    // started. Returns true if any did.
+    fn wake(&mut self, vm: &Vm) -> bool {
+        let mut progress = false;
+        let now = self.clock;
+        for t in self.threads.iter_mut() {
+            let next = match t.state {
+                State::Blocked(lock) if vm.heap().get_obj(lock).monitor_owner() == 0 => {
+                    State::Runnable
+                }
+                State::Parked {
+                    obj,
+                    count,
+                    deadline,
+                } => {
+                    let outcome = match monitor::poll(vm, t.obj) {
+                        Some(outcome) => outcome,
+                        None if deadline.is_some_and(|d| d <= now) => {
+                            monitor::cancel(vm, t.obj);
+                            Ok(())
+                        }
+                        None => continue,
+                    };
+                    if outcome == Err(WaitError::Interrupted) {
+                        thread::clear_interrupt(vm, t.obj);
+                        t.pending = Some(INTERRUPTED_EXCEPTION);
+                    }
+                    if obj == 0 {
+                        State::Runnable
+                    } else {
+                        State::Reentering { obj, count }
+                    }
+                }
+                State::Reentering { obj, count }
+                    if vm.heap().try_monitor_restore(obj, t.vm_id, count) =>
+                {
+                    State::Runnable
+                }
+                State::Exiting if vm.heap().try_monitor_enter(t.obj, t.vm_id) => {
+                    t.switch_to(vm);
+                    thread::finish(vm, t.obj);
+                    monitor::monitor_exit(vm, t.obj);
+                    State::Done
+                }
+                _ => continue,
+            };
+            t.state = next;
+            progress = true;
+        }
+        progress
+    }
+
+    fn next_deadline(&self) -> Option<u64> {
+        self.threads
+            .iter()
+            .filter_map(|t| match t.state {
+                State::Parked { deadline, .. } => deadline,
+                _ => None,
+            })
+            .min()
+    }
+}
+
+// Roots for the collector: what each thread's frames refer to, and the
+// monitors threads are waiting to take
+fn thread_refs(threads: &[GreenThread]) -> Vec<usize> {
+    let mut refs = vec![];
+    for t in threads {
+        refs.extend(t.frames.refs());
+        match t.state {
+            State::Blocked(obj) | State::Parked { obj, .. } | State::Reentering { obj, .. } if obj != 0 => {
+                refs.push(obj)
+            }
+            _ => (),
+        }
+    }
+    refs
+}
+
+impl GreenThread {
+    fn of(vm_id: u32, obj: usize, frames: FrameStack) -> GreenThread {
+        GreenThread {
+            vm_id,
+            obj,
+            frames,
+            state: State::Runnable,
+            pending: None,
+            owned_id: None,
+        }
+    }
+
+    fn switch_to(&self, vm: &Vm) {
+        monitor::set_current_thread_id(self.vm_id);
+        thread::set_current_thread(vm, self.obj);
+    }
+}
+
+// SplitMix64: small, fast and good enough for picking threads
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
@@ -2,28 +2,74 @@ use std::path::Path;
 
 use super::*;
 
-use ocelotter_runtime::constant_pool::ACC_PUBLIC;
+use ocelotter_runtime::constant_pool::{ACC_PUBLIC, ACC_STATIC};
+use ocelotter_runtime::klass_repo::NativeError;
+use ocelotter_runtime::opcode;
 // this crate is presumably old and not very good.
-use assert_float_eq::{
-  assert_f32_near, assert_f64_near,
-  afe_near_error_msg, afe_is_f32_near, afe_is_f64_near,
-};
+use assert_float_eq::{assert_f32_near, assert_f64_near};
 
-use ocelotter_util::file_to_bytes;
+use ocelotter_runtime::frame::{FrameStack, DEFAULT_STACK_SIZE};
+use std::time::{Duration, Instant};
+
+use budget::{Budget, ExecError};
+use scheduler::GreenScheduler;
+use ocelotter_util::{file_to_bytes, parse_mem_size};
 
 // Helper fns
 
-fn init_repo() -> SharedKlassRepo {
-    let mut repo = SharedKlassRepo::of();
-    repo.bootstrap(exec_method);
-    repo
+fn init_vm() -> Vm {
+    let mut vm = Vm::of();
+    vm.bootstrap(exec_method);
+    vm
+}
+
+fn execute_simple_bytecode(buf: &[u8]) -> JvmValue {
+    let mut vm = init_vm();
+    execute_bytecode(&mut vm, buf, vec![])
 }
 
-fn execute_simple_bytecode(buf: &Vec<u8>) -> JvmValue {
-    let mut repo = init_repo();
+// As execute_simple_bytecode(), with an exception table
+fn execute_bytecode(vm: &mut Vm, buf: &[u8], handlers: Vec<otmethod::ExceptionHandler>) -> JvmValue {
     let mut lvt = InterpLocalVars::of(10); // FIXME
-    exec_bytecode_method(&mut repo, "DUMMY".to_string(), buf, &mut lvt)
-        .unwrap_or_else(|| JvmValue::ObjRef(0)) // object::OtObj::get_null(),
+    let mut meth = OtMethod::of(
+        "DUMMY".to_string(),
+        "main".to_string(),
+        "()V".to_string(),
+        ACC_PUBLIC | ACC_STATIC,
+        0,
+        0,
+    );
+    meth.set_code(buf.to_vec());
+    meth.set_exception_handlers(handlers);
+    // No constant pool, so no LDC or field / method refs in these tests
+    let klass = OtKlass::of(
+        "DUMMY".to_string(),
+        "java/lang/Object".to_string(),
+        0,
+        &vec![],
+        &vec![meth],
+        &vec![],
+    );
+    vm.repo().add_klass(&klass);
+    exec_method(vm, klass.get_method(0), &mut lvt)
+        .unwrap_or(JvmValue::ObjRef(0)) // object::OtObj::get_null(),
+}
+
+// Runs buf under a handler that catches everything and returns it, giving
+// the class of what was thrown, or None if nothing was
+fn caught_by(mut buf: Vec<u8>) -> Option<String> {
+    let mut vm = init_vm();
+    let end = buf.len();
+    buf.extend(vec![opcode::ACONST_NULL, opcode::ARETURN, opcode::ARETURN]);
+    let handler = otmethod::ExceptionHandler { start: 0, end, handler: end + 2, catch_type: 0 };
+    match execute_bytecode(&mut vm, &buf, vec![handler]) {
+        JvmValue::ObjRef(0) => None,
+        JvmValue::ObjRef(id) => {
+            let klass_id = vm.heap().get_obj(id).get_klassid();
+            Some(vm.repo().klass_by_id(klass_id).get_name())
+        }
+        v => panic!("Unexpected, non-reference value {} encountered", v),
+    }
 }
 
 fn simple_parse_klass(cname: String) -> OtKlass {
@@ -45,6 +91,28 @@ fn simple_parse_klass(cname: String) -> OtKlass {
     k
 }
 
+// The contents of a java/lang/String, or None for null
+fn java_string(vm: &Vm, id: usize) -> Option<String> {
+    if id == 0 {
+        return None;
+    }
+    let k = vm.repo().lookup_klass(&"java/lang/String".to_string());
+    let heap = vm.heap();
+    let field = |name: &str| {
+        let f = vm.repo().instance_fields(&k).iter().find(|f| f.get_name() == name).unwrap();
+        heap.get_field(id, f.get_offset())
+    };
+    match (field("value"), field("offset"), field("count")) {
+        (JvmValue::ObjRef(value), JvmValue::Int(offset), JvmValue::Int(count)) => match heap.get_obj(value) {
+            OtObj::VmArrChar { elements, .. } => {
+                Some(String::from_utf16(&elements[offset as usize..(offset + count) as usize]).unwrap())
+            }
+            obj => panic!("Expected a char[], got {}", obj),
+        },
+        other => panic!("Not a String: {:?}", other),
+    }
+}
+
 /////////////////////////////////////////////////////////////////////////////
 
 #[test]
@@ -268,7 +336,7 @@ fn bc_goto() {
         opcode::IADD,
         opcode::GOTO,
         0,
-        3,
+        4,
         0xff,
         opcode::IRETURN,
     ];
@@ -382,6 +450,572 @@ fn bc_frem() {
     assert_f32_near!(ret, 8.0 / 3.0);
 }
 
+/////////////////////////////////////////////////////////////////
+//
+// JVMS conformance of the arithmetic, shift, conversion and compare opcodes
+
+// Bytecode that pushes an arbitrary int, built from two 16-bit halves
+fn push_int(v: i32) -> Vec<u8> {
+    let hi = (v >> 16) as u16;
+    let lo = v as u16;
+    vec![
+        opcode::SIPUSH,
+        (hi >> 8) as u8,
+        hi as u8,
+        opcode::BIPUSH,
+        16,
+        opcode::ISHL,
+        opcode::SIPUSH,
+        (lo >> 8) as u8,
+        lo as u8,
+        // SIPUSH sign-extends, so mask back down to the low 16 bits
+        opcode::ICONST_M1,
+        opcode::BIPUSH,
+        16,
+        opcode::IUSHR,
+        opcode::IAND,
+        opcode::IOR,
+    ]
+}
+
+fn push_long(v: i64) -> Vec<u8> {
+    let mut buf = push_int((v >> 32) as i32);
+    buf.extend(vec![opcode::I2L, opcode::BIPUSH, 32, opcode::LSHL]);
+    buf.extend(push_int(v as i32));
+    // I2L sign-extends, so mask back down to the low 32 bits
+    buf.extend(vec![
+        opcode::I2L,
+        opcode::ICONST_M1,
+        opcode::I2L,
+        opcode::BIPUSH,
+        32,
+        opcode::LUSHR,
+        opcode::LAND,
+        opcode::LOR,
+    ]);
+    buf
+}
+
+// There is no way to push an arbitrary float without a constant pool, so
+// build them as n / d - which also gives us NaN, the infinities and -0.0
+fn push_float(n: i32, d: i32) -> Vec<u8> {
+    let mut buf = push_int(n);
+    buf.push(opcode::I2F);
+    buf.extend(push_int(d));
+    buf.extend(vec![opcode::I2F, opcode::FDIV]);
+    buf
+}
+
+fn push_double(n: i64, d: i64) -> Vec<u8> {
+    let mut buf = push_long(n);
+    buf.push(opcode::L2D);
+    buf.extend(push_long(d));
+    buf.extend(vec![opcode::L2D, opcode::DDIV]);
+    buf
+}
+
+fn run_int(mut buf: Vec<u8>) -> i32 {
+    buf.push(opcode::IRETURN);
+    match execute_simple_bytecode(&buf) {
+        JvmValue::Int(i) => i,
+        v => panic!("Unexpected, non-integer value {} encountered", v),
+    }
+}
+
+fn run_long(mut buf: Vec<u8>) -> i64 {
+    buf.push(opcode::LRETURN);
+    match execute_simple_bytecode(&buf) {
+        JvmValue::Long(l) => l,
+        v => panic!("Unexpected, non-long value {} encountered", v),
+    }
+}
+
+fn run_float(mut buf: Vec<u8>) -> f32 {
+    buf.push(opcode::FRETURN);
+    match execute_simple_bytecode(&buf) {
+        JvmValue::Float(f) => f,
+        v => panic!("Unexpected, non-float value {} encountered", v),
+    }
+}
+
+fn run_double(mut buf: Vec<u8>) -> f64 {
+    buf.push(opcode::DRETURN);
+    match execute_simple_bytecode(&buf) {
+        JvmValue::Double(d) => d,
+        v => panic!("Unexpected, non-double value {} encountered", v),
+    }
+}
+
+fn int_binop(a: i32, b: i32, op: u8) -> i32 {
+    let mut buf = push_int(a);
+    buf.extend(push_int(b));
+    buf.push(op);
+    run_int(buf)
+}
+
+fn long_binop(a: i64, b: i64, op: u8) -> i64 {
+    let mut buf = push_long(a);
+    buf.extend(push_long(b));
+    buf.push(op);
+    run_long(buf)
+}
+
+fn long_shift(a: i64, s: i32, op: u8) -> i64 {
+    let mut buf = push_long(a);
+    buf.extend(push_int(s));
+    buf.push(op);
+    run_long(buf)
+}
+
+// Runs setup followed by a branch; returns whether the branch was taken
+fn branch_taken(mut setup: Vec<u8>, op: u8) -> bool {
+    setup.extend(vec![
+        op,
+        0,
+        5,
+        opcode::ICONST_0,
+        opcode::IRETURN,
+        opcode::ICONST_1,
+    ]);
+    run_int(setup) == 1
+}
+
+#[test]
+fn bc_push_helpers_round_trip() {
+    for v in &[0, 1, -1, 0x7fff, 0x8000, 0xffff, -0x8000, i32::MAX, i32::MIN, 0x1234_5678] {
+        assert_eq!(*v, run_int(push_int(*v)));
+    }
+    for v in &[0, -1, i64::MAX, i64::MIN, 0x1234_5678_9abc_def0, 0xffff_ffff] {
+        assert_eq!(*v, run_long(push_long(*v)));
+    }
+}
+
+#[test]
+fn bc_bipush_sipush_sign_extend() {
+    assert_eq!(-1, run_int(vec![opcode::BIPUSH, 0xff]));
+    assert_eq!(-128, run_int(vec![opcode::BIPUSH, 0x80]));
+    assert_eq!(127, run_int(vec![opcode::BIPUSH, 0x7f]));
+    assert_eq!(-1, run_int(vec![opcode::SIPUSH, 0xff, 0xff]));
+    assert_eq!(-32768, run_int(vec![opcode::SIPUSH, 0x80, 0x00]));
+    assert_eq!(256, run_int(vec![opcode::SIPUSH, 0x01, 0x00]));
+    assert_eq!(255, run_int(vec![opcode::SIPUSH, 0x00, 0xff]));
+}
+
+#[test]
+fn bc_int_arithmetic_wraps() {
+    assert_eq!(i32::MIN, int_binop(i32::MAX, 1, opcode::IADD));
+    assert_eq!(i32::MAX, int_binop(i32::MIN, 1, opcode::ISUB));
+    assert_eq!(2, int_binop(5, 3, opcode::ISUB));
+    assert_eq!(-2, int_binop(i32::MAX, 2, opcode::IMUL));
+    assert_eq!(0, int_binop(0x10000, 0x10000, opcode::IMUL));
+
+    let mut buf = push_int(i32::MIN);
+    buf.push(opcode::INEG);
+    assert_eq!(i32::MIN, run_int(buf));
+    let mut buf = push_int(7);
+    buf.push(opcode::INEG);
+    assert_eq!(-7, run_int(buf));
+}
+
+#[test]
+fn bc_int_division_and_remainder() {
+    assert_eq!(i32::MIN, int_binop(i32::MIN, -1, opcode::IDIV));
+    assert_eq!(0, int_binop(i32::MIN, -1, opcode::IREM));
+    // Division rounds towards zero, remainder takes the sign of the dividend
+    assert_eq!(-3, int_binop(-7, 2, opcode::IDIV));
+    assert_eq!(-1, int_binop(-7, 2, opcode::IREM));
+    assert_eq!(-3, int_binop(7, -2, opcode::IDIV));
+    assert_eq!(1, int_binop(7, -2, opcode::IREM));
+}
+
+#[test]
+fn bc_idiv_by_zero() {
+    let arithmetic = Some("java/lang/ArithmeticException".to_string());
+    for op in [opcode::IDIV, opcode::IREM].iter() {
+        let mut buf = push_int(1);
+        buf.extend(push_int(0));
+        buf.push(*op);
+        assert_eq!(arithmetic, caught_by(buf));
+    }
+    let mut buf = push_int(1);
+    buf.extend(push_int(1));
+    buf.push(opcode::IDIV);
+    assert_eq!(None, caught_by(buf));
+}
+
+#[test]
+fn bc_lrem_by_zero() {
+    let arithmetic = Some("java/lang/ArithmeticException".to_string());
+    for op in [opcode::LDIV, opcode::LREM].iter() {
+        let mut buf = push_long(1);
+        buf.extend(push_long(0));
+        buf.push(*op);
+        assert_eq!(arithmetic, caught_by(buf));
+    }
+}
+
+#[test]
+fn bc_null_arrays_and_fields_throw() {
+    let npe = Some("java/lang/NullPointerException".to_string());
+    for op in [opcode::IALOAD, opcode::BALOAD, opcode::CALOAD, opcode::SALOAD].iter() {
+        assert_eq!(npe, caught_by(vec![opcode::ACONST_NULL, opcode::ICONST_0, *op]), "{}", op);
+    }
+    for op in [opcode::IASTORE, opcode::BASTORE, opcode::CASTORE, opcode::SASTORE].iter() {
+        let buf = vec![opcode::ACONST_NULL, opcode::ICONST_0, opcode::ICONST_1, *op];
+        assert_eq!(npe, caught_by(buf), "{}", op);
+    }
+    // The null check comes before the field is resolved, so needs no CP
+    assert_eq!(npe, caught_by(vec![opcode::ACONST_NULL, opcode::GETFIELD, 0, 1]));
+    let buf = vec![opcode::ACONST_NULL, opcode::ICONST_1, opcode::PUTFIELD, 0, 1];
+    assert_eq!(npe, caught_by(buf));
+}
+
+#[test]
+fn bc_disallowed_opcode_fails_verification() {
+    let verify = Some("java/lang/VerifyError".to_string());
+    assert_eq!(verify, caught_by(vec![opcode::BREAKPOINT]));
+}
+
+#[test]
+fn bc_int_shifts_mask_distance() {
+    assert_eq!(8, int_binop(1, 3, opcode::ISHL));
+    assert_eq!(2, int_binop(1, 33, opcode::ISHL));
+    assert_eq!(i32::MIN, int_binop(1, -1, opcode::ISHL));
+    assert_eq!(-4, int_binop(-8, 1, opcode::ISHR));
+    assert_eq!(-8, int_binop(-8, 32, opcode::ISHR));
+    assert_eq!(-1, int_binop(i32::MIN, 31, opcode::ISHR));
+    assert_eq!(15, int_binop(-1, 28, opcode::IUSHR));
+    assert_eq!(1, int_binop(i32::MIN, -1, opcode::IUSHR));
+    assert_eq!(-1, int_binop(-1, 64, opcode::IUSHR));
+}
+
+#[test]
+fn bc_int_bitwise() {
+    assert_eq!(0x0f00, int_binop(0x0ff0, 0xff00, opcode::IAND));
+    assert_eq!(0xfff0, int_binop(0x0ff0, 0xff00, opcode::IOR));
+    assert_eq!(0xf0f0, int_binop(0x0ff0, 0xff00, opcode::IXOR));
+    assert_eq!(!0x1234, int_binop(0x1234, -1, opcode::IXOR));
+}
+
+#[test]
+fn bc_iinc_uses_signed_increment() {
+    let buf = vec![
+        opcode::ICONST_5,
+        opcode::ISTORE_0,
+        opcode::IINC,
+        0,
+        0xfe,
+        opcode::ILOAD_0,
+    ];
+    assert_eq!(3, run_int(buf));
+
+    let buf = vec![
+        opcode::ICONST_5,
+        opcode::ISTORE_1,
+        opcode::IINC,
+        1,
+        100,
+        opcode::ILOAD_1,
+    ];
+    assert_eq!(105, run_int(buf));
+
+    let mut buf = push_int(i32::MAX);
+    buf.extend(vec![opcode::ISTORE_2, opcode::IINC, 2, 1, opcode::ILOAD_2]);
+    assert_eq!(i32::MIN, run_int(buf));
+}
+
+#[test]
+fn bc_long_arithmetic() {
+    assert_eq!(i64::MIN, long_binop(i64::MAX, 1, opcode::LADD));
+    assert_eq!(i64::MAX, long_binop(i64::MIN, 1, opcode::LSUB));
+    assert_eq!(-2, long_binop(3, 5, opcode::LSUB));
+    assert_eq!(-2, long_binop(i64::MAX, 2, opcode::LMUL));
+    assert_eq!(i64::MIN, long_binop(i64::MIN, -1, opcode::LDIV));
+    assert_eq!(0, long_binop(i64::MIN, -1, opcode::LREM));
+    assert_eq!(-3, long_binop(-7, 2, opcode::LDIV));
+    assert_eq!(-1, long_binop(-7, 2, opcode::LREM));
+    assert_eq!(0xf0, long_binop(0xff, 0xf0f0, opcode::LAND));
+    assert_eq!(0xf0ff, long_binop(0xff, 0xf0f0, opcode::LOR));
+    assert_eq!(0xf00f, long_binop(0xff, 0xf0f0, opcode::LXOR));
+
+    let mut buf = push_long(i64::MIN);
+    buf.push(opcode::LNEG);
+    assert_eq!(i64::MIN, run_long(buf));
+}
+
+#[test]
+fn bc_long_shifts_take_int_distance() {
+    assert_eq!(1 << 40, long_shift(1, 40, opcode::LSHL));
+    assert_eq!(2, long_shift(1, 65, opcode::LSHL));
+    assert_eq!(i64::MIN, long_shift(1, -1, opcode::LSHL));
+    assert_eq!(-4, long_shift(-8, 1, opcode::LSHR));
+    assert_eq!(-8, long_shift(-8, 64, opcode::LSHR));
+    assert_eq!(15, long_shift(-1, 60, opcode::LUSHR));
+    assert_eq!(1, long_shift(i64::MIN, 63, opcode::LUSHR));
+}
+
+#[test]
+fn bc_lcmp() {
+    let lcmp = |a: i64, b: i64| -> i32 {
+        let mut buf = push_long(a);
+        buf.extend(push_long(b));
+        buf.push(opcode::LCMP);
+        run_int(buf)
+    };
+    assert_eq!(-1, lcmp(1, 2));
+    assert_eq!(0, lcmp(2, 2));
+    assert_eq!(1, lcmp(2, 1));
+    assert_eq!(-1, lcmp(i64::MIN, i64::MAX));
+}
+
+#[test]
+fn bc_int_long_conversions() {
+    let mut buf = push_int(-5);
+    buf.push(opcode::I2L);
+    assert_eq!(-5, run_long(buf));
+
+    let mut buf = push_long(0x1_8000_0001);
+    buf.push(opcode::L2I);
+    assert_eq!(-0x7fff_ffff, run_int(buf));
+
+    // 2^24 + 1 is not representable as a float, rounds to nearest even
+    let mut buf = push_int(16_777_217);
+    buf.push(opcode::I2F);
+    assert_eq!(16_777_216.0, run_float(buf));
+
+    let mut buf = push_long(i64::MAX);
+    buf.push(opcode::L2D);
+    assert_eq!(9.223_372_036_854_776E18, run_double(buf));
+
+    let mut buf = push_long(i64::MIN);
+    buf.push(opcode::L2F);
+    assert_eq!(-9.223_372E18, run_float(buf));
+}
+
+#[test]
+fn bc_float_to_integral_conversions() {
+    let f2i = |n: i32, d: i32| -> i32 {
+        let mut buf = push_float(n, d);
+        buf.push(opcode::F2I);
+        run_int(buf)
+    };
+    assert_eq!(0, f2i(0, 0));
+    assert_eq!(i32::MAX, f2i(1, 0));
+    assert_eq!(i32::MIN, f2i(-1, 0));
+    assert_eq!(-2, f2i(-27, 10));
+    assert_eq!(2, f2i(27, 10));
+
+    // 1e10 is out of range for an int
+    let mut buf = push_long(10_000_000_000);
+    buf.extend(vec![opcode::L2F, opcode::F2I]);
+    assert_eq!(i32::MAX, run_int(buf));
+
+    let f2l = |n: i32, d: i32| -> i64 {
+        let mut buf = push_float(n, d);
+        buf.push(opcode::F2L);
+        run_long(buf)
+    };
+    assert_eq!(0, f2l(0, 0));
+    assert_eq!(i64::MAX, f2l(1, 0));
+    assert_eq!(i64::MIN, f2l(-1, 0));
+    assert_eq!(-2, f2l(-27, 10));
+
+    let d2i = |n: i64, d: i64| -> i32 {
+        let mut buf = push_double(n, d);
+        buf.push(opcode::D2I);
+        run_int(buf)
+    };
+    assert_eq!(0, d2i(0, 0));
+    assert_eq!(i32::MAX, d2i(1, 0));
+    assert_eq!(i32::MIN, d2i(-1, 0));
+    assert_eq!(i32::MAX, d2i(10_000_000_000, 1));
+    assert_eq!(i32::MIN, d2i(-10_000_000_000, 1));
+    assert_eq!(-3, d2i(-7, 2));
+
+    let d2l = |n: i64, d: i64| -> i64 {
+        let mut buf = push_double(n, d);
+        buf.push(opcode::D2L);
+        run_long(buf)
+    };
+    assert_eq!(0, d2l(0, 0));
+    assert_eq!(i64::MAX, d2l(1, 0));
+    assert_eq!(i64::MIN, d2l(-1, 0));
+    assert_eq!(-3, d2l(-7, 2));
+}
+
+#[test]
+fn bc_float_double_conversions() {
+    let mut buf = push_double(1, 3);
+    buf.push(opcode::D2F);
+    assert_eq!((1.0f64 / 3.0) as f32, run_float(buf));
+
+    // Too large for a float, so becomes infinity
+    let mut buf = push_double(i64::MAX, 1);
+    buf.extend(push_double(i64::MAX, 1));
+    buf.push(opcode::DMUL);
+    buf.extend(push_double(i64::MAX, 1));
+    buf.extend(vec![opcode::DMUL, opcode::D2F]);
+    assert_eq!(f32::INFINITY, run_float(buf));
+
+    let mut buf = push_float(0, 0);
+    buf.push(opcode::F2D);
+    assert!(run_double(buf).is_nan());
+
+    let mut buf = push_float(1, 3);
+    buf.push(opcode::F2D);
+    assert_eq!((1.0f32 / 3.0) as f64, run_double(buf));
+}
+
+#[test]
+fn bc_float_arithmetic() {
+    let fbin = |a: Vec<u8>, b: Vec<u8>, op: u8| -> f32 {
+        let mut buf = a;
+        buf.extend(b);
+        buf.push(op);
+        run_float(buf)
+    };
+    assert_eq!(-1.0, fbin(push_float(1, 1), push_float(2, 1), opcode::FSUB));
+    assert_eq!(3.0, fbin(push_float(1, 1), push_float(2, 1), opcode::FADD));
+    assert_eq!(0.5, fbin(push_float(1, 1), push_float(2, 1), opcode::FDIV));
+    assert_eq!(6.0, fbin(push_float(3, 1), push_float(2, 1), opcode::FMUL));
+    // Remainder is truncating and takes the sign of the dividend
+    assert_eq!(-2.0, fbin(push_float(-5, 1), push_float(3, 1), opcode::FREM));
+    assert_eq!(2.0, fbin(push_float(5, 1), push_float(-3, 1), opcode::FREM));
+    assert!(fbin(push_float(5, 1), push_float(0, 1), opcode::FREM).is_nan());
+    assert_eq!(5.0, fbin(push_float(5, 1), push_float(1, 0), opcode::FREM));
+    assert!(fbin(push_float(1, 0), push_float(-1, 0), opcode::FADD).is_nan());
+
+    let mut buf = push_float(0, 1);
+    buf.push(opcode::FNEG);
+    assert_eq!((-0.0f32).to_bits(), run_float(buf).to_bits());
+    assert_eq!((-0.0f32).to_bits(), run_float(push_float(0, -1)).to_bits());
+}
+
+#[test]
+fn bc_double_arithmetic() {
+    let dbin = |a: Vec<u8>, b: Vec<u8>, op: u8| -> f64 {
+        let mut buf = a;
+        buf.extend(b);
+        buf.push(op);
+        run_double(buf)
+    };
+    assert_eq!(-1.0, dbin(push_double(1, 1), push_double(2, 1), opcode::DSUB));
+    assert_eq!(3.0, dbin(push_double(1, 1), push_double(2, 1), opcode::DADD));
+    assert_eq!(0.5, dbin(push_double(1, 1), push_double(2, 1), opcode::DDIV));
+    assert_eq!(6.0, dbin(push_double(3, 1), push_double(2, 1), opcode::DMUL));
+    assert_eq!(-2.0, dbin(push_double(-5, 1), push_double(3, 1), opcode::DREM));
+    assert_eq!(2.0, dbin(push_double(5, 1), push_double(-3, 1), opcode::DREM));
+    assert!(dbin(push_double(5, 1), push_double(0, 1), opcode::DREM).is_nan());
+
+    let mut buf = push_double(0, 1);
+    buf.push(opcode::DNEG);
+    assert_eq!((-0.0f64).to_bits(), run_double(buf).to_bits());
+}
+
+#[test]
+fn bc_float_compares_handle_nan() {
+    let fcmp = |a: Vec<u8>, b: Vec<u8>, op: u8| -> i32 {
+        let mut buf = a;
+        buf.extend(b);
+        buf.push(op);
+        run_int(buf)
+    };
+    assert_eq!(-1, fcmp(push_float(1, 1), push_float(2, 1), opcode::FCMPL));
+    assert_eq!(1, fcmp(push_float(2, 1), push_float(1, 1), opcode::FCMPG));
+    assert_eq!(0, fcmp(push_float(0, 1), push_float(0, -1), opcode::FCMPL));
+    assert_eq!(-1, fcmp(push_float(0, 0), push_float(1, 1), opcode::FCMPL));
+    assert_eq!(1, fcmp(push_float(0, 0), push_float(1, 1), opcode::FCMPG));
+    assert_eq!(-1, fcmp(push_float(1, 1), push_float(0, 0), opcode::FCMPL));
+    assert_eq!(1, fcmp(push_float(1, 1), push_float(0, 0), opcode::FCMPG));
+
+    assert_eq!(-1, fcmp(push_double(1, 1), push_double(2, 1), opcode::DCMPL));
+    assert_eq!(1, fcmp(push_double(2, 1), push_double(1, 1), opcode::DCMPG));
+    assert_eq!(0, fcmp(push_double(0, 1), push_double(0, -1), opcode::DCMPG));
+    assert_eq!(-1, fcmp(push_double(0, 0), push_double(1, 1), opcode::DCMPL));
+    assert_eq!(1, fcmp(push_double(0, 0), push_double(1, 1), opcode::DCMPG));
+}
+
+#[test]
+fn bc_if_zero_compares() {
+    let cases: Vec<(u8, [bool; 3])> = vec![
+        // Taken for -1, 0, 1
+        (opcode::IFEQ, [false, true, false]),
+        (opcode::IFNE, [true, false, true]),
+        (opcode::IFLT, [true, false, false]),
+        (opcode::IFGE, [false, true, true]),
+        (opcode::IFGT, [false, false, true]),
+        (opcode::IFLE, [true, true, false]),
+    ];
+    for (op, expected) in cases {
+        for (v, taken) in [-1, 0, 1].iter().zip(expected.iter()) {
+            assert_eq!(*taken, branch_taken(push_int(*v), op), "op {:#x} with {}", op, v);
+        }
+    }
+}
+
+#[test]
+fn bc_if_icmp_compares() {
+    let cases: Vec<(u8, [bool; 3])> = vec![
+        // Taken for (1, 2), (2, 2), (2, 1)
+        (opcode::IF_ICMPEQ, [false, true, false]),
+        (opcode::IF_ICMPNE, [true, false, true]),
+        (opcode::IF_ICMPLT, [true, false, false]),
+        (opcode::IF_ICMPGE, [false, true, true]),
+        (opcode::IF_ICMPGT, [false, false, true]),
+        (opcode::IF_ICMPLE, [true, true, false]),
+    ];
+    for (op, expected) in cases {
+        for ((a, b), taken) in [(1, 2), (2, 2), (2, 1)].iter().zip(expected.iter()) {
+            let mut setup = push_int(*a);
+            setup.extend(push_int(*b));
+            assert_eq!(*taken, branch_taken(setup, op), "op {:#x} with {}, {}", op, a, b);
+        }
+    }
+    let mut setup = push_int(i32::MIN);
+    setup.extend(push_int(i32::MAX));
+    assert!(branch_taken(setup, opcode::IF_ICMPLT));
+}
+
+/////////////////////////////////////////////////////////////////
+//
+// boolean, byte, short and char are ints on the stack and in locals
+
+#[test]
+fn bc_narrowing_conversions_push_ints() {
+    let narrow = |v: i32, op: u8| {
+        let mut buf = push_int(v);
+        buf.push(op);
+        // Must still be usable by an int opcode afterwards
+        buf.push(opcode::ICONST_0);
+        buf.push(opcode::IADD);
+        run_int(buf)
+    };
+    assert_eq!(-56, narrow(200, opcode::I2B));
+    assert_eq!(127, narrow(0x17f, opcode::I2B));
+    assert_eq!(4464, narrow(70000, opcode::I2S));
+    assert_eq!(-1, narrow(0xffff, opcode::I2S));
+    assert_eq!(0xffff, narrow(-1, opcode::I2C));
+    // Lone surrogates are legal char values
+    assert_eq!(0xd800, narrow(0xd800, opcode::I2C));
+    assert_eq!(0xdfff, narrow(0x1dfff, opcode::I2C));
+}
+
+#[test]
+fn bc_narrow_array_stores_truncate() {
+    // new T[2]; arr[1] = v; return arr[1]
+    let store_and_load = |atype: u8, v: i32, store: u8, load: u8| {
+        let mut buf = vec![opcode::ICONST_2, opcode::NEWARRAY, atype, opcode::DUP, opcode::ICONST_1];
+        buf.extend(push_int(v));
+        buf.extend(vec![store, opcode::ICONST_1, load]);
+        run_int(buf)
+    };
+    assert_eq!(1, store_and_load(4, 3, opcode::BASTORE, opcode::BALOAD));
+    assert_eq!(0, store_and_load(4, 2, opcode::BASTORE, opcode::BALOAD));
+    assert_eq!(0xd800, store_and_load(5, -0x2800 - 0x10000, opcode::CASTORE, opcode::CALOAD));
+    assert_eq!(-56, store_and_load(8, 200, opcode::BASTORE, opcode::BALOAD));
+    assert_eq!(-1, store_and_load(9, 0xffff, opcode::SASTORE, opcode::SALOAD));
+}
+
 /////////////////////////////////////////////////////////////////
 //
 // Tests for helper methods
@@ -421,19 +1055,19 @@ fn parse_signatures() {
 
 #[test]
 fn interp_invoke_simple() {
-    let mut repo = init_repo();
+    let mut vm = init_vm();
     let k = simple_parse_klass("SampleInvoke".to_string());
-    repo.add_klass(&k);
+    vm.repo().add_klass(&k);
 
     {
         let fq_meth = "SampleInvoke.bar:()I";
         let meth = k
             .get_method_by_name_and_desc(&fq_meth.to_string())
-            .expect(&format!("{} not found", fq_meth));
+            .unwrap_or_else(|| panic!("{} not found", fq_meth));
         assert_eq!(ACC_PUBLIC | ACC_STATIC, meth.get_flags());
 
         let mut vars = InterpLocalVars::of(5);
-        let ret = exec_method(&mut repo, &meth, &mut vars).unwrap();
+        let ret = exec_method(&mut vm, meth, &mut vars).unwrap();
         let ret2 = match ret {
             JvmValue::Int(i) => i,
             _ => panic!("Error executing SampleInvoke.bar:()I - non-int value returned"),
@@ -449,7 +1083,7 @@ fn interp_invoke_simple() {
         assert_eq!(ACC_PUBLIC | ACC_STATIC, meth.get_flags());
 
         let mut vars = InterpLocalVars::of(5);
-        let ret = exec_method(&mut repo, &meth, &mut vars).unwrap();
+        let ret = exec_method(&mut vm, meth, &mut vars).unwrap();
         let ret2 = match ret {
             JvmValue::Int(i) => i,
             _ => panic!("Error executing SampleInvoke.foo:()I - non-int value returned"),
@@ -460,9 +1094,9 @@ fn interp_invoke_simple() {
 
 #[test]
 fn test_math_sin() {
-    let mut repo = init_repo();
+    let mut vm = init_vm();
     let k = simple_parse_klass("TestMathSin".to_string());
-    repo.add_klass(&k);
+    vm.repo().add_klass(&k);
 
     {
         let fq_meth = "TestMathSin.main_ifge:()I";
@@ -472,7 +1106,7 @@ fn test_math_sin() {
         assert_eq!(ACC_PUBLIC | ACC_STATIC, meth.get_flags());
 
         let mut vars = InterpLocalVars::of(5);
-        let ret = exec_method(&mut repo, &meth, &mut vars).unwrap();
+        let ret = exec_method(&mut vm, meth, &mut vars).unwrap();
         let ret2 = match ret {
             JvmValue::Int(i) => i,
             _ => panic!("Error executing {} - non-int value returned", fq_meth),
@@ -488,7 +1122,7 @@ fn test_math_sin() {
         assert_eq!(ACC_PUBLIC | ACC_STATIC, meth.get_flags());
 
         let mut vars = InterpLocalVars::of(5);
-        let ret = exec_method(&mut repo, &meth, &mut vars).unwrap();
+        let ret = exec_method(&mut vm, meth, &mut vars).unwrap();
         let ret2 = match ret {
             JvmValue::Int(i) => i,
             _ => panic!("Error executing {} - non-int value returned", fq_meth),
@@ -504,7 +1138,7 @@ fn test_math_sin() {
         assert_eq!(ACC_PUBLIC | ACC_STATIC, meth.get_flags());
 
         let mut vars = InterpLocalVars::of(5);
-        let ret = exec_method(&mut repo, &meth, &mut vars).unwrap();
+        let ret = exec_method(&mut vm, meth, &mut vars).unwrap();
         let ret2 = match ret {
             JvmValue::Int(i) => i,
             _ => panic!("Error executing {} - non-int value returned", fq_meth),
@@ -515,19 +1149,19 @@ fn test_math_sin() {
 
 #[test]
 fn interp_iffer() {
-    let mut repo = init_repo();
+    let mut vm = init_vm();
     let k = simple_parse_klass("Iffer".to_string());
-    repo.add_klass(&k);
+    vm.repo().add_klass(&k);
 
     {
         let fq_meth = "Iffer.baz:()I";
         let meth = k
             .get_method_by_name_and_desc(&fq_meth.to_string())
-            .expect(&format!("{} not found", fq_meth));
+            .unwrap_or_else(|| panic!("{} not found", fq_meth));
         assert_eq!(ACC_PUBLIC | ACC_STATIC, meth.get_flags());
 
         let mut vars = InterpLocalVars::of(5);
-        let ret = exec_method(&mut repo, &meth, &mut vars).unwrap();
+        let ret = exec_method(&mut vm, meth, &mut vars).unwrap();
         let ret2 = match ret {
             JvmValue::Int(i) => i,
             _ => panic!("Error executing Iffer.baz:()I - non-int value returned"),
@@ -536,11 +1170,42 @@ fn interp_iffer() {
     }
 }
 
+#[test]
+fn interp_loops_and_switches() {
+    let mut vm = init_vm();
+    let k = simple_parse_klass("Looper".to_string());
+    vm.repo().add_klass(&k);
+
+    let run = |vm: &mut Vm, fqname: &str, arg: i32| {
+        let meth = k
+            .get_method_by_name_and_desc(&fqname.to_string())
+            .unwrap_or_else(|| panic!("{} not found", fqname));
+        let mut vars = InterpLocalVars::of(5);
+        vars.store(0, JvmValue::Int(arg));
+        match exec_method(vm, meth, &mut vars).unwrap() {
+            JvmValue::Int(i) => i,
+            _ => panic!("Error executing {} - non-int value returned", fqname),
+        }
+    };
+
+    assert_eq!(0, run(&mut vm, "Looper.sum:(I)I", 0));
+    assert_eq!(4950, run(&mut vm, "Looper.sum:(I)I", 100));
+
+    assert_eq!(10, run(&mut vm, "Looper.table:(I)I", 1));
+    assert_eq!(30, run(&mut vm, "Looper.table:(I)I", 3));
+    assert_eq!(-1, run(&mut vm, "Looper.table:(I)I", 0));
+    assert_eq!(-1, run(&mut vm, "Looper.table:(I)I", i32::MIN));
+
+    assert_eq!(1, run(&mut vm, "Looper.lookup:(I)I", -100));
+    assert_eq!(2, run(&mut vm, "Looper.lookup:(I)I", 1000));
+    assert_eq!(3, run(&mut vm, "Looper.lookup:(I)I", 7));
+}
+
 #[test]
 fn interp_array_set() {
-    let mut repo = init_repo();
+    let mut vm = init_vm();
     let k = simple_parse_klass("ArraySimple".to_string());
-    repo.add_klass(&k);
+    vm.repo().add_klass(&k);
 
     {
         let fqname = "ArraySimple.baz:()I".to_string();
@@ -549,7 +1214,7 @@ fn interp_array_set() {
         assert_eq!(ACC_PUBLIC | ACC_STATIC, meth.get_flags());
 
         let mut vars = InterpLocalVars::of(5);
-        let ret = exec_method(&mut repo, &meth, &mut vars).unwrap();
+        let ret = exec_method(&mut vm, meth, &mut vars).unwrap();
         let ret2 = match ret {
             JvmValue::Int(i) => i,
             _ => panic!("Error executing {} - non-int value returned", fqname),
@@ -560,9 +1225,9 @@ fn interp_array_set() {
 
 #[test]
 fn interp_field_set() {
-    let mut repo = init_repo();
+    let mut vm = init_vm();
     let k = simple_parse_klass("FieldHaver".to_string());
-    repo.add_klass(&k);
+    vm.repo().add_klass(&k);
 
     {
         let fqname = "FieldHaver.main2:([Ljava/lang/String;)I".to_string();
@@ -571,7 +1236,7 @@ fn interp_field_set() {
         assert_eq!(ACC_PUBLIC | ACC_STATIC, meth.get_flags());
 
         let mut vars = InterpLocalVars::of(5);
-        let ret = match exec_method(&mut repo, &meth, &mut vars).unwrap() {
+        let ret = match exec_method(&mut vm, meth, &mut vars).unwrap() {
             JvmValue::Int(i) => i,
             _ => panic!("Error executing {} - non-int value returned", fqname),
         };
@@ -579,27 +1244,74 @@ fn interp_field_set() {
     }
 }
 
+#[test]
+fn interp_narrow_fields_and_arrays() {
+    let mut vm = init_vm();
+    let k = simple_parse_klass("NarrowHaver".to_string());
+    vm.repo().add_klass(&k);
+
+    for fqname in &["NarrowHaver.fields:()I", "NarrowHaver.arrays:()I"] {
+        let meth = k
+            .get_method_by_name_and_desc(&fqname.to_string())
+            .unwrap_or_else(|| panic!("{} not found", fqname));
+
+        let mut vars = InterpLocalVars::of(5);
+        let ret = match exec_method(&mut vm, meth, &mut vars).unwrap() {
+            JvmValue::Int(i) => i,
+            _ => panic!("Error executing {} - non-int value returned", fqname),
+        };
+        assert_eq!(-56 + 4464 + 0xd800, ret);
+    }
+
+    {
+        let fqname = "NarrowHaver.flag:()Z".to_string();
+        let meth = k.get_method_by_name_and_desc(&fqname).unwrap();
+
+        let mut vars = InterpLocalVars::of(5);
+        let ret = match exec_method(&mut vm, meth, &mut vars).unwrap() {
+            JvmValue::Int(i) => i,
+            _ => panic!("Error executing {} - non-int value returned", fqname),
+        };
+        assert_eq!(1, ret);
+    }
+}
+
+#[test]
+fn interp_native_boolean_return_is_int() {
+    let mut vm = init_vm();
+    let k = vm.repo().lookup_klass(&"java/lang/Compiler".to_string());
+    let meth = k
+        .get_method_by_name_and_desc(&"java/lang/Compiler.compileClass:(Ljava/lang/Class;)Z".to_string())
+        .unwrap();
+
+    let mut vars = InterpLocalVars::of(5);
+    match exec_method(&mut vm, meth, &mut vars).unwrap() {
+        JvmValue::Int(1) => (),
+        v => panic!("Expected Int(1) from a boolean native, got {}", v),
+    }
+}
+
 #[test]
 fn interp_system_current_timemillis() {
-    let mut repo = init_repo();
+    let mut vm = init_vm();
     let k = simple_parse_klass("Main3".to_string());
-    repo.add_klass(&k);
+    vm.repo().add_klass(&k);
 
     {
         let fqname = "Main3.main2:([Ljava/lang/String;)I";
         let meth = k
             .get_method_by_name_and_desc(&fqname.to_string())
-            .expect(&format!("{} not found", fqname));
+            .unwrap_or_else(|| panic!("{} not found", fqname));
         assert_eq!(ACC_PUBLIC | ACC_STATIC, meth.get_flags());
 
         let mut vars = InterpLocalVars::of(5);
-        let ret = exec_method(&mut repo, &meth, &mut vars).unwrap();
+        let ret = exec_method(&mut vm, meth, &mut vars).unwrap();
         let ctm1 = match ret {
             JvmValue::Int(i) => i,
             _ => panic!("Error executing {} - non-int value returned", fqname),
         };
         vars = InterpLocalVars::of(5);
-        let opt_ret = exec_method(&mut repo, &meth, &mut vars);
+        let opt_ret = exec_method(&mut vm, meth, &mut vars);
         let ret2 = match opt_ret {
             Some(value) => value,
             None => panic!("Error executing {} - no value returned", fqname),
@@ -608,16 +1320,18 @@ fn interp_system_current_timemillis() {
             JvmValue::Int(i) => i,
             _ => panic!("Error executing {} - non-int value returned", fqname),
         };
-        assert_eq!(true, ctm2 >= ctm1, "System clock appears to go backwards");
+        assert!(ctm2 >= ctm1, "System clock appears to go backwards");
     }
 }
 
 #[test]
-// #[ignore]
+// Autoboxing compiles to Integer.valueOf(int), which is not present in the
+// JDK 1.0 classes.jar that we bootstrap from
+#[ignore]
 fn interp_class_based_addition() {
-    let mut repo = init_repo();
+    let mut vm = init_vm();
     let k = simple_parse_klass("AddFieldInteger".to_string());
-    repo.add_klass(&k);
+    vm.repo().add_klass(&k);
 
     {
         let fqname = "AddFieldInteger.main2:([Ljava/lang/String;)I".to_string();
@@ -626,7 +1340,7 @@ fn interp_class_based_addition() {
         assert_eq!(ACC_PUBLIC | ACC_STATIC, meth.get_flags());
 
         let mut vars = InterpLocalVars::of(5);
-        let ret = exec_method(&mut repo, meth, &mut vars).unwrap();
+        let ret = exec_method(&mut vm, meth, &mut vars).unwrap();
         let ret2 = match ret {
             JvmValue::Int(i) => i,
             _ => panic!("Error executing {} - non-int value returned", fqname),
@@ -637,9 +1351,9 @@ fn interp_class_based_addition() {
 
 #[test]
 fn interp_ldc_based_addition() {
-    let mut repo = init_repo();
+    let mut vm = init_vm();
     let k = simple_parse_klass("AddLdc".to_string());
-    repo.add_klass(&k);
+    vm.repo().add_klass(&k);
 
     {
         let fqname = "AddLdc.main2:([Ljava/lang/String;)I".to_string();
@@ -648,7 +1362,7 @@ fn interp_ldc_based_addition() {
         assert_eq!(ACC_PUBLIC | ACC_STATIC, meth.get_flags());
 
         let mut vars = InterpLocalVars::of(5);
-        let ret = exec_method(&mut repo, meth, &mut vars).unwrap();
+        let ret = exec_method(&mut vm, meth, &mut vars).unwrap();
         let ret2 = match ret {
             JvmValue::Int(i) => i,
             _ => panic!("Error executing {} - non-int value returned", fqname),
@@ -656,3 +1370,726 @@ fn interp_ldc_based_addition() {
         assert_eq!(44451, ret2);
     }
 }
+
+#[test]
+fn interp_statics_persist_across_calls() {
+    let mut vm = init_vm();
+    let k = simple_parse_klass("Counter".to_string());
+    vm.repo().add_klass(&k);
+
+    let bump = k
+        .get_method_by_name_and_desc(&"Counter.bump:()I".to_string())
+        .unwrap();
+    let twice = k
+        .get_method_by_name_and_desc(&"Counter.bumpTwice:()I".to_string())
+        .unwrap();
+
+    let mut expected = vec![1, 2].into_iter();
+    for _ in 0..2 {
+        let mut vars = InterpLocalVars::of(5);
+        let ret = exec_method(&mut vm, bump, &mut vars).and_then(|v| v.as_int());
+        assert_eq!(expected.next(), ret);
+    }
+    let mut vars = InterpLocalVars::of(5);
+    let ret = exec_method(&mut vm, twice, &mut vars).and_then(|v| v.as_int());
+    assert_eq!(Some(4), ret);
+}
+
+#[test]
+fn interp_deep_recursion_uses_heap_frames() {
+    let mut vm = init_vm();
+    let k = simple_parse_klass("Recurser".to_string());
+    vm.repo().add_klass(&k);
+
+    let meth = k
+        .get_method_by_name_and_desc(&"Recurser.depth:(I)I".to_string())
+        .unwrap();
+    // Far deeper than the Rust stack of a test thread would allow if each
+    // Java call recursed natively
+    let mut vars = InterpLocalVars::of(1);
+    vars.store(0, JvmValue::Int(200_000));
+    let mut frames = FrameStack::with_max_size(64 * 1024 * 1024);
+    let ret = exec_method_on(&mut vm, meth, &mut vars, &mut frames, &mut Budget::unlimited())
+        .unwrap()
+        .and_then(|v| v.as_int());
+    assert_eq!(Some(200_000), ret);
+}
+
+#[test]
+fn interp_stack_overflow_is_catchable() {
+    let mut vm = init_vm();
+    let k = simple_parse_klass("Recurser".to_string());
+    vm.repo().add_klass(&k);
+
+    let meth = k
+        .get_method_by_name_and_desc(&"Recurser.caught:(I)I".to_string())
+        .unwrap();
+    for (n, expected) in &[(100, 100), (1_000_000, -1)] {
+        let mut vars = InterpLocalVars::of(1);
+        vars.store(0, JvmValue::Int(*n));
+        let ret = exec_method(&mut vm, meth, &mut vars).and_then(|v| v.as_int());
+        assert_eq!(Some(*expected), ret);
+    }
+}
+
+#[test]
+#[should_panic(expected = "Exception in thread \"main\" java/lang/StackOverflowError")]
+fn interp_uncaught_stack_overflow() {
+    let mut vm = init_vm();
+    let k = simple_parse_klass("Recurser".to_string());
+    vm.repo().add_klass(&k);
+
+    let meth = k
+        .get_method_by_name_and_desc(&"Recurser.depth:(I)I".to_string())
+        .unwrap();
+    let mut vars = InterpLocalVars::of(1);
+    vars.store(0, JvmValue::Int(100));
+    // Too small for 100 frames
+    let mut frames = FrameStack::with_max_size(4 * 1024);
+    let _ = exec_method_on(&mut vm, meth, &mut vars, &mut frames, &mut Budget::unlimited());
+}
+
+#[test]
+fn parse_stack_sizes() {
+    assert_eq!(Ok(4096), parse_mem_size("4096"));
+    assert_eq!(Ok(512 * 1024), parse_mem_size("512k"));
+    assert_eq!(Ok(2 * 1024 * 1024), parse_mem_size("2M"));
+    assert_eq!(Ok(1024 * 1024 * 1024), parse_mem_size("1g"));
+    assert!(parse_mem_size("lots").is_err());
+    assert!(parse_mem_size("k").is_err());
+}
+
+#[test]
+fn interp_instruction_budget() {
+    let mut vm = init_vm();
+    let k = simple_parse_klass("Looper".to_string());
+    vm.repo().add_klass(&k);
+    let meth = k
+        .get_method_by_name_and_desc(&"Looper.sum:(I)I".to_string())
+        .unwrap();
+
+    // Plenty for a short loop, with the unused part left over
+    let mut budget = Budget::of(Some(1000), None);
+    let mut vars = InterpLocalVars::of(2);
+    vars.store(0, JvmValue::Int(10));
+    let ret = exec_method_on(&mut vm, meth, &mut vars, &mut FrameStack::of(), &mut budget);
+    assert_eq!(Some(45), ret.unwrap().and_then(|v| v.as_int()));
+    assert!(budget.remaining_instructions() > 0 && budget.remaining_instructions() < 1000);
+
+    // A long one stops part way, with its frame intact...
+    let mut frames = FrameStack::of();
+    let mut vars = InterpLocalVars::of(2);
+    vars.store(0, JvmValue::Int(100_000));
+    let ret = exec_method_on(&mut vm, meth, &mut vars, &mut frames, &mut Budget::of(Some(1000), None));
+    assert_eq!(Err(ExecError::OutOfFuel), ret.map(|_| ()));
+    assert_eq!(1, frames.depth());
+
+    // ...so it can be picked up again with more
+    let ret = exec_frames(&mut vm, &mut frames, &mut Budget::unlimited());
+    let expected = (0..100_000).fold(0i32, |acc, i| acc.wrapping_add(i));
+    assert_eq!(Some(expected), ret.unwrap().and_then(|v| v.as_int()));
+    assert_eq!(0, frames.depth());
+}
+
+#[test]
+fn interp_deadline_stops_infinite_loop() {
+    let mut vm = init_vm();
+    let k = simple_parse_klass("Looper".to_string());
+    vm.repo().add_klass(&k);
+    let meth = k
+        .get_method_by_name_and_desc(&"Looper.spin:()V".to_string())
+        .unwrap();
+
+    let mut budget = Budget::of(None, Some(Instant::now() + Duration::from_millis(50)));
+    let mut vars = InterpLocalVars::of(0);
+    let ret = exec_method_on(&mut vm, meth, &mut vars, &mut FrameStack::of(), &mut budget);
+    assert_eq!(Err(ExecError::DeadlineExceeded), ret.map(|_| ()));
+}
+
+#[test]
+fn interp_synchronized_blocks_and_methods() {
+    let mut vm = init_vm();
+    let k = simple_parse_klass("Syncer".to_string());
+    vm.repo().add_klass(&k);
+
+    let run = |vm: &mut Vm, fqname: &str, arg: i32| {
+        let meth = k
+            .get_method_by_name_and_desc(&fqname.to_string())
+            .unwrap_or_else(|| panic!("{} not found", fqname));
+        let mut vars = InterpLocalVars::of(1);
+        vars.store(0, JvmValue::Int(arg));
+        exec_method(vm, meth, &mut vars).unwrap()
+    };
+
+    // Every monitor taken is given back on the way out
+    for fqname in &["Syncer.nested:()Ljava/lang/Object;", "Syncer.counted:()LSyncer;"] {
+        match run(&mut vm, fqname, 0) {
+            JvmValue::ObjRef(id) => assert_eq!(0, vm.heap().get_obj(id).get_mark()),
+            other => panic!("Error executing {} - non-object {} returned", fqname, other),
+        }
+    }
+    assert_eq!(Some(42), run(&mut vm, "Syncer.twice:(I)I", 21).as_int());
+    assert_eq!(Some(-1), run(&mut vm, "Syncer.nullLock:()I", 0).as_int());
+}
+
+#[test]
+#[should_panic(expected = "java/lang/IllegalMonitorStateException")]
+fn bc_monitorexit_without_enter() {
+    let buf = vec![
+        opcode::ICONST_0,
+        opcode::NEWARRAY,
+        10,
+        opcode::MONITOREXIT,
+        opcode::RETURN,
+    ];
+    execute_simple_bytecode(&buf);
+}
+
+#[test]
+fn interp_threads_start_and_join() {
+    let mut vm = init_vm();
+    let k = simple_parse_klass("Threads".to_string());
+    vm.repo().add_klass(&k);
+
+    let run = |vm: &mut Vm, fqname: &str, arg: i32| {
+        let meth = k
+            .get_method_by_name_and_desc(&fqname.to_string())
+            .unwrap_or_else(|| panic!("{} not found", fqname));
+        let mut vars = InterpLocalVars::of(1);
+        vars.store(0, JvmValue::Int(arg));
+        exec_method(vm, meth, &mut vars).unwrap().as_int()
+    };
+
+    assert_eq!(Some(20_000), run(&mut vm, "Threads.race:(I)I", 10_000));
+    assert_eq!(Some(500), run(&mut vm, "Threads.runnable:(I)I", 500));
+    // Thread.NORM_PRIORITY
+    assert_eq!(Some(5), run(&mut vm, "Threads.current:()I", 0));
+}
+
+#[test]
+fn interp_wait_and_notify() {
+    let mut vm = init_vm();
+    let k = simple_parse_klass("Waits".to_string());
+    vm.repo().add_klass(&k);
+
+    let run = |vm: &mut Vm, fqname: &str| {
+        let meth = k
+            .get_method_by_name_and_desc(&fqname.to_string())
+            .unwrap_or_else(|| panic!("{} not found", fqname));
+        exec_method(vm, meth, &mut InterpLocalVars::of(1)).unwrap().as_int()
+    };
+
+    assert_eq!(Some(1), run(&mut vm, "Waits.handoff:()I"));
+    assert_eq!(Some(2), run(&mut vm, "Waits.interruptWait:()I"));
+    assert_eq!(Some(2), run(&mut vm, "Waits.interruptSleep:()I"));
+    assert_eq!(Some(1), run(&mut vm, "Waits.timedWait:()I"));
+    assert_eq!(Some(2), run(&mut vm, "Waits.notOwner:()I"));
+}
+
+#[test]
+fn interp_gc_keeps_reachable_objects() {
+    let mut vm = init_vm();
+    let k = simple_parse_klass("Garbage".to_string());
+    vm.repo().add_klass(&k);
+    let meth = k
+        .get_method_by_name_and_desc(&"Garbage.churn:(I)I".to_string())
+        .unwrap();
+
+    let mut vars = InterpLocalVars::of(1);
+    vars.store(0, JvmValue::Int(1000));
+    assert_eq!(Some(610), exec_method(&mut vm, meth, &mut vars).unwrap().as_int());
+
+    // Green threads are collected by the scheduler
+    let mut vars = InterpLocalVars::of(1);
+    vars.store(0, JvmValue::Int(1000));
+    let ret = GreenScheduler::of(1, 100, DEFAULT_STACK_SIZE).run(&mut vm, meth, &mut vars, &mut Budget::unlimited());
+    assert_eq!(Some(610), ret.unwrap().unwrap().as_int());
+}
+
+#[test]
+fn interp_finalizers_run_once_for_unreachable_objects() {
+    let mut vm = init_vm();
+    let k = simple_parse_klass("Finals".to_string());
+    vm.repo().add_klass(&k);
+
+    // resurrect() keeps asking for a collection until its object is back,
+    // so runs within a deadline
+    let mut run = |fqname: &str, vars: &mut InterpLocalVars| {
+        let meth = k.get_method_by_name_and_desc(&fqname.to_string()).unwrap();
+        let mut budget = Budget::of(None, Some(Instant::now() + Duration::from_secs(60)));
+        let mut frames = FrameStack::of();
+        exec_method_on(&mut vm, meth, vars, &mut frames, &mut budget).unwrap().unwrap().as_int()
+    };
+    let mut vars = InterpLocalVars::of(1);
+    vars.store(0, JvmValue::Int(10));
+    assert_eq!(Some(55), run("Finals.drop:(I)I", &mut vars));
+    assert_eq!(Some(95), run("Finals.resurrect:()I", &mut InterpLocalVars::of(1)));
+}
+
+#[test]
+fn interp_run_finalization_drains_the_queue() {
+    let mut vm = init_vm();
+    let k = simple_parse_klass("Finals".to_string());
+    vm.repo().add_klass(&k);
+    // The caller holds no objects, so the VM's roots are all there are
+    vm.repo()
+        .register_native("Finals", "collectQuietly:()V", |vm, _| {
+            gc::collect(vm, vec![], false);
+            None
+        })
+        .unwrap();
+
+    let meth = k.get_method_by_name_and_desc(&"Finals.runOnce:(I)I".to_string()).unwrap();
+    let mut vars = InterpLocalVars::of(1);
+    vars.store(0, JvmValue::Int(10));
+    assert_eq!(Some(55), exec_method(&mut vm, meth, &mut vars).unwrap().as_int());
+}
+
+#[test]
+fn interp_identity_hash_codes() {
+    let mut vm = init_vm();
+    let k = simple_parse_klass("Hashes".to_string());
+    vm.repo().add_klass(&k);
+
+    let meth = k.get_method_by_name_and_desc(&"Hashes.stable:()I".to_string()).unwrap();
+    let ret = exec_method(&mut vm, meth, &mut InterpLocalVars::of(2));
+    assert_eq!(Some(0), ret.unwrap().as_int());
+
+    let meth = k.get_method_by_name_and_desc(&"Hashes.buckets:(I)I".to_string()).unwrap();
+    let mut vars = InterpLocalVars::of(4);
+    vars.store(0, JvmValue::Int(200));
+    assert_eq!(Some(16), exec_method(&mut vm, meth, &mut vars).unwrap().as_int());
+}
+
+#[test]
+fn interp_clone_copies_objects_and_arrays() {
+    let mut vm = init_vm();
+    let k = simple_parse_klass("Sheep".to_string());
+    vm.repo().add_klass(&k);
+    vm.repo().add_klass(&simple_parse_klass("Sheep$Goat".to_string()));
+    let meth = |fqname: &str| k.get_method_by_name_and_desc(&fqname.to_string()).unwrap();
+
+    // Pinned, as the calls in between may collect
+    let dolly = match exec_method(&mut vm, meth("Sheep.dolly:()Ljava/lang/Object;"), &mut InterpLocalVars::of(1)) {
+        Some(JvmValue::ObjRef(id)) => id,
+        ret => panic!("Expected a clone, got {:?}", ret),
+    };
+    vm.heap().pin(dolly);
+    let mut vars = InterpLocalVars::of(1);
+    vars.store(0, JvmValue::ObjRef(dolly));
+    assert_eq!(Some(0), exec_method(&mut vm, meth("Sheep.compare:(LSheep;)I"), &mut vars).unwrap().as_int());
+    vm.heap().unpin(dolly);
+
+    let ints = vm.heap().allocate_int_arr(3);
+    for i in 0..3 {
+        vm.heap().iastore(ints, i, i + 1);
+    }
+    vm.heap().pin(ints);
+    let mut vars = InterpLocalVars::of(1);
+    vars.store(0, JvmValue::ObjRef(ints));
+    let copy = match exec_method(&mut vm, meth("Sheep.copy:([I)Ljava/lang/Object;"), &mut vars) {
+        Some(JvmValue::ObjRef(id)) => id,
+        ret => panic!("Expected a clone, got {:?}", ret),
+    };
+    vm.heap().unpin(ints);
+    assert_ne!(ints, copy);
+    match vm.heap().get_obj(copy) {
+        OtObj::VmArrInt { elements, .. } => assert_eq!(&vec![1, 2, 3], elements),
+        obj => panic!("Expected an int[], got {}", obj),
+    }
+
+    // Goats aren't Cloneable
+    assert_eq!(Some(-1), exec_method(&mut vm, meth("Sheep.goat:()I"), &mut InterpLocalVars::of(1)).unwrap().as_int());
+}
+
+#[test]
+fn interp_system_arraycopy() {
+    let mut vm = init_vm();
+    let k = simple_parse_klass("Copies".to_string());
+    vm.repo().add_klass(&k);
+
+    let mut run = |fqname: &str| {
+        let meth = k.get_method_by_name_and_desc(&fqname.to_string()).unwrap();
+        exec_method(&mut vm, meth, &mut InterpLocalVars::of(5)).unwrap().as_int()
+    };
+    assert_eq!(Some(11234 - 23455), run("Copies.overlap:()I"));
+    assert_eq!(Some(98099), run("Copies.chars:()I"));
+    assert_eq!(Some(3), run("Copies.objects:()I"));
+    assert_eq!(Some(127), run("Copies.errors:()I"));
+}
+
+// Stores go straight into the array or field, leaving its storage where
+// it was rather than replacing it with an updated copy
+#[test]
+fn interp_fills_store_in_place() {
+    let mut vm = init_vm();
+    let k = simple_parse_klass("Fills".to_string());
+    vm.repo().add_klass(&k);
+    let object = vm.repo().lookup_klass(&"java/lang/Object".to_string());
+    let fills = vm.repo().lookup_klass(&"Fills".to_string());
+    vm.repo().instance_fields(&fills);
+
+    // Where each keeps its elements or fields, which stores mustn't move.
+    // A store made on a copy needs fresh storage while the old is still
+    // held, so even a single one shows up as a move.
+    let storage = |vm: &Vm, id: usize| match vm.heap().get_obj(id) {
+        OtObj::VmArrInt { elements, .. } => elements.as_ptr() as usize,
+        OtObj::VmArrChar { elements, .. } => elements.as_ptr() as usize,
+        OtObj::VmArrObj { elements, .. } => elements.as_ptr() as usize,
+        OtObj::VmObj { fields, .. } => fields.as_ptr() as usize,
+        _ => panic!("Unexpected object {}", id),
+    };
+    for &n in [1, 1000].iter() {
+        let ints = vm.heap().allocate_int_arr(n);
+        let chars = vm.heap().allocate_narrow_arr('C', n);
+        let objects = vm.heap().allocate_obj_arr(&object, n);
+        let fields = vm.heap().allocate_obj(&fills);
+        let cases = [
+            ("Fills.ints:([I)I", ints, n - 1),
+            ("Fills.chars:([C)I", chars, n - 1),
+            ("Fills.objects:([Ljava/lang/Object;)I", objects, n),
+            ("Fills.fields:(LFills;I)I", fields, n * (n - 1) / 2),
+        ];
+        for (fqname, id, expected) in cases.iter() {
+            let before = storage(&vm, *id);
+            let meth = k.get_method_by_name_and_desc(&fqname.to_string()).unwrap();
+            let mut vars = InterpLocalVars::of(2);
+            vars.store(0, JvmValue::ObjRef(*id));
+            vars.store(1, JvmValue::Int(n));
+            assert_eq!(Some(*expected), exec_method(&mut vm, meth, &mut vars).unwrap().as_int(), "{}", fqname);
+            assert_eq!(before, storage(&vm, *id), "{} moved what it stored into", fqname);
+        }
+    }
+}
+
+// A benchmark, run with --ignored: as stores are made in place, filling
+// four times as many takes about four times as long, not sixteen. Timings
+// are best of three, and only quadratic growth is ruled out.
+#[test]
+#[ignore]
+fn interp_fills_take_linear_time() {
+    let mut vm = init_vm();
+    let k = simple_parse_klass("Fills".to_string());
+    vm.repo().add_klass(&k);
+    let object = vm.repo().lookup_klass(&"java/lang/Object".to_string());
+    let fills = vm.repo().lookup_klass(&"Fills".to_string());
+    vm.repo().instance_fields(&fills);
+
+    let mut time = |fqname: &str, n: i32| {
+        let meth = k.get_method_by_name_and_desc(&fqname.to_string()).unwrap();
+        (0..3)
+            .map(|_| {
+                let id = match fqname {
+                    "Fills.ints:([I)I" => vm.heap().allocate_int_arr(n),
+                    "Fills.chars:([C)I" => vm.heap().allocate_narrow_arr('C', n),
+                    "Fills.objects:([Ljava/lang/Object;)I" => vm.heap().allocate_obj_arr(&object, n),
+                    _ => vm.heap().allocate_obj(&fills),
+                };
+                let mut vars = InterpLocalVars::of(2);
+                vars.store(0, JvmValue::ObjRef(id));
+                vars.store(1, JvmValue::Int(n));
+                let start = Instant::now();
+                exec_method(&mut vm, meth, &mut vars).unwrap();
+                start.elapsed()
+            })
+            .min()
+            .unwrap()
+    };
+    for fqname in [
+        "Fills.ints:([I)I",
+        "Fills.chars:([C)I",
+        "Fills.objects:([Ljava/lang/Object;)I",
+        "Fills.fields:(LFills;I)I",
+    ]
+    .iter()
+    {
+        let (small, large) = (time(fqname, 20_000), time(fqname, 80_000));
+        assert!(large < small * 8, "{} took {:?} for 20000, {:?} for 80000", fqname, small, large);
+    }
+}
+
+#[test]
+fn interp_registered_natives() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let mut vm = init_vm();
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counted = calls.clone();
+    // Registered ahead of loading, so bound as Natives is added
+    vm.repo().register_native("Natives", "add:(II)I", move |_, args| {
+        counted.fetch_add(1, Ordering::SeqCst);
+        match (args.load(0), args.load(1)) {
+            (JvmValue::Int(a), JvmValue::Int(b)) => Some(JvmValue::Int(a + b)),
+            other => panic!("Expected two ints, got {:?}", other),
+        }
+    })
+    .unwrap();
+    let k = simple_parse_klass("Natives".to_string());
+    vm.repo().add_klass(&k);
+    let k = vm.repo().lookup_klass(&"Natives".to_string());
+
+    vm.repo().register_native("Natives", "scaled:(I)I", |vm, args| {
+        let klass = vm.repo().lookup_klass(&"Natives".to_string());
+        let base = vm.repo().instance_fields(&klass).iter().find(|f| f.get_name() == "base").unwrap().get_offset();
+        match (args.load(0), args.load(1)) {
+            (JvmValue::ObjRef(id), JvmValue::Int(by)) => match vm.heap().get_field(id, base) {
+                JvmValue::Int(base) => Some(JvmValue::Int(base * by)),
+                other => panic!("Expected an int base, got {}", other),
+            },
+            other => panic!("Expected a receiver and an int, got {:?}", other),
+        }
+    })
+    .unwrap();
+    vm.repo().register_native("Natives", "squares:(I)[I", |vm, args| {
+        let n = args.load(0).as_int().unwrap();
+        let mut heap = vm.heap();
+        let squares = heap.allocate_int_arr(n);
+        for i in 0..n {
+            heap.iastore(squares, i, i * i);
+        }
+        Some(JvmValue::ObjRef(squares))
+    })
+    .unwrap();
+
+    // Turned away before anything is kept
+    let noop = |_: &Vm, _: &InterpLocalVars| None;
+    assert_eq!(
+        Err(NativeError::Duplicate("Natives.add:(II)I".to_string())),
+        vm.repo().register_native("Natives", "add:(II)I", noop)
+    );
+    assert_eq!(
+        Err(NativeError::NoSuchMethod("Natives.nope:()I".to_string())),
+        vm.repo().register_native("Natives", "nope:()I", noop)
+    );
+    assert_eq!(
+        Err(NativeError::NotNative("Natives.sum:()I".to_string())),
+        vm.repo().register_native("Natives", "sum:()I", noop)
+    );
+    assert_eq!(
+        vec!["Natives.add:(II)I", "Natives.scaled:(I)I", "Natives.squares:(I)[I"],
+        vm.repo()
+            .bound_natives()
+            .into_iter()
+            .filter(|n| n.starts_with("Natives."))
+            .collect::<Vec<String>>()
+    );
+    assert!(vm.repo().bound_natives().contains(&"java/lang/Object.hashCode:()I".to_string()));
+
+    let mut run = |fqname: &str, arg: i32| {
+        let meth = k.get_method_by_name_and_desc(&fqname.to_string()).unwrap();
+        let mut vars = InterpLocalVars::of(5);
+        vars.store(0, JvmValue::Int(arg));
+        exec_method(&mut vm, meth, &mut vars).unwrap().as_int()
+    };
+    assert_eq!(Some(45), run("Natives.sum:()I", 0));
+    assert_eq!(1, calls.load(Ordering::SeqCst));
+    assert_eq!(Some(14), run("Natives.squaresTotal:(I)I", 4));
+    // Nothing was registered for missing()
+    assert_eq!(Some(1), run("Natives.unlinked:()I", 0));
+}
+
+#[test]
+fn interp_natives_registered_ahead_are_checked_on_loading() {
+    let mut vm = init_vm();
+    // Neither matches a native method of Natives once it's loaded
+    vm.repo().register_native("Natives", "missing:()J", |_, _| Some(JvmValue::Long(1))).unwrap();
+    vm.repo().register_native("Natives", "sum:()I", |_, _| Some(JvmValue::Int(-1))).unwrap();
+    let k = simple_parse_klass("Natives".to_string());
+    vm.repo().add_klass(&k);
+
+    assert!(vm.repo().bound_natives().iter().all(|n| !n.starts_with("Natives.")));
+    let meth = k.get_method_by_name_and_desc(&"Natives.unlinked:()I".to_string()).unwrap();
+    let mut vars = InterpLocalVars::of(1);
+    assert_eq!(Some(1), exec_method(&mut vm, meth, &mut vars).unwrap().as_int());
+    // Dropped rather than kept, so the right one can still be registered
+    vm.repo().register_native("Natives", "missing:()I", |_, _| Some(JvmValue::Int(7))).unwrap();
+    let mut vars = InterpLocalVars::of(1);
+    assert_eq!(Some(0), exec_method(&mut vm, meth, &mut vars).unwrap().as_int());
+}
+
+#[test]
+fn interp_class_mirrors() {
+    let mut vm = init_vm();
+    let k = simple_parse_klass("Mirrors".to_string());
+    vm.repo().add_klass(&k);
+
+    let run = |vm: &mut Vm, fqname: &str, which: i32| {
+        let meth = k.get_method_by_name_and_desc(&fqname.to_string()).unwrap();
+        let mut vars = InterpLocalVars::of(2);
+        vars.store(0, JvmValue::Int(which));
+        exec_method(vm, meth, &mut vars).unwrap()
+    };
+    assert_eq!(Some(0), run(&mut vm, "Mirrors.same:()I", 0).as_int());
+    assert_eq!(Some(211), run(&mut vm, "Mirrors.interfaces:()I", 0).as_int());
+    assert_eq!(Some(9), run(&mut vm, "Mirrors.kinds:()I", 0).as_int());
+    assert_eq!(Some(1), run(&mut vm, "Mirrors.locked:()I", 0).as_int());
+
+    // Names are kept alive by Mirrors.last until the next call
+    let mut name = |fqname: &str, which: i32| match run(&mut vm, fqname, which) {
+        JvmValue::ObjRef(id) => java_string(&vm, id),
+        other => panic!("Expected a name, got {}", other),
+    };
+    let object = Some("java.lang.Object".to_string());
+    assert_eq!(Some("Mirrors".to_string()), name("Mirrors.name:(I)Ljava/lang/Object;", 0));
+    assert_eq!(Some("[I".to_string()), name("Mirrors.name:(I)Ljava/lang/Object;", 1));
+    assert_eq!(Some("[LMirrors;".to_string()), name("Mirrors.name:(I)Ljava/lang/Object;", 2));
+    assert_eq!(object, name("Mirrors.name:(I)Ljava/lang/Object;", 3));
+    assert_eq!(object, name("Mirrors.superName:(I)Ljava/lang/Object;", 0));
+    assert_eq!(object, name("Mirrors.superName:(I)Ljava/lang/Object;", 1));
+    assert_eq!(object, name("Mirrors.superName:(I)Ljava/lang/Object;", 2));
+    assert_eq!(None, name("Mirrors.superName:(I)Ljava/lang/Object;", 3));
+}
+
+#[test]
+fn interp_class_for_name_and_new_instance() {
+    let mut vm = init_vm();
+    let k = simple_parse_klass("Reflect".to_string());
+    vm.repo().add_klass(&k);
+    for nested in &["Shape", "Named", "Hidden", "Sized"] {
+        vm.repo().add_klass(&simple_parse_klass(format!("Reflect${}", nested)));
+    }
+    // Reflect$Base and Reflect$Lazy are left for forName() to load
+    vm.repo().add_classpath(Path::new("./resources/test"));
+
+    let mut run = |fqname: &str| {
+        let meth = k.get_method_by_name_and_desc(&fqname.to_string()).unwrap();
+        let mut vars = InterpLocalVars::of(1);
+        exec_method(&mut vm, meth, &mut vars).unwrap().as_int()
+    };
+    assert_eq!(Some(1), run("Reflect.initialise:()I"));
+    assert_eq!(Some(12), run("Reflect.loads:()I"));
+    assert_eq!(Some(111), run("Reflect.arrays:()I"));
+    assert_eq!(Some(111), run("Reflect.missing:()I"));
+    assert_eq!(Some(2), run("Reflect.instances:()I"));
+    assert_eq!(Some(12111), run("Reflect.errors:()I"));
+}
+
+#[test]
+fn vms_keep_their_own_heaps_and_statics() {
+    let mut first = init_vm();
+    let mut second = init_vm();
+    let k = simple_parse_klass("Isolated".to_string());
+    first.repo().add_klass(&k);
+    second.repo().add_klass(&k);
+
+    let run = |vm: &mut Vm, fqname: &str| {
+        let meth = k.get_method_by_name_and_desc(&fqname.to_string()).unwrap();
+        exec_method(vm, meth, &mut InterpLocalVars::of(1)).unwrap()
+    };
+    let hello = |vm: &mut Vm| match run(vm, "Isolated.hello:()Ljava/lang/Object;") {
+        JvmValue::ObjRef(id) => java_string(vm, id),
+        other => panic!("Expected a String, got {}", other),
+    };
+    assert_eq!(Some(1), run(&mut first, "Isolated.bump:()I").as_int());
+    assert_eq!(Some(2), run(&mut first, "Isolated.bump:()I").as_int());
+    assert_eq!(Some(1), run(&mut second, "Isolated.bump:()I").as_int());
+    assert_eq!(Some(1), run(&mut first, "Isolated.interned:()I").as_int());
+    assert_eq!(Some("hello".to_string()), hello(&mut first));
+
+    // The other VM carries on without it, and its klasses go with it
+    let isolated = Arc::downgrade(&first.repo().lookup_klass(&"Isolated".to_string()));
+    let object = Arc::downgrade(&first.repo().lookup_klass(&"java/lang/Object".to_string()));
+    drop(first);
+    assert!(isolated.upgrade().is_none());
+    assert!(object.upgrade().is_none());
+    assert_eq!(Some("hello".to_string()), hello(&mut second));
+    assert_eq!(Some(2), run(&mut second, "Isolated.bump:()I").as_int());
+}
+
+#[test]
+fn interp_out_of_memory_error() {
+    let mut vm = init_vm();
+    let k = simple_parse_klass("Garbage".to_string());
+    vm.repo().add_klass(&k);
+
+    let run = |vm: &mut Vm, fqname: &str, arg: i32| {
+        let meth = k
+            .get_method_by_name_and_desc(&fqname.to_string())
+            .unwrap_or_else(|| panic!("{} not found", fqname));
+        let mut vars = InterpLocalVars::of(1);
+        vars.store(0, JvmValue::Int(arg));
+        exec_method(vm, meth, &mut vars).unwrap().as_int()
+    };
+
+    // 8 GiB of ints is well over the default maximum
+    assert_eq!(Some(-1), run(&mut vm, "Garbage.hog:(I)I", i32::MAX));
+    assert_eq!(Some(10), run(&mut vm, "Garbage.hog:(I)I", 10));
+    assert_eq!(Some(1_000_000), run(&mut vm, "Garbage.memory:(I)I", 1_000_000));
+}
+
+#[test]
+fn interp_threads_collect_before_running_out_of_memory() {
+    let mut vm = init_vm();
+    let k = simple_parse_klass("Hogs".to_string());
+    vm.repo().add_klass(&k);
+
+    let run = |vm: &mut Vm, fqname: &str| {
+        let meth = k
+            .get_method_by_name_and_desc(&fqname.to_string())
+            .unwrap_or_else(|| panic!("{} not found", fqname));
+        let mut vars = InterpLocalVars::of(2);
+        vars.store(0, JvmValue::Int(100_000));
+        vars.store(1, JvmValue::Int(50));
+        exec_method(vm, meth, &mut vars).unwrap().as_int()
+    };
+
+    // Room for four arrays of 100,000 ints, and a thread each to fill it
+    let used = vm.heap().used_bytes();
+    vm.heap().set_limits(used, used + 1_700_000);
+    assert_eq!(Some(50_050), run(&mut vm, "Hogs.race:(II)I"));
+    assert_eq!(Some(50), run(&mut vm, "Hogs.joined:(II)I"));
+}
+
+#[test]
+fn green_threads_replay_with_the_same_seed() {
+    let mut vm = init_vm();
+    let k = simple_parse_klass("Races".to_string());
+    vm.repo().add_klass(&k);
+    let meth = k
+        .get_method_by_name_and_desc(&"Races.unlocked:(I)I".to_string())
+        .unwrap();
+
+    let run = |vm: &mut Vm, seed: u64| {
+        let mut sched = GreenScheduler::of(seed, 20, DEFAULT_STACK_SIZE);
+        let mut vars = InterpLocalVars::of(1);
+        vars.store(0, JvmValue::Int(1000));
+        let ret = sched.run(vm, meth, &mut vars, &mut Budget::unlimited());
+        (ret.unwrap().unwrap().as_int().unwrap(), sched.trace().to_vec())
+    };
+
+    let (total, trace) = run(&mut vm, 42);
+    // Switches in between reading and writing the counter lose updates
+    assert!(total < 2000, "No updates lost: {}", total);
+    assert_eq!((total, trace), run(&mut vm, 42));
+}
+
+#[test]
+fn green_threads_block_wait_and_join() {
+    let mut vm = init_vm();
+    let threads = simple_parse_klass("Threads".to_string());
+    vm.repo().add_klass(&threads);
+    let waits = simple_parse_klass("Waits".to_string());
+    vm.repo().add_klass(&waits);
+    let races = simple_parse_klass("Races".to_string());
+    vm.repo().add_klass(&races);
+
+    let run = |vm: &mut Vm, k: &OtKlass, fqname: &str| {
+        let meth = k
+            .get_method_by_name_and_desc(&fqname.to_string())
+            .unwrap_or_else(|| panic!("{} not found", fqname));
+        let mut vars = InterpLocalVars::of(1);
+        vars.store(0, JvmValue::Int(1000));
+        GreenScheduler::of(7, 50, DEFAULT_STACK_SIZE)
+            .run(vm, meth, &mut vars, &mut Budget::unlimited())
+            .map(|ret| ret.unwrap().as_int().unwrap())
+    };
+
+    assert_eq!(Ok(2000), run(&mut vm, &threads, "Threads.race:(I)I"));
+    assert_eq!(Ok(1000), run(&mut vm, &threads, "Threads.runnable:(I)I"));
+    assert_eq!(Ok(1), run(&mut vm, &waits, "Waits.handoff:()I"));
+    assert_eq!(Ok(2), run(&mut vm, &waits, "Waits.interruptWait:()I"));
+    assert_eq!(Ok(2), run(&mut vm, &waits, "Waits.interruptSleep:()I"));
+    assert_eq!(Ok(2), run(&mut vm, &waits, "Waits.notOwner:()I"));
+    assert_eq!(Err(ExecError::Deadlock), run(&mut vm, &races, "Races.stuck:()I"));
+}
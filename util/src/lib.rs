@@ -47,3 +47,19 @@ impl ZipFiles<File> {
         ZipFiles { i: 0, archive }
     }
 }
+
+// Parses JVM-style memory sizes as used by -Xss, -Xms and -Xmx: a number of
+// bytes with an optional k, m or g suffix
+pub fn parse_mem_size(s: &str) -> Result<usize, String> {
+    let (digits, scale) = match s.chars().last() {
+        Some('k') | Some('K') => (&s[..s.len() - 1], 1024),
+        Some('m') | Some('M') => (&s[..s.len() - 1], 1024 * 1024),
+        Some('g') | Some('G') => (&s[..s.len() - 1], 1024 * 1024 * 1024),
+        _ => (s, 1),
+    };
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(scale))
+        .ok_or_else(|| format!("Invalid memory size: {}", s))
+}